@@ -29,6 +29,9 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut console_level = LevelFilter::Warn;
     if args.len() > 1 && args[1] == "debug" {
         console_level = LevelFilter::Debug;
+    } else if args.len() > 1 && args[1] == "calibrate" {
+        // Show the progress and the result of the calibration
+        console_level = LevelFilter::Info;
     }
 
     // Prepare the resources by initializing the property struct
@@ -42,6 +45,17 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
     log::info!("Starting Roktrack..."); // Log an info message
 
+    // Calibrate the lens from the checkerboard images and exit
+    if args.len() > 1 && args[1] == "calibrate" {
+        let dir = match args.get(2) {
+            Some(dir) => dir.clone(),
+            None => {
+                module::util::path::join(&[&property.path.dir.data, define::path::CALIBRATION_DIR])
+            }
+        };
+        return module::vision::calibration::run(&property, &dir);
+    }
+
     // Start the drive thread that controls the movement of the mower
     let drive_handler = module::drive::run(property);
 
@@ -4,6 +4,8 @@ pub mod com; // Communication module: Handles communication-related functionalit
 pub mod define; // Definition module: Contains definitions and constants used throughout the project.
 pub mod device; // Device module: Manages hardware devices and interactions.
 pub mod drive; // Drive module: Handles autonomous driving thread.
+pub mod map; // Map module: Records the pylon layout of each site.
 pub mod pilot; // Pilot module: Manages autonomous driving logic and control.
+pub mod report; // Report module: Records mission statistics and writes reports.
 pub mod util; // Utility module: Provides various utility functions and helpers.
 pub mod vision; // Vision module: Handles computer vision and object detection.
@@ -294,6 +294,9 @@ pub enum ParentMsg {
     FollowPerson,
     Call,
     Chorus,
+    Patrol,
+    Route,
+    Survey,
     Unknown,
 }
 
@@ -320,6 +323,9 @@ impl ParentMsg {
             17 => ParentMsg::FollowPerson,
             18 => ParentMsg::Call,
             19 => ParentMsg::Chorus,
+            20 => ParentMsg::Patrol,
+            21 => ParentMsg::Route,
+            22 => ParentMsg::Survey,
             _ => ParentMsg::Unknown,
         }
     }
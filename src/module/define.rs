@@ -23,27 +23,27 @@ pub mod path {
     // Log Directory
     pub const LOG_DIR: &str = "log";
 
+    // Map Directory
+    pub const MAP_DIR: &str = "map";
+
+    // Report Directory
+    pub const REPORT_DIR: &str = "report";
+
     // Configuration File
     pub const CONF_FILE: &str = "conf.toml";
 
+    // Lens Calibration File
+    pub const CALIBRATION_FILE: &str = "calibration.toml";
+
+    // Checkerboard Image Directory for the Lens Calibration
+    pub const CALIBRATION_DIR: &str = "calibration";
+
     // Last Captured Image
     pub const LAST_IMAGE: &str = "vision.jpg";
 
     // Cropped Image
     pub const CROP_IMAGE: &str = "crop.jpg";
 
-    // YOLOv8 Model (320x320)
-    pub const PYLON_320_MODEL: &str = "asset/model/roktrack_yolov8_nano_fixed_320_320.onnx";
-
-    // YOLOv8 Model (640x640)
-    pub const PYLON_640_MODEL: &str = "asset/model/roktrack_yolov8_nano_fixed_640_640.onnx";
-
-    // Digit Detection Model (96x96)
-    pub const DIGIT_OCR_96_MODEL: &str = "asset/model/digit_yolov8_nano_fixed_96_96.onnx";
-
-    // Animal Detection Model (320x320)
-    pub const ANIMAL_320_MODEL: &str = "asset/model/animal_yolov8_nano_fixed_320_320.onnx";
-
-    // Animal Detection Model (640x640)
-    pub const ANIMAL_640_MODEL: &str = "asset/model/animal_yolov8_nano_fixed_640_640.onnx";
+    // Model Manifest (model paths, class lists and default thresholds)
+    pub const MODEL_MANIFEST: &str = "asset/model/manifest.toml";
 }
@@ -3,6 +3,7 @@
 //! This module includes various components for controlling hardware devices, such as motors and speakers.
 
 pub mod base;
+pub mod blade;
 pub mod motor;
 pub mod speaker;
 
@@ -12,8 +13,9 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use std::{sync::mpsc::Receiver, thread::JoinHandle, time::Duration};
 
+use crate::module::device::blade::{stagger_due, Blade, BladePolicy};
 use crate::module::device::motor::Motor;
-use crate::module::util::conf::Config;
+use crate::module::util::conf::{Config, Speed};
 
 // File path to get the temperature of the SoC of Raspberry Pi.
 const TEMPERATURE_FILE: &str = "/sys/class/thermal/thermal_zone0/temp";
@@ -41,6 +43,11 @@ impl Roktrack {
         speaker::speak(name);
     }
 
+    /// Plays audio files stored in the asset/audio/ folder one after another.
+    pub fn speak_seq(&self, names: Vec<String>) {
+        speaker::speak_seq(names);
+    }
+
     /// Runs the device management thread.
     ///
     /// # Note: THIS THREAD MUST BE FAST LOOP.
@@ -59,8 +66,10 @@ impl Roktrack {
                 {
                     let utc = chrono::Utc::now();
                     let now = utc.timestamp_millis() as u64;
+                    // Start the action waiting for the blade when it is ready.
+                    let held = local_self.clone().lock().unwrap().update_blade();
                     // When the target time is reached, the operation is paused.
-                    if now > local_self.clone().lock().unwrap().target_time {
+                    if !held && now > local_self.clone().lock().unwrap().target_time {
                         local_self.clone().lock().unwrap().pause();
                     }
                 }
@@ -75,20 +84,101 @@ impl Roktrack {
     }
 }
 
+/// A work motor with its own state.
+pub struct WorkUnit {
+    motor: Option<motor::WorkMotor>, // None if the GPIO pin is not available
+    pub pin: u8,
+    pub running: bool,
+    pub fault: bool,
+}
+
+impl WorkUnit {
+    /// Creates a new work unit. The unit is faulted if the GPIO pin is not available.
+    pub fn new(pin: u8, positive_relay: bool) -> Self {
+        let motor = match motor::WorkMotor::try_new(pin, positive_relay) {
+            Ok(motor) => Some(motor),
+            Err(e) => {
+                log::error!("Work Motor Unavailable. pin: {}, error: {}", pin, e);
+                None
+            }
+        };
+        Self {
+            fault: motor.is_none(),
+            motor,
+            pin,
+            running: false,
+        }
+    }
+
+    /// Start the work motor unless faulted.
+    fn start(&mut self) {
+        if self.fault || self.running {
+            return;
+        }
+        if let Some(motor) = self.motor.as_mut() {
+            motor.cw();
+            self.running = true;
+        }
+    }
+
+    /// Stop the work motor.
+    fn stop(&mut self) {
+        if let Some(motor) = self.motor.as_mut() {
+            motor.stop();
+        }
+        self.running = false;
+    }
+
+    /// Whether the work motor is running but its output is not on.
+    fn output_fault(&self) -> bool {
+        match self.motor.as_ref() {
+            Some(motor) => self.running && !motor.is_on(),
+            None => false,
+        }
+    }
+}
+
 /// Device set containing hardware components.
 pub struct RoktrackInner {
     pub drive_motor_right: motor::DriveMotor,
     pub drive_motor_left: motor::DriveMotor,
-    pub work_motor: motor::WorkMotor,
+    pub work_motors: Vec<WorkUnit>,
+    pub blade: Blade,
     pub bumper: base::Bumper,
     pub turn_adj: f32,    // Turn time adjustment factor
     pub target_time: u64, // Milliseconds
     pub action: Actions,
+    held: Option<(Actions, u64)>, // Action waiting for the blade and its duration
+    stagger: u64,                 // Milliseconds between the starts of the work motors
+    work_on_time: Option<u64>,    // Milliseconds, when the blade was switched on
+    pub speed: Speed,             // Speed limits
+    speed_scale: f64,             // Speed scale for driving straight
+    work_inhibit: bool,           // The work can't be enabled, e.g. persons can't be detected
 }
 
 impl RoktrackInner {
     /// Creates a new RoktrackInner instance with the given configuration.
     pub fn new(conf: Config) -> Self {
+        // Without the list, the work motors are built from the pin section
+        let work_pins = match conf.work.motors.is_empty() {
+            true => vec![
+                (conf.pin.work1_pin, conf.pin.work_ctrl_positive),
+                (conf.pin.work2_pin, conf.pin.work_ctrl_positive),
+            ],
+            false => conf
+                .work
+                .motors
+                .iter()
+                .map(|m| (m.pin, m.positive))
+                .collect(),
+        };
+        let work_motors: Vec<WorkUnit> = work_pins
+            .into_iter()
+            .map(|(pin, positive)| WorkUnit::new(pin, positive))
+            .collect();
+        // Driving forward waits until the last work motor has spun up
+        let spin_up =
+            conf.blade.spin_up + conf.work.stagger * (work_motors.len().max(1) - 1) as u64;
         Self {
             drive_motor_right: motor::DriveMotor::new(
                 conf.pin.right_pin1,
@@ -100,14 +190,167 @@ impl RoktrackInner {
                 conf.pin.left_pin2,
                 conf.pwm.pwm_power_left,
             ),
-            work_motor: motor::WorkMotor::new(conf.pin.work1_pin, conf.pin.work_ctrl_positive),
+            work_motors,
+            blade: Blade::new(
+                BladePolicy::from_string(&conf.blade.policy),
+                spin_up,
+                conf.blade.spin_down,
+            ),
             bumper: base::Bumper::new(conf.pin.bumper_pin),
             turn_adj: conf.drive.turn_adj,
             target_time: 0, // Milliseconds
             action: Actions::Stop,
+            held: None,
+            stagger: conf.work.stagger,
+            work_on_time: None,
+            speed_scale: conf.speed.max,
+            speed: conf.speed,
+            work_inhibit: false,
+        }
+    }
+
+    /// Set the speed scale for driving straight within the limits.
+    ///
+    /// It takes effect from the next forward or backward.
+    pub fn set_speed(&mut self, scale: f64) {
+        self.speed_scale = scale.max(self.speed.min).min(self.speed.max);
+    }
+
+    /// Speed scale of the current action.
+    pub fn speed_scale(&self) -> f64 {
+        match self.action {
+            Actions::Left | Actions::Right => {
+                self.speed.turn.max(self.speed.min).min(self.speed.max)
+            }
+            _ => self.speed_scale,
+        }
+    }
+
+    /// Start the work motors whose turn has come.
+    ///
+    /// A work motor whose output does not follow is marked as faulted.
+    fn start_due_work(&mut self, now: u64) {
+        if let Some(on_time) = self.work_on_time {
+            for (i, unit) in self.work_motors.iter_mut().enumerate() {
+                if stagger_due(i, on_time, self.stagger, now) {
+                    unit.start();
+                }
+            }
+        }
+        let faulted: Vec<usize> = self
+            .work_motors
+            .iter()
+            .enumerate()
+            .filter(|(_, unit)| !unit.fault && unit.output_fault())
+            .map(|(i, _)| i)
+            .collect();
+        for i in faulted {
+            self.set_work_fault(i, true);
+        }
+    }
+
+    /// Enable or disable the work.
+    ///
+    /// While the work is enabled, the work motors run according to the blade policy.
+    /// The work stays disabled while it is inhibited.
+    pub fn set_work(&mut self, on: bool) {
+        self.blade.enabled = on && !self.work_inhibit;
+        let action = match &self.held {
+            Some((action, _)) => action.clone(),
+            None => self.action.clone(),
+        };
+        self.apply_blade(&action);
+    }
+
+    /// Inhibit the work, e.g. while persons can't be detected.
+    ///
+    /// The work is disabled at once and can't be enabled until the inhibit is lifted.
+    pub fn inhibit_work(&mut self, inhibit: bool) {
+        self.work_inhibit = inhibit;
+        if inhibit {
+            self.set_work(false);
+        }
+    }
+
+    /// Whether the work is inhibited.
+    pub fn work_inhibited(&self) -> bool {
+        self.work_inhibit
+    }
+
+    /// Switch the work motors according to the blade policy.
+    fn apply_blade(&mut self, action: &Actions) {
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let on = self.blade.wants_on(action);
+        if self.blade.switch(on, now) {
+            log::debug!("Switch Blade. on: {}, action: {:?}", on, action);
+            if on {
+                self.work_on_time = Some(now);
+                self.start_due_work(now);
+            } else {
+                self.work_on_time = None;
+                self.work_motors.iter_mut().for_each(|unit| unit.stop());
+            }
         }
     }
 
+    /// Start the action, or hold it until the blade has spun up or down.
+    fn start(&mut self, action: Actions, milsec: u64) {
+        self.apply_blade(&action);
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        if !self.blade.ready(&action, now) {
+            log::debug!("Waiting for Blade. action: {:?}", action);
+            self.drive_motor_left.stop();
+            self.drive_motor_right.stop();
+            self.action = Actions::Pause;
+            self.held = Some((action, milsec));
+            return;
+        }
+        self.held = None;
+        self.action = action.clone();
+        let scale = self.speed_scale();
+        self.drive_motor_left.scale = scale;
+        self.drive_motor_right.scale = scale;
+        match action {
+            Actions::Forward => {
+                self.drive_motor_left.cw();
+                self.drive_motor_right.cw();
+            }
+            Actions::Backward => {
+                self.drive_motor_left.ccw();
+                self.drive_motor_right.ccw();
+            }
+            Actions::Left => {
+                self.drive_motor_left.ccw();
+                self.drive_motor_right.cw();
+            }
+            Actions::Right => {
+                self.drive_motor_left.cw();
+                self.drive_motor_right.ccw();
+            }
+            Actions::Stop | Actions::Pause => {
+                self.drive_motor_left.stop();
+                self.drive_motor_right.stop();
+            }
+        }
+        self.set_target_time(milsec);
+    }
+
+    /// Start the held action if the blade is ready.
+    ///
+    /// Returns `true` if an action is still waiting for the blade.
+    pub fn update_blade(&mut self) -> bool {
+        self.start_due_work(chrono::Utc::now().timestamp_millis() as u64);
+        if let Some((action, milsec)) = self.held.clone() {
+            self.start(action, milsec);
+        }
+        self.held.is_some()
+    }
+
+    /// Whether an action is waiting for the blade.
+    pub fn is_held(&self) -> bool {
+        self.held.is_some()
+    }
+
     /// Is turning
     pub fn is_turning(&self) -> bool {
         log::debug!("IsturningAction: {:?}", self.action);
@@ -149,6 +392,8 @@ pub trait Chassis {
     fn backward(&mut self, duration: u64);
     fn left(&mut self, duration: u64);
     fn right(&mut self, duration: u64);
+    fn work_faults(&self) -> Vec<bool>;
+    fn set_work_fault(&mut self, index: usize, fault: bool);
 }
 
 impl Chassis for RoktrackInner {
@@ -162,11 +407,16 @@ impl Chassis for RoktrackInner {
         };
     }
 
-    /// Stop all motors, including the work motor.
+    /// Stop all motors, including the work motors.
     fn stop(&mut self) {
         self.drive_motor_left.stop();
         self.drive_motor_right.stop();
-        self.work_motor.stop();
+        self.work_motors.iter_mut().for_each(|unit| unit.stop());
+        self.work_on_time = None;
+        self.blade.enabled = false;
+        self.blade
+            .switch(false, chrono::Utc::now().timestamp_millis() as u64);
+        self.held = None;
         self.action = Actions::Stop;
     }
 
@@ -174,39 +424,47 @@ impl Chassis for RoktrackInner {
     fn pause(&mut self) {
         self.drive_motor_left.stop();
         self.drive_motor_right.stop();
+        self.held = None;
         self.action = Actions::Pause;
+        self.apply_blade(&Actions::Pause);
     }
 
     /// Move the machine forward for the specified duration.
     fn forward(&mut self, milsec: u64) {
-        self.drive_motor_left.cw();
-        self.drive_motor_right.cw();
-        self.set_target_time(milsec);
-        self.action = Actions::Forward;
+        self.start(Actions::Forward, milsec);
     }
 
     /// Move the machine backward for the specified duration.
     fn backward(&mut self, milsec: u64) {
-        self.drive_motor_left.ccw();
-        self.drive_motor_right.ccw();
-        self.set_target_time(milsec);
-        self.action = Actions::Backward;
+        self.start(Actions::Backward, milsec);
     }
 
     /// Move the machine left for the specified duration.
     fn left(&mut self, milsec: u64) {
-        self.drive_motor_left.ccw();
-        self.drive_motor_right.cw();
-        self.set_target_time(milsec);
-        self.action = Actions::Left;
+        self.start(Actions::Left, milsec);
     }
 
     /// Move the machine right for the specified duration.
     fn right(&mut self, milsec: u64) {
-        self.drive_motor_left.cw();
-        self.drive_motor_right.ccw();
-        self.set_target_time(milsec);
-        self.action = Actions::Right;
+        self.start(Actions::Right, milsec);
+    }
+
+    /// Fault state of each work motor.
+    fn work_faults(&self) -> Vec<bool> {
+        self.work_motors.iter().map(|unit| unit.fault).collect()
+    }
+
+    /// Set the fault state of a work motor.
+    ///
+    /// A faulted work motor is stopped and will not start until the fault is cleared.
+    fn set_work_fault(&mut self, index: usize, fault: bool) {
+        if let Some(unit) = self.work_motors.get_mut(index) {
+            log::warn!("Work Motor Fault. index: {}, fault: {}", index, fault);
+            unit.fault = fault;
+            if fault {
+                unit.stop();
+            }
+        }
     }
 }
 
@@ -222,7 +480,7 @@ impl Drop for RoktrackInner {
 }
 
 /// Drive System Actions
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Actions {
     Stop,
     Pause,
@@ -237,6 +495,29 @@ mod tests {
     use super::*;
     use std::{thread, time};
 
+    #[test]
+    fn work_fault_test() {
+        let property = crate::module::util::init::resource::init();
+        let mut conf = property.conf.clone();
+        conf.blade.policy = String::from("always");
+        conf.work.stagger = 0;
+        let mut inner = RoktrackInner::new(conf);
+        inner.set_work(true);
+        assert!(inner.work_motors.iter().all(|unit| unit.running));
+        assert!(inner.work_faults().iter().all(|fault| !fault));
+        // A faulted work motor is stopped and stays stopped
+        inner.set_work_fault(1, true);
+        inner.update_blade();
+        assert_eq!(inner.work_faults(), vec![false, true]);
+        assert!(inner.work_motors[0].running);
+        assert!(!inner.work_motors[1].running);
+        // The fault is detected when the output does not follow
+        inner.work_motors[0].motor.as_mut().unwrap().stop();
+        inner.update_blade();
+        assert_eq!(inner.work_faults(), vec![true, true]);
+        inner.stop();
+    }
+
     /// Test the drive system.
     ///
     /// NOTE: This test must be run in a single thread.
@@ -327,6 +608,11 @@ mod tests {
         roktrack.inner.clone().lock().unwrap().forward(1000);
         thread::sleep(time::Duration::from_millis(2000));
         roktrack.inner.clone().lock().unwrap().stop();
+        println!("device test inhibit work");
+        roktrack.inner.clone().lock().unwrap().inhibit_work(true);
+        roktrack.inner.clone().lock().unwrap().set_work(true);
+        assert!(!roktrack.inner.clone().lock().unwrap().blade.enabled);
+        roktrack.inner.clone().lock().unwrap().inhibit_work(false);
         println!("device test done!");
     }
 
@@ -0,0 +1,161 @@
+//! Provides Blade Control Policy.
+//!
+//! Decides when the work motors run depending on the maneuver,
+//! and how long driving waits for the blade to spin up or down.
+
+use super::Actions;
+
+/// When the blade runs while the work is enabled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BladePolicy {
+    Forward, // Only while driving forward
+    Turn,    // While driving forward and turning
+    Always,  // Whenever the work is enabled
+}
+
+impl BladePolicy {
+    /// Convert a string to a blade policy.
+    pub fn from_string(s: &str) -> BladePolicy {
+        match s {
+            "forward" => BladePolicy::Forward,
+            "turn" => BladePolicy::Turn,
+            _ => BladePolicy::Always,
+        }
+    }
+}
+
+/// Blade state and timers.
+#[derive(Debug, Clone)]
+pub struct Blade {
+    pub policy: BladePolicy,
+    pub enabled: bool,         // The work is requested by the pilot
+    pub running: bool,         // The work motors are on
+    spin_up: u64,              // Milliseconds to wait before driving forward after switching on
+    spin_down: u64,            // Milliseconds to wait before reversing after switching off
+    changed_time: Option<u64>, // Milliseconds, when the work motors were switched last
+}
+
+impl Blade {
+    /// Creates a new blade policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - When the blade runs.
+    /// * `spin_up` - Milliseconds to wait before driving forward after switching on.
+    /// * `spin_down` - Milliseconds to wait before reversing after switching off.
+    ///
+    pub fn new(policy: BladePolicy, spin_up: u64, spin_down: u64) -> Self {
+        Self {
+            policy,
+            enabled: false,
+            running: false,
+            spin_up,
+            spin_down,
+            changed_time: None,
+        }
+    }
+
+    /// Whether the blade should run during the action.
+    pub fn wants_on(&self, action: &Actions) -> bool {
+        self.enabled
+            && match self.policy {
+                BladePolicy::Forward => *action == Actions::Forward,
+                BladePolicy::Turn => {
+                    *action == Actions::Forward
+                        || *action == Actions::Left
+                        || *action == Actions::Right
+                }
+                BladePolicy::Always => true,
+            }
+    }
+
+    /// Record switching the work motors.
+    ///
+    /// Returns `true` if the state has changed and the motors should be switched.
+    pub fn switch(&mut self, on: bool, now: u64) -> bool {
+        if self.running == on {
+            return false;
+        }
+        self.running = on;
+        self.changed_time = Some(now);
+        true
+    }
+
+    /// Whether driving can start for the action.
+    ///
+    /// Driving forward waits for the blade to spin up,
+    /// reversing waits for the blade to spin down.
+    pub fn ready(&self, action: &Actions, now: u64) -> bool {
+        let changed_time = match self.changed_time {
+            Some(t) => t,
+            None => return true,
+        };
+        match action {
+            Actions::Forward if self.running => changed_time + self.spin_up <= now,
+            Actions::Backward if !self.running => changed_time + self.spin_down <= now,
+            _ => true,
+        }
+    }
+}
+
+/// Whether the work motor at the index is due to start.
+///
+/// The work motors start one by one at intervals to limit the inrush current.
+///
+/// # Arguments
+///
+/// * `index` - Index of the work motor.
+/// * `on_time` - Milliseconds, when the blade was switched on.
+/// * `stagger` - Milliseconds between the starts of the work motors.
+/// * `now` - Current time in milliseconds.
+///
+pub fn stagger_due(index: usize, on_time: u64, stagger: u64, now: u64) -> bool {
+    on_time + stagger * index as u64 <= now
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blade_policy_test() {
+        let mut blade = Blade::new(BladePolicy::from_string("forward"), 1000, 2000);
+        // Not enabled
+        assert!(!blade.wants_on(&Actions::Forward));
+        blade.enabled = true;
+        assert!(blade.wants_on(&Actions::Forward));
+        assert!(!blade.wants_on(&Actions::Left));
+        assert!(!blade.wants_on(&Actions::Backward));
+        blade.policy = BladePolicy::Turn;
+        assert!(blade.wants_on(&Actions::Left));
+        assert!(!blade.wants_on(&Actions::Backward));
+        blade.policy = BladePolicy::Always;
+        assert!(blade.wants_on(&Actions::Backward));
+        assert!(blade.wants_on(&Actions::Pause));
+    }
+
+    #[test]
+    fn blade_timer_test() {
+        let mut blade = Blade::new(BladePolicy::Forward, 1000, 2000);
+        assert!(blade.ready(&Actions::Forward, 0));
+        // Spin up before driving forward
+        assert!(blade.switch(true, 10000));
+        assert!(!blade.switch(true, 10100));
+        assert!(!blade.ready(&Actions::Forward, 10500));
+        assert!(blade.ready(&Actions::Forward, 11000));
+        // Spin down before reversing
+        assert!(blade.switch(false, 20000));
+        assert!(!blade.ready(&Actions::Backward, 21000));
+        assert!(blade.ready(&Actions::Backward, 22000));
+        // Turning does not wait
+        assert!(blade.ready(&Actions::Left, 20000));
+    }
+
+    #[test]
+    fn stagger_due_test() {
+        assert!(stagger_due(0, 1000, 300, 1000));
+        assert!(!stagger_due(1, 1000, 300, 1299));
+        assert!(stagger_due(1, 1000, 300, 1300));
+        assert!(!stagger_due(2, 1000, 300, 1500));
+    }
+}
@@ -1,6 +1,9 @@
 /// Provides Motor Control functionality.
 use rppal::gpio::Gpio;
 
+// Lowest PWM duty cycle of the drive motor. Below this, there is an unusual noise.
+const MIN_DUTY: f64 = 0.4;
+
 /// Defines the basic Motor trait.
 pub trait Motor {
     /// Rotate the motor clockwise.
@@ -16,6 +19,7 @@ pub struct DriveMotor {
     pin1: rppal::gpio::OutputPin,
     pin2: rppal::gpio::OutputPin,
     pub power: f64,
+    pub scale: f64, // Speed scale applied to the power
 }
 
 impl DriveMotor {
@@ -35,8 +39,14 @@ impl DriveMotor {
             pin1: gpio1.get(pin1).unwrap().into_output(),
             pin2: gpio2.get(pin2).unwrap().into_output(),
             power,
+            scale: 1.0,
         }
     }
+
+    /// PWM duty cycle from the power and the speed scale, not below the noise floor.
+    fn duty(&self) -> f64 {
+        (self.power * self.scale).clamp(MIN_DUTY, 1.0)
+    }
 }
 
 impl Motor for DriveMotor {
@@ -45,14 +55,14 @@ impl Motor for DriveMotor {
         self.pin1.clear_pwm().unwrap();
         self.pin2.clear_pwm().unwrap();
         self.pin1.set_low();
-        self.pin2.set_pwm_frequency(100.0, self.power).unwrap();
+        self.pin2.set_pwm_frequency(100.0, self.duty()).unwrap();
     }
 
     /// Rotate the drive motor counterclockwise (CCW).
     fn ccw(&mut self) {
         self.pin1.clear_pwm().unwrap();
         self.pin2.clear_pwm().unwrap();
-        self.pin1.set_pwm_frequency(100.0, self.power).unwrap();
+        self.pin1.set_pwm_frequency(100.0, self.duty()).unwrap();
         self.pin2.set_low();
     }
 
@@ -97,6 +107,29 @@ impl WorkMotor {
             positive_relay,
         }
     }
+
+    /// Creates a new WorkMotor instance, or an error if the GPIO pin is not available.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin1` - GPIO pin number for work motor control.
+    /// * `positive_relay` - Whether the motor control uses a positive relay (true) or not (false).
+    ///
+    pub fn try_new(pin1: u8, positive_relay: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let gpio1 = Gpio::new()?;
+
+        Ok(Self {
+            pin1: gpio1.get(pin1)?.into_output(),
+            positive_relay,
+        })
+    }
+
+    /// Whether the output pin is at the level to run the motor.
+    ///
+    /// The level is read back from the pin, so an output held by a shorted or overloaded relay driver can be detected.
+    pub fn is_on(&self) -> bool {
+        self.pin1.is_set_high() == self.positive_relay
+    }
 }
 
 impl Motor for WorkMotor {
@@ -141,6 +174,17 @@ mod tests {
     use super::*;
     use std::{thread, time};
 
+    #[test]
+    fn duty_test() {
+        let mut motor = DriveMotor::new(22, 23, 0.8);
+        motor.scale = 0.75;
+        assert!((motor.duty() - 0.6).abs() < 1e-9);
+        // Patrol power with the lowest speed scale
+        motor.power = 0.6;
+        motor.scale = 0.6;
+        assert_eq!(motor.duty(), MIN_DUTY);
+    }
+
     #[test]
     fn drive_motor_test() {
         // Left motor test
@@ -51,6 +51,9 @@ pub fn play(file: &str, sync: bool) {
 /// ```
 pub fn speak(name: &str) {
     let path = Path::new("./asset/audio/ja/").join(format!("{name}.mp3"));
+    if !path.exists() {
+        log::warn!("Audio Clip Not Found. path: {:?}", path);
+    }
     thread::spawn(move || play(path.to_str().unwrap(), false));
 }
 
@@ -59,6 +62,26 @@ pub fn speak_sync(name: &str) {
     thread::spawn(move || play(path.to_str().unwrap(), true));
 }
 
+/// Play asset audio files one after another.
+///
+/// # Arguments
+///
+/// * `names` - Names of the asset audio files (without extension) in the order to play.
+///
+/// # Example
+///
+/// ```
+/// speak_seq(vec![String::from("target1"), String::from("target2")]);
+/// ```
+pub fn speak_seq(names: Vec<String>) {
+    thread::spawn(move || {
+        for name in names {
+            let path = Path::new("./asset/audio/ja/").join(format!("{name}.mp3"));
+            play(path.to_str().unwrap(), true);
+        }
+    });
+}
+
 /// Logger functions for speaking audio messages based on log levels.
 pub mod logger {
     use super::speak;
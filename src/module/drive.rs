@@ -2,7 +2,8 @@
 
 use crate::module::com::{BleBroadCast, Neighbor, ParentMsg};
 use crate::module::device::speaker;
-use crate::module::pilot::{Modes, RoktrackState};
+use crate::module::pilot::{FillStage, Modes, RoktrackState};
+use crate::module::util::common::send_line_notify_with_image;
 use crate::module::util::init::RoktrackProperty;
 use crate::module::vision::{RoktrackVision, VisionMgmtCommand};
 use std::collections::HashMap;
@@ -10,15 +11,20 @@ use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread::{self, JoinHandle};
 use std::time::Duration;
 
-use super::device::{Chassis, DeviceMgmtCommand, Roktrack};
+use super::device::{Actions, Chassis, DeviceMgmtCommand, Roktrack};
+use super::map::FieldMap;
 use super::pilot::base::{post_process, pre_process};
 use super::pilot::fill::Fill;
 use super::pilot::follow_person::FollowPerson;
 use super::pilot::monitor_animal::MonitorAnimal;
 use super::pilot::monitor_person::MonitorPerson;
 use super::pilot::oneway::OneWay;
+use super::pilot::patrol::Patrol;
 use super::pilot::round_trip::RoundTrip;
+use super::pilot::route::Route;
+use super::pilot::survey::Survey;
 use super::pilot::PilotHandler;
+use super::report::MissionLog;
 use super::util::conf::Config;
 use super::vision::VisualInfo;
 
@@ -61,6 +67,12 @@ pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
 
     // Initialize the state.
     let mut state = RoktrackState::new(property.conf.clone());
+    // Continue the pylon layout of the site from the earlier runs
+    state.map = FieldMap::load_or_new(&property.path.dir.map, &property.conf.map.site);
+    // The mode is on from the start
+    if state.state {
+        state.mission.start(state.odometry.distance);
+    }
     // Initialize drive handler.
     let mut handler: Box<dyn PilotHandler> = mode_to_handler(
         Modes::from_string(property.conf.drive.mode.as_str()),
@@ -73,6 +85,19 @@ pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
         // Sleep to control the loop rate.
         thread::sleep(Duration::from_millis(10));
 
+        // Dead reckoning
+        {
+            let now = chrono::Utc::now().timestamp_millis() as u64;
+            let inner = device.inner.lock().unwrap();
+            state.odometry.scale = inner.speed_scale() as f32;
+            state.odometry.update(&inner.action, inner.target_time, now);
+            // Coverage
+            if inner.action == Actions::Forward {
+                let pose = state.odometry.pose;
+                state.mission.coverage.mark(&pose);
+            }
+        }
+
         // Get new neighbor information.
         if let Ok(neighbor) = channel_neighbor_rx.try_recv() {
             log::debug!("New Neighbor Info Received: {:?}", neighbor.clone());
@@ -89,6 +114,9 @@ pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
                 log::debug!("Replace Handle");
                 // If there are new instructions, replace the handler.
                 handler = n;
+                handler.reset(&mut state);
+                // The power limits of the previous mode don't apply
+                restore_power(&mut device, &property.conf);
             }
         }
 
@@ -103,6 +131,9 @@ pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
             // Binding for detections
             let mut visual_info = visual_info;
 
+            // Persons can't be detected by the color detector, so the blade must not run.
+            inhibit_work(&mut device, visual_info.fallback, &property);
+
             // Pre-processing for handling
             let _ = pre_process(&mut state, &mut device);
 
@@ -116,7 +147,7 @@ pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
             );
 
             // Post-processing for handling
-            let _ = post_process(&mut state, &mut device);
+            let _ = post_process(&mut state, &mut device, property.clone());
 
             // Broadcast my state to neighbors.
             let payload = state.dump(&neighbors.clone(), property.conf.clone(), &device);
@@ -129,6 +160,34 @@ pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
     })
 }
 
+/// Inhibit the work while the color detector stands in for the pylon model.
+///
+/// Notifies the user when the inhibit changes.
+fn inhibit_work(device: &mut Roktrack, fallback: bool, property: &RoktrackProperty) {
+    let mut inner = device.inner.lock().unwrap();
+    if inner.work_inhibited() == fallback {
+        return;
+    }
+    inner.inhibit_work(fallback);
+    if fallback {
+        log::error!("Color Detector in Use. Blade Inhibited.");
+        let _ = send_line_notify_with_image(
+            "Pylon model not loaded. Blade off.",
+            &property.path.img.last,
+            property.conf.clone(),
+        );
+    } else {
+        log::info!("Pylon Model in Use. Blade Allowed.");
+    }
+}
+
+/// Restore the drive motor power of the config, e.g. after the slow modes.
+fn restore_power(device: &mut Roktrack, conf: &Config) {
+    let mut inner = device.inner.lock().unwrap();
+    inner.drive_motor_left.power = conf.pwm.pwm_power_left;
+    inner.drive_motor_right.power = conf.pwm.pwm_power_right;
+}
+
 /// Handle commands received from neighbors.
 fn command_to_handler(
     state: &mut RoktrackState,
@@ -154,6 +213,7 @@ fn command_to_handler(
                 if !state.state {
                     device.speak("receive_on");
                     state.state = true;
+                    state.mission.start(state.odometry.distance);
                     tx.send(VisionMgmtCommand::On).unwrap();
                 }
                 None
@@ -162,9 +222,14 @@ fn command_to_handler(
             ParentMsg::Reset => {
                 if !state.state {
                     state.reset();
+                    state.fill_stage = FillStage::first(&conf);
+                    state.mission = MissionLog::new(conf.report.cell_size);
                     device.speak("receive_reset");
+                    // Start the mode over with a new handler
+                    mode_to_handler(state.mode, tx, conf)
+                } else {
+                    None
                 }
-                None
             }
             // Switch mode
             ParentMsg::Fill => {
@@ -230,6 +295,33 @@ fn command_to_handler(
                     None
                 }
             }
+            ParentMsg::Patrol => {
+                if !state.state && state.mode != Modes::Patrol {
+                    device.speak("receive_patrolmode");
+                    state.mode = Modes::Patrol;
+                    mode_to_handler(state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Route => {
+                if !state.state && state.mode != Modes::Route {
+                    device.speak("receive_routemode");
+                    state.mode = Modes::Route;
+                    mode_to_handler(state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Survey => {
+                if !state.state && state.mode != Modes::Survey {
+                    device.speak("receive_surveymode");
+                    state.mode = Modes::Survey;
+                    mode_to_handler(state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
             // Miscellaneous
             ParentMsg::Call => {
                 if !state.state && state.mode != Modes::Unknown {
@@ -336,7 +428,11 @@ fn mode_to_handler(
             Some(Box::new(MonitorAnimal::new()))
         }
         Modes::RoundTrip => {
-            tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap();
+            // Endpoints with an id need OCR
+            match conf.round_trip.start.id.is_some() || conf.round_trip.end.id.is_some() {
+                true => tx.send(VisionMgmtCommand::SwitchSessionPylonOcr).unwrap(),
+                false => tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap(),
+            }
             tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
             Some(Box::new(RoundTrip::new()))
         }
@@ -345,6 +441,28 @@ fn mode_to_handler(
             tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
             Some(Box::new(FollowPerson::new()))
         }
+        Modes::Patrol => {
+            match conf.vision.ocr {
+                true => tx.send(VisionMgmtCommand::SwitchSessionPylonOcr).unwrap(),
+                false => tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap(),
+            }
+            tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
+            Some(Box::new(Patrol::new()))
+        }
+        Modes::Route => {
+            // Waypoints are identified by OCR ids
+            tx.send(VisionMgmtCommand::SwitchSessionPylonOcr).unwrap();
+            tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
+            Some(Box::new(Route::new()))
+        }
+        Modes::Survey => {
+            match conf.vision.ocr {
+                true => tx.send(VisionMgmtCommand::SwitchSessionPylonOcr).unwrap(),
+                false => tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap(),
+            }
+            tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
+            Some(Box::new(Survey::new()))
+        }
         _ => None,
     }
 }
@@ -0,0 +1,205 @@
+//! Field Map Module
+//!
+//! This module records where the pylons of each site are, using the OCR ids and dead reckoning.
+
+pub mod lap; // Lap module: Counts the laps from the sequence of OCR ids.
+pub mod odometry; // Odometry module: Estimates the pose of the mower.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use self::odometry::Pose;
+
+// Records closer than this are regarded as the same pylon (metres).
+const MERGE_RADIUS: f32 = 1.0;
+
+/// A pylon on the field.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PylonRecord {
+    pub id: Option<u8>, // OCR id, if read
+    pub order: u32,     // Order of the first approach
+    pub x: f32,         // Estimated position in metres
+    pub y: f32,         // Estimated position in metres
+    pub visits: u32,    // Number of approaches
+}
+
+/// Pylon layout of a site.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct FieldMap {
+    pub site: String,
+    pub pylons: Vec<PylonRecord>,
+    #[serde(skip)]
+    dirty: bool, // Changed since the last save
+    #[serde(skip)]
+    known: usize, // Pylons recorded in the earlier runs
+}
+
+impl FieldMap {
+    /// Creates an empty map for the site.
+    pub fn new(site: &str) -> Self {
+        Self {
+            site: site.to_string(),
+            pylons: vec![],
+            dirty: false,
+            known: 0,
+        }
+    }
+
+    /// Number of pylons recorded in the earlier runs.
+    pub fn known(&self) -> usize {
+        self.known
+    }
+
+    /// Record an approach to a pylon.
+    ///
+    /// The pylon is assumed to be `distance` metres ahead of the mower.
+    /// If a pylon with the same id has already been recorded nearby, its position is averaged.
+    /// Returns the record of the pylon.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The OCR id of the pylon, if read.
+    /// * `pose` - The pose of the mower at the approach.
+    /// * `distance` - The distance from the mower to the pylon in metres.
+    ///
+    pub fn record(&mut self, id: Option<u8>, pose: &Pose, distance: f32) -> PylonRecord {
+        let x = pose.x + distance * pose.theta.cos();
+        let y = pose.y + distance * pose.theta.sin();
+        self.dirty = true;
+        if let Some(pylon) = self
+            .pylons
+            .iter_mut()
+            .find(|p| p.id == id && ((p.x - x).powi(2) + (p.y - y).powi(2)).sqrt() < MERGE_RADIUS)
+        {
+            let n = pylon.visits as f32;
+            pylon.x = (pylon.x * n + x) / (n + 1.0);
+            pylon.y = (pylon.y * n + y) / (n + 1.0);
+            pylon.visits += 1;
+            log::debug!("Pylon Revisited: {:?}", pylon);
+            return pylon.clone();
+        }
+        let pylon = PylonRecord {
+            id,
+            order: self.pylons.len() as u32,
+            x,
+            y,
+            visits: 1,
+        };
+        log::info!("New Pylon Recorded: {:?}", pylon);
+        self.pylons.push(pylon.clone());
+        pylon
+    }
+
+    /// Area enclosed by the pylons in the approach order (square metres).
+    pub fn area(&self) -> f32 {
+        let mut pylons = self.pylons.clone();
+        pylons.sort_by_key(|p| p.order);
+        let n = pylons.len();
+        if n < 3 {
+            return 0.0;
+        }
+        // Shoelace formula
+        let twice: f32 = (0..n)
+            .map(|i| {
+                let (a, b) = (&pylons[i], &pylons[(i + 1) % n]);
+                a.x * b.y - b.x * a.y
+            })
+            .sum();
+        twice.abs() / 2.0
+    }
+
+    /// Path of the map file of the site.
+    pub fn path(dir: &str, site: &str) -> PathBuf {
+        Path::new(dir).join(format!("{}.json", site))
+    }
+
+    /// Loads the map of the site from the map directory.
+    pub fn load(dir: &str, site: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let json = fs::read_to_string(Self::path(dir, site))?;
+        let mut map: Self = serde_json::from_str(&json)?;
+        map.known = map.pylons.len();
+        Ok(map)
+    }
+
+    /// Loads the map of the site recorded in the earlier runs, or creates an empty one.
+    pub fn load_or_new(dir: &str, site: &str) -> Self {
+        match Self::load(dir, site) {
+            Ok(map) => {
+                log::info!("Map Loaded. site: {}, pylons: {}", site, map.pylons.len());
+                map
+            }
+            Err(e) => {
+                log::info!("New Map. site: {}, {}", site, e);
+                Self::new(site)
+            }
+        }
+    }
+
+    /// Saves the map to the map directory if it has changed.
+    pub fn save(&mut self, dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let json = serde_json::to_string_pretty(&self)?;
+        fs::write(Self::path(dir, &self.site), json)?;
+        self.dirty = false;
+        log::debug!("Map Saved. site: {}", self.site);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_test() {
+        let mut map = FieldMap::new("test");
+        let mut pose = Pose::default();
+        map.record(Some(1), &pose, 0.5);
+        // Same pylon approached again with a small error
+        pose.x = 0.2;
+        map.record(Some(1), &pose, 0.5);
+        assert_eq!(map.pylons.len(), 1);
+        assert_eq!(map.pylons[0].visits, 2);
+        assert!((map.pylons[0].x - 0.6).abs() < 1e-5);
+        // Another pylon
+        pose.theta = std::f32::consts::FRAC_PI_2;
+        map.record(Some(1), &pose, 3.0);
+        assert_eq!(map.pylons.len(), 2);
+        assert_eq!(map.pylons[1].order, 1);
+    }
+
+    #[test]
+    fn area_test() {
+        let mut map = FieldMap::new("test");
+        // Pylons at the corners of 4m x 3m rectangle
+        for (x, y) in [(0.0, 0.0), (4.0, 0.0), (4.0, 3.0), (0.0, 3.0)] {
+            let pose = Pose { x, y, theta: 0.0 };
+            map.record(None, &pose, 0.0);
+        }
+        assert!((map.area() - 12.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn save_load_test() {
+        let dir = "/tmp/roktracktest/";
+        fs::create_dir_all(dir).unwrap();
+        let mut map = FieldMap::new("test_map");
+        map.record(Some(3), &Pose::default(), 1.0);
+        map.save(dir).unwrap();
+        let loaded = FieldMap::load(dir, "test_map").unwrap();
+        assert_eq!(loaded.site, "test_map");
+        assert_eq!(loaded.pylons, map.pylons);
+        // The next run continues the layout
+        let mut next = FieldMap::load_or_new(dir, "test_map");
+        next.record(Some(3), &Pose::default(), 1.0);
+        assert_eq!(next.pylons.len(), 1);
+        assert_eq!(next.known(), 1);
+        assert_eq!(next.pylons[0].visits, 2);
+        // No layout for a new site
+        let new = FieldMap::load_or_new(dir, "test_map_none");
+        assert!(new.pylons.is_empty());
+    }
+}
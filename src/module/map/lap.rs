@@ -0,0 +1,137 @@
+//! Lap Counter
+//!
+//! Learns the sequence of OCR ids in the first lap, and counts the laps by recognizing when the sequence repeats.
+
+/// Counts the laps from the OCR ids of the reached pylons.
+#[derive(Debug, Clone, Default)]
+pub struct LapCounter {
+    pub sequence: Vec<u8>, // OCR ids in the order of the first lap
+    pub learned: bool,     // The sequence has repeated once
+    pub laps: u32,         // Completed laps
+    last: Option<usize>,   // Index of the last reached pylon in the sequence
+    min_pylons: usize,     // Minimum number of pylons in a lap
+}
+
+impl LapCounter {
+    /// Creates a new LapCounter.
+    ///
+    /// # Arguments
+    ///
+    /// * `min_pylons` - Minimum number of pylons in a lap. A repeat in a shorter sequence is regarded as a misread.
+    ///
+    pub fn new(min_pylons: usize) -> Self {
+        Self {
+            min_pylons,
+            ..Default::default()
+        }
+    }
+
+    /// Number of pylons in a lap, once learned.
+    pub fn pylons(&self) -> Option<usize> {
+        match self.learned {
+            true => Some(self.sequence.len()),
+            false => None,
+        }
+    }
+
+    /// Forget the sequence and the laps.
+    pub fn clear(&mut self) {
+        *self = Self::new(self.min_pylons);
+    }
+
+    /// Restart counting the laps, keeping the learned sequence.
+    pub fn restart(&mut self) {
+        self.laps = 0;
+    }
+
+    /// Record a reached pylon.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The OCR id of the pylon.
+    ///
+    /// # Returns
+    ///
+    /// `true` if a lap has been completed.
+    pub fn observe(&mut self, id: u8) -> bool {
+        let index = self.sequence.iter().position(|s| *s == id);
+        if !self.learned {
+            match index {
+                // The same pylon read again
+                Some(k) if k + 1 == self.sequence.len() => false,
+                // The sequence repeats
+                Some(k) if self.min_pylons <= self.sequence.len() => {
+                    // The lap starts at the repeated pylon
+                    self.sequence.rotate_left(k);
+                    self.learned = true;
+                    self.last = Some(0);
+                    self.laps += 1;
+                    log::info!("Lap Sequence Learned: {:?}", self.sequence);
+                    true
+                }
+                Some(_) => {
+                    log::debug!("Too Short Lap. Ignored. id: {}", id);
+                    false
+                }
+                None => {
+                    self.sequence.push(id);
+                    false
+                }
+            }
+        } else {
+            let k = match index {
+                Some(k) => k,
+                None => {
+                    log::debug!("Unknown Pylon Id. Ignored. id: {}", id);
+                    return false;
+                }
+            };
+            // Passing the first pylon again means a lap is completed
+            let wrapped = matches!(self.last, Some(last) if k < last);
+            self.last = Some(k);
+            if wrapped {
+                self.laps += 1;
+            }
+            wrapped
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lap_counter_test() {
+        let mut counter = LapCounter::new(3);
+        // First lap, with a pylon read twice
+        for id in [4, 7, 7, 2, 9] {
+            assert!(!counter.observe(id));
+        }
+        assert_eq!(counter.pylons(), None);
+        assert!(counter.observe(4));
+        assert_eq!(counter.pylons(), Some(4));
+        // Second lap, skipping a pylon and misreading another
+        for id in [7, 9, 5] {
+            assert!(!counter.observe(id));
+        }
+        assert!(counter.observe(4));
+        assert_eq!(counter.laps, 2);
+        // Starting from the middle of the lap
+        let mut counter = LapCounter::new(3);
+        for id in [1, 2, 3] {
+            assert!(!counter.observe(id));
+        }
+        assert!(counter.observe(2));
+        assert_eq!(counter.sequence, vec![2, 3, 1]);
+        assert!(!counter.observe(3));
+        assert!(!counter.observe(1));
+        assert!(counter.observe(2));
+        // Too short to be a lap
+        let mut counter = LapCounter::new(3);
+        assert!(!counter.observe(1));
+        assert!(!counter.observe(2));
+        assert!(!counter.observe(1));
+        assert!(!counter.learned);
+    }
+}
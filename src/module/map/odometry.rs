@@ -0,0 +1,113 @@
+//! Dead Reckoning
+//!
+//! There are no wheel encoders, so the pose is estimated from the commanded action and its duration.
+
+use serde::{Deserialize, Serialize};
+
+use crate::module::device::Actions;
+
+/// Position and heading of the mower.
+///
+/// The origin is the start position, and the mower initially faces the +x direction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct Pose {
+    pub x: f32,     // Metres
+    pub y: f32,     // Metres
+    pub theta: f32, // Radians, counterclockwise is positive
+}
+
+/// Integrates the drive actions into a pose.
+#[derive(Debug, Clone)]
+pub struct Odometry {
+    pub pose: Pose,    // Current pose
+    pub distance: f32, // Total distance travelled in metres
+    pub scale: f32,    // Current speed relative to full power
+    speed: f32,        // Metres per second at full power
+    turn_speed: f32,   // Radians per second when turning on the spot
+    last_update: u64,  // Milliseconds
+}
+
+impl Odometry {
+    /// Creates a new Odometry at the origin.
+    ///
+    /// # Arguments
+    ///
+    /// * `speed` - Travelling speed in metres per second.
+    /// * `turn_speed` - Turning speed in degrees per second.
+    ///
+    pub fn new(speed: f32, turn_speed: f32) -> Self {
+        Self {
+            pose: Pose::default(),
+            distance: 0.0,
+            scale: 1.0,
+            speed,
+            turn_speed: turn_speed.to_radians(),
+            last_update: 0,
+        }
+    }
+
+    /// Integrate the current action from the last update to `now`.
+    ///
+    /// The device thread pauses the motors at `target_time`, so the motion is counted up to that time at most.
+    ///
+    /// # Arguments
+    ///
+    /// * `action` - The current action of the drive system.
+    /// * `target_time` - The time when the current action ends in milliseconds.
+    /// * `now` - The current time in milliseconds.
+    ///
+    pub fn update(&mut self, action: &Actions, target_time: u64, now: u64) {
+        if self.last_update == 0 {
+            self.last_update = now;
+            return;
+        }
+        let end = now.min(target_time.max(self.last_update));
+        let dt = end.saturating_sub(self.last_update) as f32 / 1000.0;
+        self.last_update = now;
+        let dt = dt * self.scale;
+        match action {
+            Actions::Forward => self.translate(self.speed * dt),
+            Actions::Backward => self.translate(-self.speed * dt),
+            Actions::Left => self.pose.theta += self.turn_speed * dt,
+            Actions::Right => self.pose.theta -= self.turn_speed * dt,
+            Actions::Stop | Actions::Pause => {}
+        }
+    }
+
+    /// Move along the current heading.
+    fn translate(&mut self, d: f32) {
+        self.pose.x += d * self.pose.theta.cos();
+        self.pose.y += d * self.pose.theta.sin();
+        self.distance += d.abs();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn odometry_test() {
+        let mut odometry = Odometry::new(0.5, 90.0);
+        // The first update only initializes the clock
+        odometry.update(&Actions::Forward, 10000, 1000);
+        assert_eq!(odometry.pose, Pose::default());
+        // 2 seconds forward
+        odometry.update(&Actions::Forward, 10000, 3000);
+        assert!((odometry.pose.x - 1.0).abs() < 1e-5);
+        // Turning is limited by the target time (1 second -> 90 degrees)
+        odometry.update(&Actions::Left, 4000, 6000);
+        assert!((odometry.pose.theta - std::f32::consts::FRAC_PI_2).abs() < 1e-5);
+        // 1 second forward to +y
+        odometry.update(&Actions::Forward, 10000, 7000);
+        assert!((odometry.pose.x - 1.0).abs() < 1e-5);
+        assert!((odometry.pose.y - 0.5).abs() < 1e-5);
+        // Paused
+        odometry.update(&Actions::Pause, 10000, 9000);
+        assert!((odometry.distance - 1.5).abs() < 1e-5);
+        // 2 seconds forward at half speed
+        odometry.scale = 0.5;
+        odometry.update(&Actions::Forward, 20000, 11000);
+        assert!((odometry.distance - 2.0).abs() < 1e-5);
+    }
+}
@@ -7,13 +7,18 @@ pub mod follow_person; // Follow person module
 pub mod monitor_animal; // Monitoring animal module
 pub mod monitor_person; // Monitoring person module
 pub mod oneway; // One-way module
+pub mod patrol; // Patrol module
 pub mod round_trip; // Round-trip between person and marker module
+pub mod route; // Route over numbered pylons module
+pub mod survey; // Pre-mission field survey module
 
 use super::{
     com::Neighbor, // Import the Neighbor type from the com module
-    device::Roktrack,
+    device::{Chassis, Roktrack},
+    map::{lap::LapCounter, odometry::Odometry, FieldMap},
+    report::MissionLog,
     util::{conf::Config, init::RoktrackProperty},
-    vision::{VisionMgmtCommand, VisualInfo},
+    vision::{detector::Detection, geometry::Geometry, VisionMgmtCommand, VisualInfo},
 };
 use rand::{self, seq::SliceRandom}; // Import random number generation
 use std::collections::HashMap;
@@ -30,6 +35,9 @@ pub enum Modes {
     MonitorAnimal,
     RoundTrip,
     FollowPerson,
+    Patrol,
+    Route,
+    Survey,
     Unknown,
 }
 
@@ -45,6 +53,9 @@ impl Modes {
             "monitor_person" => Modes::MonitorPerson,
             "round_trip" => Modes::RoundTrip,
             "follow_person" => Modes::FollowPerson,
+            "patrol" => Modes::Patrol,
+            "route" => Modes::Route,
+            "survey" => Modes::Survey,
             _ => Modes::Unknown,
         }
     }
@@ -60,6 +71,9 @@ impl Modes {
             5 => Modes::MonitorAnimal,
             6 => Modes::RoundTrip,
             7 => Modes::FollowPerson,
+            8 => Modes::Patrol,
+            9 => Modes::Route,
+            10 => Modes::Survey,
             _ => Modes::Unknown,
         }
     }
@@ -75,6 +89,9 @@ impl Modes {
             Modes::MonitorAnimal => 5,
             Modes::RoundTrip => 6,
             Modes::FollowPerson => 7,
+            Modes::Patrol => 8,
+            Modes::Route => 9,
+            Modes::Survey => 10,
             _ => 255,
         }
     }
@@ -87,6 +104,23 @@ pub enum Phase {
     CCW,
 }
 
+/// This enum represents the stage of Fill.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FillStage {
+    Edge,   // First lap along the pylon line
+    Spiral, // Shrinking laps
+}
+
+impl FillStage {
+    /// The first stage of Fill.
+    pub fn first(conf: &Config) -> FillStage {
+        match conf.edge.enable {
+            true => FillStage::Edge,
+            false => FillStage::Spiral,
+        }
+    }
+}
+
 /// This struct represents the state for auto-pilot.
 #[derive(Debug, Clone)]
 pub struct RoktrackState {
@@ -102,10 +136,39 @@ pub struct RoktrackState {
     pub pi_temp: f32,       // Raspberry Pi's SoC temperature
     pub msg: u8,            // Current state message
     pub identifier: u8,     // My identifier
-    pub img_width: u32,     // Width of the image to process
-    pub img_height: u32,    // Height of the image to process
+    pub img_width: u32,     // Width of the original image (detections are in its pixels)
+    pub img_height: u32,    // Height of the original image (detections are in its pixels)
     pub diff: f32,          // Normalized marker gap to center.
     pub marker_height: u32, // Normalized marker height.
+
+    pub still_since: u64, // Time when the image stopped changing while driving forward
+    pub stuck_count: u8,  // Number of recovery maneuvers for being stuck
+
+    pub odometry: Odometry,  // Dead reckoning of the mower's pose
+    pub map: FieldMap,       // Pylon layout of the site
+    pub reach_distance: f32, // Distance to the marker when the approach ends (metres)
+
+    pub mission: MissionLog, // Statistics of the current mission
+
+    pub keepouts: Vec<Detection>, // Keep-out pylons in the latest image
+    pub keepout_clearance: f32,   // Distance to pass by the keep-out pylons (pylon widths)
+
+    pub fill_stage: FillStage, // Edge lap or shrinking laps of Fill
+    pub edge_offset: f32,      // Shift to the side of the pylon in the edge lap
+
+    pub person_seen: u64, // Last time a person was seen while stopped (milliseconds)
+
+    pub tracked_frames: u32, // Number of frames the current target has been tracked
+
+    pub waypoint: u8, // Index of the next waypoint of the route
+
+    pub lap_counter: LapCounter, // Laps counted from the sequence of OCR ids
+
+    pub input_size: u32, // Input size of the model (320 or 640)
+
+    pub target_track: Option<u32>, // Track id of the current target
+
+    pub reach_height: u16, // Marker height at the reach distance, the target height of the outermost lap
 }
 
 impl RoktrackState {
@@ -117,7 +180,8 @@ impl RoktrackState {
             turn_count: -1,
             ex_height: 0,
             rest: 1.0,
-            target_height: (240.0 * 0.9) as u16,
+            target_height: Geometry::new(&conf).reach_height(),
+            reach_height: Geometry::new(&conf).reach_height(),
             phase: Phase::CCW,
             constant: 0.005,
             marker_id: None,
@@ -128,10 +192,26 @@ impl RoktrackState {
             // 251-254: preserved
             // 255: broadcast
             identifier: conf.system.identifier,
-            img_width: 320,
-            img_height: 240,
+            img_width: conf.camera.width as u32,
+            img_height: conf.camera.height as u32,
+            input_size: 320,
             diff: 0.0,
             marker_height: 0,
+            still_since: 0,
+            stuck_count: 0,
+            odometry: Odometry::new(conf.map.speed, conf.map.turn_speed),
+            map: FieldMap::new(&conf.map.site),
+            reach_distance: conf.map.reach_distance,
+            mission: MissionLog::new(conf.report.cell_size),
+            keepouts: vec![],
+            keepout_clearance: conf.keepout.clearance,
+            fill_stage: FillStage::first(&conf),
+            edge_offset: conf.edge.offset,
+            person_seen: 0,
+            tracked_frames: 0,
+            target_track: None,
+            waypoint: 0,
+            lap_counter: LapCounter::new(conf.lap.min_pylons),
         }
     }
 
@@ -141,15 +221,28 @@ impl RoktrackState {
         self.turn_count = -1;
         self.ex_height = 0;
         self.rest = 1.0;
-        self.target_height = (240.0 * 0.9) as u16;
+        self.target_height = self.reach_height;
         self.phase = Phase::CCW;
         self.constant = 0.005;
         self.marker_id = None;
         self.msg = 255;
-        self.img_width = 320;
-        self.img_height = 240;
+        self.input_size = 320;
         self.diff = 0.0;
         self.marker_height = 0;
+        self.still_since = 0;
+        self.stuck_count = 0;
+        self.keepouts = vec![];
+        self.fill_stage = FillStage::Spiral;
+        self.person_seen = 0;
+        self.tracked_frames = 0;
+        self.target_track = None;
+        self.waypoint = 0;
+        self.lap_counter.clear();
+    }
+
+    /// Whether the Fill is in the edge lap.
+    pub fn is_edge_pass(&self) -> bool {
+        self.mode == Modes::Fill && self.fill_stage == FillStage::Edge
     }
 
     /// Invert the phase (CCW -> CW) and reset counters.
@@ -180,6 +273,19 @@ impl RoktrackState {
             (device.inner.clone().lock().unwrap().drive_motor_left.power * 100.0) as u8;
         let diff_u8 = ((self.diff + 1.0) * 127.0) as u8;
         let marker_height_u8 = (self.marker_height as f32 / self.img_height as f32 * 100.0) as u8;
+        let person_stops_u8 = self.mission.person_stops.min(255) as u8;
+        let person_resumes_u8 = self.mission.person_resumes.min(255) as u8;
+        // Bit i is set if the work motor i is faulted
+        let work_faults_u8 = device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .work_faults()
+            .iter()
+            .take(8)
+            .enumerate()
+            .fold(0u8, |bits, (i, fault)| bits | ((*fault as u8) << i));
         // Construct the payload
         let mut val = vec![
             state_and_rest,          // State and rest
@@ -192,6 +298,10 @@ impl RoktrackState {
             right_power_u8,          // Right Motor Power
             diff_u8,                 // Normalized f32 diff to u8. (-1 ~ 1) -> (0 ~ 255)
             marker_height_u8,        // u8 marker height.
+            person_stops_u8,         // Stops for a person
+            person_resumes_u8,       // Resumes after a person has gone
+            work_faults_u8,          // Faulted work motors as bits
+            self.waypoint,           // Index of the next waypoint of the route
         ];
         // Padding
         val.resize(23, 0);
@@ -228,6 +338,14 @@ mod tests {
         assert_eq!(state.phase, Phase::CCW);
         state.invert_phase();
         assert_eq!(state.phase, Phase::CW);
+        // edge pass test
+        state.fill_stage = FillStage::Edge;
+        assert!(state.is_edge_pass());
+        state.mode = Modes::OneWay;
+        assert!(!state.is_edge_pass());
+        state.mode = Modes::Fill;
+        state.invert_phase();
+        assert!(!state.is_edge_pass());
         // dump test
         let neighbors = HashMap::new();
         assert_eq!(
@@ -249,4 +367,7 @@ pub trait PilotHandler: Send + Sync {
         property: RoktrackProperty,
     ) {
     }
+
+    /// Clear the progress kept in the state by the pilot, called when the mode starts over.
+    fn reset(&mut self, state: &mut RoktrackState) {}
 }
@@ -6,12 +6,17 @@ use std::thread;
 use std::time;
 
 use crate::module::com::ChildMsg;
+use crate::module::device::Actions;
 use crate::module::device::Chassis;
 use crate::module::device::Roktrack;
 use crate::module::pilot::RoktrackState;
+use crate::module::report::{MissionLog, MissionReport};
+use crate::module::util::common::send_line_notify_with_image;
+use crate::module::util::conf::{Config, Speed};
 use crate::module::util::init::RoktrackProperty;
-use crate::module::vision::detector::Detection;
+use crate::module::vision::detector::{Detection, FilterClass, RoktrackClasses};
 use crate::module::vision::VisionMgmtCommand;
+use crate::module::vision::VisualInfo;
 
 use super::Phase;
 
@@ -29,9 +34,12 @@ pub fn pre_process(
 
 /// Post-processing for handle.
 pub fn post_process(
-    _state: &mut RoktrackState,
+    state: &mut RoktrackState,
     _device: &mut Roktrack,
+    property: RoktrackProperty,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    // Save the pylon layout if a pylon has been recorded.
+    state.map.save(&property.path.dir.map)?;
     Ok(())
 }
 
@@ -66,25 +74,256 @@ pub fn stop(device: &mut Roktrack) -> Result<(), Box<dyn std::error::Error>> {
 ///
 /// An `Option<()>` where `Some(())` indicates success.
 pub fn escape(
-    state: &RoktrackState,
+    state: &mut RoktrackState,
     device: &mut Roktrack,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let binding = device.inner.clone();
-    let mut device_lock = binding.lock().unwrap();
-    device_lock.backward(2000);
-    thread::sleep(time::Duration::from_millis(2000));
+    state.mission.bumps += 1;
+    run_action(device, Actions::Backward, 2000);
     match state.phase {
-        Phase::CCW => device_lock.left(800),
-        Phase::CW => device_lock.right(800),
+        Phase::CCW => run_action(device, Actions::Left, 800),
+        Phase::CW => run_action(device, Actions::Right, 800),
     };
-    thread::sleep(time::Duration::from_millis(800));
-    device_lock.forward(2000);
-    thread::sleep(time::Duration::from_millis(2000));
+    run_action(device, Actions::Forward, 2000);
     match state.phase {
-        Phase::CCW => device_lock.right(800),
-        Phase::CW => device_lock.left(800),
+        Phase::CCW => run_action(device, Actions::Right, 800),
+        Phase::CW => run_action(device, Actions::Left, 800),
     };
-    thread::sleep(time::Duration::from_millis(1000));
+    thread::sleep(time::Duration::from_millis(200));
+    Ok(())
+}
+
+/// Run an action and wait until its duration has passed.
+///
+/// The device lock is released while waiting, so that the device thread can start
+/// the action held until the blade has spun up or down.
+///
+/// # Arguments
+///
+/// * `device` - A mutable reference to the Roktrack device.
+/// * `action` - The action to run.
+/// * `milsec` - Duration of the action in milliseconds.
+///
+pub fn run_action(device: &mut Roktrack, action: Actions, milsec: u64) {
+    {
+        let binding = device.inner.clone();
+        let mut device_lock = binding.lock().unwrap();
+        match action {
+            Actions::Forward => device_lock.forward(milsec),
+            Actions::Backward => device_lock.backward(milsec),
+            Actions::Left => device_lock.left(milsec),
+            Actions::Right => device_lock.right(milsec),
+            Actions::Pause => device_lock.pause(),
+            Actions::Stop => device_lock.stop(),
+        }
+    }
+    // Wait for the blade
+    while device.inner.clone().lock().unwrap().is_held() {
+        thread::sleep(time::Duration::from_millis(10));
+    }
+    thread::sleep(time::Duration::from_millis(milsec));
+}
+
+/// Responses to a detected person.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PersonResponse {
+    Warn,    // The person is far. Keep working.
+    Stop,    // The person is close. Stop.
+    HoldOff, // Stopped for a person. Stay stopped until the person has gone for a while.
+}
+
+/// Judge how to respond to people in the image.
+///
+/// The bbox height of the biggest person relative to the image height is used as a proxy for the distance.
+/// Once stopped, the mower stays stopped until no person has been seen for the hold-off time.
+///
+/// # Arguments
+///
+/// * `state` - A mutable reference to the RoktrackState representing the current state of the pilot.
+/// * `dets` - Detections of the latest image.
+/// * `conf` - The configuration for the person response.
+/// * `now` - Current time in milliseconds.
+///
+/// # Returns
+///
+/// The response, or `None` if there is nothing to care about.
+pub fn assess_person(
+    state: &mut RoktrackState,
+    dets: &mut [Detection],
+    conf: Config,
+    now: u64,
+) -> Option<PersonResponse> {
+    let persons = RoktrackClasses::filter(
+        dets,
+        RoktrackClasses::PERSON.to_u32(),
+        conf.detectthreshold.person,
+    );
+    let ratio = persons.iter().map(|p| p.h).max().unwrap_or(0) as f32 / state.img_height as f32;
+    if !persons.is_empty() {
+        state.person_seen = now;
+    }
+    if !persons.is_empty() && conf.person.stop <= ratio {
+        Some(PersonResponse::Stop)
+    } else if state.mission.is_person_stopping() && now < state.person_seen + conf.person.holdoff {
+        Some(PersonResponse::HoldOff)
+    } else if !persons.is_empty() {
+        Some(PersonResponse::Warn)
+    } else {
+        None
+    }
+}
+
+/// Respond to a detected person.
+///
+/// Stops the mower for a close person, and keeps working with a warning for a distant one.
+/// Each response is reported through the state message.
+///
+/// # Arguments
+///
+/// * `state` - A mutable reference to the RoktrackState representing the current state of the pilot.
+/// * `device` - A mutable reference to the Roktrack device.
+/// * `response` - The response judged by `assess_person`.
+///
+/// # Returns
+///
+/// `true` if the mower must not proceed.
+pub fn respond_person(
+    state: &mut RoktrackState,
+    device: &mut Roktrack,
+    response: Option<PersonResponse>,
+) -> bool {
+    match response {
+        Some(PersonResponse::Stop) => {
+            let _ = stop(device);
+            if !state.mission.is_person_stopping() {
+                log::warn!("Person Close. Stopped.");
+                device.speak("person_detecting");
+            }
+            state.mission.person_stop();
+            state.msg = ChildMsg::to_u8(ChildMsg::PersonFoundPause);
+            true
+        }
+        Some(PersonResponse::HoldOff) => {
+            let _ = stop(device);
+            state.msg = ChildMsg::to_u8(ChildMsg::PersonFoundPause);
+            true
+        }
+        Some(PersonResponse::Warn) => {
+            if state.mission.person_warn() {
+                log::warn!("Person Far. Warning.");
+                device.speak("person_detecting_warn");
+            }
+            state.msg = ChildMsg::to_u8(ChildMsg::PersonFoundWarn);
+            false
+        }
+        None => {
+            if state.mission.is_person_stopping() {
+                log::info!("Hold-off Elapsed. Resumed.");
+            }
+            state.mission.person_clear();
+            false
+        }
+    }
+}
+
+/// Judge whether the mower is stuck.
+///
+/// While the mower is driving forward, the image should keep changing.
+/// If the frame-to-frame change stays below the threshold for the configured duration,
+/// the wheels are probably slipping or the chassis is hung up on something.
+///
+/// # Arguments
+///
+/// * `state` - A mutable reference to the RoktrackState representing the current state of the pilot.
+/// * `device` - A reference to the Roktrack device.
+/// * `visual_info` - The latest visual information including the motion score.
+/// * `conf` - The configuration for stuck detection.
+///
+/// # Returns
+///
+/// `true` if the mower is regarded as stuck.
+pub fn assess_stuck(
+    state: &mut RoktrackState,
+    device: &Roktrack,
+    visual_info: &VisualInfo,
+    conf: Config,
+) -> bool {
+    if !conf.stuck.enable {
+        return false;
+    }
+    let forwarding = device.inner.clone().lock().unwrap().action == Actions::Forward;
+    match visual_info.motion {
+        Some(motion) if forwarding && motion < conf.stuck.motion_threshold => {
+            let now = chrono::Utc::now().timestamp_millis() as u64;
+            if state.still_since == 0 {
+                state.still_since = now;
+            }
+            log::debug!(
+                "No Motion. motion: {}, still_for: {}",
+                motion,
+                now - state.still_since
+            );
+            now - state.still_since > conf.stuck.duration
+        }
+        Some(_) if forwarding => {
+            // Moving again, the earlier recoveries have worked
+            if 0 < state.stuck_count {
+                log::info!("Moving Again. stuck_count: {} -> 0", state.stuck_count);
+            }
+            state.still_since = 0;
+            state.stuck_count = 0;
+            false
+        }
+        _ => {
+            // Not driving forward
+            state.still_since = 0;
+            false
+        }
+    }
+}
+
+/// Try to get out of the stuck situation.
+///
+/// This function backs up and twists the chassis, alternating the direction each time.
+/// When the number of attempts exceeds the limit, the mower is halted and the owner is notified.
+///
+/// # Arguments
+///
+/// * `state` - A mutable reference to the RoktrackState representing the current state of the pilot.
+/// * `device` - A mutable reference to the Roktrack device.
+/// * `tx` - A sender for sending commands to the vision management system.
+/// * `property` - The properties of the app, such as paths and configurations.
+///
+/// # Returns
+///
+/// A `Result` indicating success or an error.
+pub fn recover_stuck(
+    state: &mut RoktrackState,
+    device: &mut Roktrack,
+    tx: Sender<VisionMgmtCommand>,
+    property: RoktrackProperty,
+) -> Result<(), Box<dyn std::error::Error>> {
+    state.still_since = 0;
+    state.stuck_count += 1;
+    if state.stuck_count > property.conf.stuck.max_retry {
+        state.state = false;
+        state.msg = ChildMsg::to_u8(ChildMsg::Halt);
+        device.inner.clone().lock().unwrap().stop();
+        tx.send(VisionMgmtCommand::Off).unwrap();
+        log::warn!("Stuck. Halted!");
+        let _ =
+            send_line_notify_with_image("Stuck. Halted.", &property.path.img.last, property.conf);
+        return Ok(());
+    }
+    log::warn!("Stuck. Recovering... stuck_count: {}", state.stuck_count);
+    device.speak("stuck");
+    run_action(device, Actions::Backward, 1500);
+    // Twist to the other side each time
+    if state.stuck_count % 2 == 1 {
+        run_action(device, Actions::Left, 600);
+    } else {
+        run_action(device, Actions::Right, 600);
+    }
+    device.inner.clone().lock().unwrap().pause();
     Ok(())
 }
 
@@ -117,9 +356,9 @@ pub fn halt(
 
 /// Increase the image resolution and adjust state.
 ///
-/// This function sends a command to the vision system to upscale the image resolution.
-/// It also updates the local state to reflect the new image dimensions and adjusts
-/// the expected and target heights accordingly.
+/// This function sends a command to the vision system to upscale the input size of the model.
+/// The detections are in the pixels of the original image regardless of the input size,
+/// so the expected and target heights stay as they are.
 ///
 /// # Arguments
 ///
@@ -132,29 +371,21 @@ pub fn upscale(
     // Command vision to upscale
     tx.send(VisionMgmtCommand::SwitchSz640).unwrap();
     // Change local state
-    let new_width: f32 = 640.0;
-    let new_height: f32 = 480.0;
-    let ratio = new_width / state.img_width as f32;
-    state.img_height = new_height as u32;
-    state.img_width = new_width as u32;
-    state.ex_height = (state.ex_height as f32 * ratio) as u16;
-    state.target_height = (state.target_height as f32 * ratio) as u16;
+    state.input_size = 640;
     log::debug!(
-        "UpScaled. ih:{}, iw:{}, eh:{}, th:{}, ratio:{}",
-        state.img_height,
-        state.img_width,
+        "UpScaled. input_size:{}, eh:{}, th:{}",
+        state.input_size,
         state.ex_height,
         state.target_height,
-        ratio,
     );
     Ok(())
 }
 
 /// Decrease the image resolution and adjust state.
 ///
-/// This function sends a command to the vision system to downscale the image resolution.
-/// It also updates the local state to reflect the new image dimensions and adjusts
-/// the expected and target heights accordingly.
+/// This function sends a command to the vision system to downscale the input size of the model.
+/// The detections are in the pixels of the original image regardless of the input size,
+/// so the expected and target heights stay as they are.
 ///
 /// # Arguments
 ///
@@ -167,20 +398,12 @@ pub fn downscale(
     // Command vision to downscale
     tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
     // Change local state
-    let new_width: f32 = 320.0;
-    let new_height: f32 = 240.0;
-    let ratio = new_width / state.img_width as f32;
-    state.img_height = new_height as u32;
-    state.img_width = new_width as u32;
-    state.ex_height = (state.ex_height as f32 * ratio) as u16;
-    state.target_height = (state.target_height as f32 * ratio) as u16;
+    state.input_size = 320;
     log::debug!(
-        "DownScaled. ih:{}, iw:{}, eh:{}, th:{}, ratio:{}",
-        state.img_height,
-        state.img_width,
+        "DownScaled. input_size:{}, eh:{}, th:{}",
+        state.input_size,
         state.ex_height,
         state.target_height,
-        ratio,
     );
     Ok(())
 }
@@ -283,11 +506,13 @@ pub fn invert_phase(
 /// Perform actions when mission targets are achieved, and the system is shut down.
 ///
 /// This function sets the pilot's state to false (off) and stops the Roktrack's movement.
+/// Then the mission report is written to the data directory and sent as a notification.
 ///
 /// # Arguments
 ///
 /// * `state` - A mutable reference to the `RoktrackState` representing the current state of the pilot.
 /// * `device` - A mutable reference to the `Roktrack` device.
+/// * `property` - The properties of the app, such as paths and configurations.
 ///
 /// # Returns
 ///
@@ -295,12 +520,24 @@ pub fn invert_phase(
 pub fn mission_complete(
     state: &mut RoktrackState,
     device: &mut Roktrack,
+    property: RoktrackProperty,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Set the pilot's state to false (off)
     state.state = false;
+    state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
     // Stop the Roktrack's movement
     device.inner.clone().lock().unwrap().stop();
+    device.speak("mission_complete");
     log::info!("Mission Completed!");
+    // Write the mission report
+    let report = MissionReport::from_state(state);
+    log::info!("{}", report.summary());
+    // The next start is a new mission
+    state.mission = MissionLog::new(state.mission.coverage.cell_size);
+    let img_path = report.save(&property.path.dir.report)?;
+    if property.conf.report.notify {
+        let _ = send_line_notify_with_image(&report.summary(), &img_path, property.conf);
+    }
     Ok(())
 }
 
@@ -363,14 +600,19 @@ pub fn set_new_target(
     device.inner.clone().lock().unwrap().pause();
     // Speak a notification
     device.speak("new_cone_found");
-    // Subtract the rest value
-    state.rest -= state.constant;
+    // Subtract the rest value, except in the edge lap
+    if !state.is_edge_pass() {
+        state.rest -= state.constant;
+    }
     // Calculate the new target height based on the marker properties
     state.target_height = (marker.h as f32
-        + (state.img_height as f32 * 0.9 - marker.h as f32) * (state.rest.powf(2.0)))
+        + (state.reach_height as f32 - marker.h as f32) * (state.rest.powf(2.0)))
         as u16;
     // Reset the turn count
     state.turn_count = -1;
+    // Start tracking the new target
+    state.tracked_frames = 0;
+    state.target_track = marker.track_id;
     log::debug!(
         "Set New Target. rest: {}, target_height: {}, turn_count: {}",
         state.rest,
@@ -401,6 +643,9 @@ pub fn stand(
     let _ = upscale(state, tx);
     // Send "target lost" message
     state.msg = ChildMsg::to_u8(ChildMsg::TargetLost);
+    // The target has been lost
+    state.tracked_frames = 0;
+    state.target_track = None;
     // Reset the turn count
     state.turn_count = 0;
     Ok(())
@@ -467,6 +712,27 @@ pub fn reach_marker(
     device.inner.clone().lock().unwrap().pause();
     // Reset the turn count to 1
     state.turn_count = 1;
+    // Reaching a marker means the mower is moving
+    state.stuck_count = 0;
+    // The target has been reached
+    state.tracked_frames = 0;
+    state.target_track = None;
+    // Record the pylon on the map, at the estimated distance if its height is known
+    let pose = state.odometry.pose;
+    let distance = marker.distance.unwrap_or(state.reach_distance);
+    let pylon = state
+        .map
+        .record(marker.ids.first().copied(), &pose, distance);
+    // A repeat of the OCR id sequence, or back at the first pylon of the mission, means a lap is completed
+    let back_at_first = state.mission.reach(pylon.order);
+    let lap_completed = match marker.ids.first() {
+        Some(id) => state.lap_counter.observe(*id),
+        None => back_at_first,
+    };
+    if lap_completed {
+        state.mission.laps += 1;
+        log::info!("Lap Completed. laps: {}", state.mission.laps);
+    }
     // Set the expected height to the marker's height
     state.ex_height = marker.h as u16;
     // Clear the target height
@@ -533,13 +799,14 @@ fn get_diff(
     cam_height: u32,
     cam_width: u32,
     phase: Phase,
+    offset_ratio: f32,
 ) -> f32 {
     let mut offset = 0.0;
     // When approaching the pylon closest,
     // shift slightly to the side of the pylon
     // so that you can get as close as possible.
     offset += if marker_height as f32 > cam_height as f32 * 0.5 {
-        cam_width as f32 / 2.0 * 0.4
+        cam_width as f32 / 2.0 * offset_ratio
     } else {
         0.0
     };
@@ -576,6 +843,11 @@ pub fn proceed(
     marker: Detection,
     tx: Sender<VisionMgmtCommand>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    // Hug the pylon line in the edge lap
+    let offset_ratio = match state.is_edge_pass() {
+        true => state.edge_offset,
+        false => 0.4,
+    };
     // Calculate the difference between the target direction and the current direction of travel
     let diff = get_diff(
         marker.xc,
@@ -584,9 +856,38 @@ pub fn proceed(
         state.img_height,
         state.img_width,
         state.phase.clone(),
+        offset_ratio,
+    );
+    // Steer around keep-out pylons in front of the marker
+    let diff = avoid_keepout(
+        diff,
+        &marker,
+        &state.keepouts,
+        state.img_width,
+        state.phase.clone(),
+        state.keepout_clearance,
     );
     state.diff = diff; // Save normalized marker gap to center.
 
+    // Lock onto the track of the marker, tracking afresh when the target has changed
+    if marker.track_id != state.target_track {
+        state.target_track = marker.track_id;
+        state.tracked_frames = 0;
+    }
+    // Slow down when approaching the marker or until the marker is tracked steadily
+    state.tracked_frames += 1;
+    {
+        let binding = device.inner.clone();
+        let mut device_lock = binding.lock().unwrap();
+        let scale = speed_scale(
+            marker.h,
+            state.target_height,
+            state.tracked_frames,
+            &device_lock.speed,
+        );
+        device_lock.set_speed(scale);
+    }
+
     // Calculate a value based on the difference for motor adjustments
     let val = (0.1 * diff).abs() as f64;
 
@@ -621,7 +922,7 @@ pub fn proceed(
     }
 
     // Check if high-resolution processing is needed based on marker height and current image resolution
-    if marker.h as f32 > state.img_height as f32 * 0.05 && state.img_width == 640 {
+    if marker.h as f32 > state.img_height as f32 * 0.05 && state.input_size == 640 {
         // Send a command to downscale the resolution
         let _ = downscale(state, tx);
     }
@@ -629,11 +930,156 @@ pub fn proceed(
     Ok(())
 }
 
+/// Calculate the speed scale for approaching the marker.
+///
+/// The speed goes down linearly from `max` to `min` as the marker height grows
+/// from `slow_zone` of the target height to the target height,
+/// and goes up from `min` to `max` over `ramp_frames` after the marker is found.
+///
+/// # Arguments
+///
+/// * `marker_height` - Height of the marker in pixels.
+/// * `target_height` - Height of the marker to reach in pixels.
+/// * `tracked_frames` - Number of frames the marker has been tracked.
+/// * `conf` - The configuration for speed limits.
+///
+/// # Returns
+///
+/// The speed scale within the limits.
+fn speed_scale(marker_height: u32, target_height: u16, tracked_frames: u32, conf: &Speed) -> f64 {
+    let range = conf.max - conf.min;
+    // Approach
+    let approach = match target_height {
+        0 => 0.0,
+        _ => marker_height as f64 / target_height as f64,
+    };
+    let approach_scale = if approach <= conf.slow_zone {
+        conf.max
+    } else {
+        let progress = ((approach - conf.slow_zone) / (1.0 - conf.slow_zone)).clamp(0.0, 1.0);
+        conf.max - range * progress
+    };
+    // Tracking
+    let tracking_scale = match conf.ramp_frames {
+        0 => conf.max,
+        _ => conf.min + range * (tracked_frames as f64 / conf.ramp_frames as f64).min(1.0),
+    };
+    approach_scale
+        .min(tracking_scale)
+        .max(conf.min)
+        .min(conf.max)
+}
+
+/// Shift the difference so that keep-out pylons are passed by.
+///
+/// A keep-out pylon closer than the marker (taller bbox) blocks the way when the heading,
+/// widened by the clearance, crosses it. Then the heading is shifted to pass it on the outside
+/// of the lap, i.e. the keep-out pylon is left on the inner side like a lap marker.
+///
+/// # Arguments
+///
+/// * `diff` - The difference calculated for the marker.
+/// * `marker` - The target marker.
+/// * `keepouts` - Keep-out pylons in the image.
+/// * `cam_width` - Width of the image.
+/// * `phase` - Direction of laps.
+/// * `clearance` - Distance to pass by in pylon widths.
+///
+/// # Returns
+///
+/// The difference to steer with.
+fn avoid_keepout(
+    diff: f32,
+    marker: &Detection,
+    keepouts: &[Detection],
+    cam_width: u32,
+    phase: Phase,
+    clearance: f32,
+) -> f32 {
+    let center = cam_width as f32 / 2.0;
+    keepouts
+        .iter()
+        .filter(|keepout| marker.h < keepout.h)
+        .fold(diff, |diff, keepout| {
+            let margin = keepout.w as f32 * clearance;
+            let (left, right) = (keepout.x1 as f32 - margin, keepout.x2 as f32 + margin);
+            // Where the current heading points to
+            let heading = center - diff * cam_width as f32;
+            if heading < left || right < heading {
+                return diff;
+            }
+            log::debug!("Avoid Keep-out Pylon. keepout: {:?}", keepout);
+            match phase {
+                // Pass on the right, leaving the keep-out pylon on the left
+                Phase::CCW => diff.min((center - right) / cam_width as f32),
+                // Pass on the left, leaving the keep-out pylon on the right
+                Phase::CW => diff.max((center - left) / cam_width as f32),
+            }
+        })
+}
+
+/// Separate keep-out pylons from the detections.
+///
+/// # Returns
+///
+/// A tuple of (keep-out pylons, other detections).
+fn split_keepout(detections: Vec<Detection>, ids: &[u8]) -> (Vec<Detection>, Vec<Detection>) {
+    detections
+        .into_iter()
+        .partition(|det| det.ids.iter().any(|id| ids.contains(id)))
+}
+
+/// Find the detection of the locked target track.
+///
+/// # Arguments
+///
+/// * `state` - The `RoktrackState` holding the locked track id.
+/// * `detections` - Detections of the current frame.
+///
+/// # Returns
+///
+/// The detection of the locked track, if it is in sight.
+fn locked_track<'a>(state: &RoktrackState, detections: &'a [Detection]) -> Option<&'a Detection> {
+    state.target_track?;
+    detections
+        .iter()
+        .find(|det| det.track_id == state.target_track)
+}
+
+/// Put the detection of the locked target track first.
+///
+/// Pilots that take the first detection as the marker keep the same pylon
+/// while it is in sight, instead of re-picking one every frame.
+///
+/// # Arguments
+///
+/// * `state` - The `RoktrackState` holding the locked track id.
+/// * `detections` - Sorted detections of the current frame.
+///
+pub fn lock_track(state: &RoktrackState, mut detections: Vec<Detection>) -> Vec<Detection> {
+    if state.target_track.is_none() {
+        return detections;
+    }
+    if let Some(i) = detections
+        .iter()
+        .position(|det| det.track_id == state.target_track)
+    {
+        let det = detections.remove(i);
+        detections.insert(0, det);
+    }
+    detections
+}
+
 /// Determine if this marker is eligible for pass-through
 ///
 /// If the marker in the foreground is above the target height and another marker exists
 /// to the right of the screen, the marker in the foreground is passed through in case of CCW phase.
 fn determine_pass_through(state: RoktrackState, detections: Vec<Detection>) -> Detection {
+    // Stay on the locked track while it is in sight
+    if let Some(det) = locked_track(&state, &detections) {
+        log::debug!("Locked Track Selected. track_id: {:?}", det.track_id);
+        return det.clone();
+    }
     match detections.len() {
         0 => Detection::default(),                // No detection
         1 => detections.first().unwrap().clone(), // The only one
@@ -688,6 +1134,16 @@ fn determine_pass_through(state: RoktrackState, detections: Vec<Detection>) -> D
     }
 }
 
+/// Names of the audio clips to announce the target id.
+///
+/// The clips are for a single digit, so a longer id is announced digit by digit.
+fn target_clips(id: u8) -> Vec<String> {
+    id.to_string()
+        .chars()
+        .map(|digit| format!("target{}", digit))
+        .collect()
+}
+
 /// Select one marker from several detected markers
 ///
 /// The markers are selected in the opposite direction of the direction of rotation.
@@ -700,7 +1156,14 @@ pub fn select_marker(
     device: &mut Roktrack,
     tx: Sender<VisionMgmtCommand>,
 ) -> Detection {
-    if property.conf.vision.ocr {
+    // Keep-out pylons are obstacles, never lap targets
+    let (keepouts, detections) = split_keepout(detections, &property.conf.keepout.ids);
+    state.keepouts = keepouts;
+    if property.conf.vision.ocr && property.conf.lap.enable {
+        // The lap counter learns the ids of all the pylons, so the OCR stays on without locking onto one id
+        log::debug!("Select Detection With Lap Counting");
+        determine_pass_through(state.clone(), detections)
+    } else if property.conf.vision.ocr {
         if detections.is_empty() {
             determine_pass_through(state.clone(), detections)
         } else {
@@ -724,7 +1187,7 @@ pub fn select_marker(
                         state.marker_id = detection.ids.first().copied();
                         device.speak("switch_ocr_mode");
                         thread::sleep(time::Duration::from_millis(2000));
-                        device.speak(format!("target{}", state.marker_id.unwrap()).as_str());
+                        device.speak_seq(target_clips(state.marker_id.unwrap()));
                         thread::sleep(time::Duration::from_millis(1000));
                         log::debug!(
                             "First Marker Id Found. new_id: {}",
@@ -736,7 +1199,10 @@ pub fn select_marker(
                         determine_pass_through(state.clone(), detections)
                     }
                 } else {
-                    tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap();
+                    // Ids are still needed to find keep-out pylons
+                    if property.conf.keepout.ids.is_empty() {
+                        tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap();
+                    }
                     determine_pass_through(state.clone(), detections.clone())
                 }
             }
@@ -780,6 +1246,173 @@ mod tests {
         );
     }
 
+    #[test]
+    fn assess_person_test() {
+        let property = crate::module::util::init::resource::init();
+        let mut conf = property.conf.clone();
+        conf.detectthreshold.person = 0.5;
+        conf.person.stop = 0.3;
+        conf.person.holdoff = 5000;
+        let mut state = RoktrackState::new(conf.clone());
+        let (far, close) = (state.img_height / 10, state.img_height / 2);
+        let person = |h: u32| Detection {
+            cls: RoktrackClasses::PERSON.to_u32(),
+            prob: 0.9,
+            h,
+            ..Default::default()
+        };
+        // Far person
+        let res = assess_person(&mut state, &mut [person(far)], conf.clone(), 1000);
+        assert_eq!(res, Some(PersonResponse::Warn));
+        // Close person
+        let res = assess_person(&mut state, &mut [person(close)], conf.clone(), 2000);
+        assert_eq!(res, Some(PersonResponse::Stop));
+        state.mission.person_stop();
+        // Moved away but still visible
+        let res = assess_person(&mut state, &mut [person(far)], conf.clone(), 3000);
+        assert_eq!(res, Some(PersonResponse::HoldOff));
+        // Gone, but within the hold-off time
+        let res = assess_person(&mut state, &mut [], conf.clone(), 7000);
+        assert_eq!(res, Some(PersonResponse::HoldOff));
+        // Gone for the hold-off time
+        let res = assess_person(&mut state, &mut [], conf.clone(), 8000);
+        assert_eq!(res, None);
+    }
+
+    #[test]
+    fn assess_stuck_test() {
+        let property = crate::module::util::init::resource::init();
+        let mut conf = property.conf.clone();
+        conf.stuck.enable = true;
+        let mut state = RoktrackState::new(conf.clone());
+        let mut device = Roktrack::new(conf.clone());
+        device.inner.lock().unwrap().forward(0);
+        let visual_info = |motion: f32| VisualInfo {
+            motion: Some(motion),
+            ..VisualInfo::default()
+        };
+        // Still while driving forward
+        state.stuck_count = 2;
+        let still = visual_info(conf.stuck.motion_threshold / 2.0);
+        assert!(!assess_stuck(&mut state, &device, &still, conf.clone()));
+        assert!(0 < state.still_since);
+        assert_eq!(state.stuck_count, 2);
+        // Moving again
+        let moving = visual_info(conf.stuck.motion_threshold * 2.0);
+        assert!(!assess_stuck(&mut state, &device, &moving, conf.clone()));
+        assert_eq!(state.still_since, 0);
+        assert_eq!(state.stuck_count, 0);
+        stop(&mut device).unwrap();
+    }
+
+    #[test]
+    fn run_action_test() {
+        let property = crate::module::util::init::resource::init();
+        let mut conf = property.conf.clone();
+        conf.blade.policy = String::from("forward");
+        conf.blade.spin_up = 300;
+        conf.blade.spin_down = 300;
+        let mut device = Roktrack::new(conf);
+        let (tx, rx) = mpsc::channel();
+        device.run(rx);
+        device.inner.lock().unwrap().set_work(true);
+        // Driving forward waits for the blade to spin up
+        let start = time::Instant::now();
+        run_action(&mut device, Actions::Forward, 200);
+        assert!(time::Duration::from_millis(500) <= start.elapsed());
+        assert!(!device.inner.lock().unwrap().is_held());
+        // Reversing waits for the blade to spin down
+        let start = time::Instant::now();
+        run_action(&mut device, Actions::Backward, 200);
+        assert!(time::Duration::from_millis(500) <= start.elapsed());
+        assert!(!device.inner.lock().unwrap().is_held());
+        tx.send(crate::module::device::DeviceMgmtCommand::Stop)
+            .unwrap();
+        stop(&mut device).unwrap();
+    }
+
+    #[test]
+    fn target_clips_test() {
+        assert_eq!(target_clips(7), vec!["target7"]);
+        assert_eq!(target_clips(12), vec!["target1", "target2"]);
+    }
+
+    #[test]
+    fn speed_scale_test() {
+        let conf = Speed {
+            min: 0.6,
+            max: 1.0,
+            turn: 1.0,
+            slow_zone: 0.5,
+            ramp_frames: 4,
+        };
+        // Far and tracked steadily
+        assert_eq!(speed_scale(50, 200, 10, &conf), 1.0);
+        // Just found
+        assert_eq!(speed_scale(50, 200, 0, &conf), 0.6);
+        assert!((speed_scale(50, 200, 2, &conf) - 0.8).abs() < 1e-9);
+        // Approaching
+        assert!((speed_scale(150, 200, 10, &conf) - 0.8).abs() < 1e-9);
+        assert_eq!(speed_scale(200, 200, 10, &conf), 0.6);
+        assert_eq!(speed_scale(250, 200, 10, &conf), 0.6);
+    }
+
+    #[test]
+    fn keepout_test() {
+        let marker = Detection {
+            x1: 150,
+            x2: 170,
+            w: 20,
+            h: 20,
+            ids: vec![1],
+            ..Default::default()
+        };
+        let keepout = Detection {
+            x1: 150,
+            x2: 170,
+            w: 20,
+            h: 60,
+            ids: vec![8],
+            ..Default::default()
+        };
+        let (keepouts, others) = split_keepout(vec![marker.clone(), keepout.clone()], &[8, 9]);
+        assert_eq!(keepouts, vec![keepout.clone()]);
+        assert_eq!(others, vec![marker.clone()]);
+        // Blocking the way. Pass it on the right in CCW laps, on the left in CW laps.
+        let diff = avoid_keepout(0.0, &marker, &keepouts, 320, Phase::CCW, 1.0);
+        assert_eq!(diff, (160.0 - 190.0) / 320.0);
+        let diff = avoid_keepout(0.0, &marker, &keepouts, 320, Phase::CW, 1.0);
+        assert_eq!(diff, (160.0 - 130.0) / 320.0);
+        // Behind the marker
+        let diff = avoid_keepout(0.0, &keepout, &[marker], 320, Phase::CCW, 1.0);
+        assert_eq!(diff, 0.0);
+    }
+
+    #[test]
+    fn lock_track_test() {
+        let property = crate::module::util::init::resource::init();
+        let mut state = RoktrackState::new(property.conf);
+        let pylon = |x1: u32, h: u32, track_id: u32| Detection {
+            x1,
+            x2: x1 + 20,
+            w: 20,
+            h,
+            track_id: Some(track_id),
+            ..Default::default()
+        };
+        let dets = vec![pylon(900, 100, 1), pylon(300, 120, 2)];
+        // Nothing locked
+        assert_eq!(lock_track(&state, dets.clone()), dets);
+        assert_eq!(determine_pass_through(state.clone(), dets.clone()), dets[0]);
+        // The locked pylon is kept, even when the other one is picked first
+        state.target_track = Some(2);
+        assert_eq!(lock_track(&state, dets.clone())[0], dets[1]);
+        assert_eq!(determine_pass_through(state.clone(), dets.clone()), dets[1]);
+        // The locked pylon is out of sight
+        state.target_track = Some(3);
+        assert_eq!(lock_track(&state, dets.clone()), dets);
+    }
+
     #[test]
     fn scale_test() {
         // Create channels for testing vision management commands
@@ -793,24 +1426,25 @@ mod tests {
         let mut state = RoktrackState::new(property.conf);
         state.ex_height = 100;
 
-        // Test initial state values
+        // Test initial state values (the image size of the camera)
         assert_eq!(state.ex_height, 100);
-        assert_eq!(state.target_height, 216);
-        assert_eq!(state.img_height, 240);
-        assert_eq!(state.img_width, 320);
+        assert_eq!(state.target_height, 648);
+        assert_eq!(state.img_height, 720);
+        assert_eq!(state.img_width, 1280);
+        assert_eq!(state.input_size, 320);
 
-        // Test upscaling
+        // Test upscaling, the heights are in the pixels of the original image
         let _ = upscale(&mut state, channel_vision_mgmt_tx.clone());
-        assert_eq!(state.ex_height, 200);
-        assert_eq!(state.target_height, 432);
-        assert_eq!(state.img_height, 480);
-        assert_eq!(state.img_width, 640);
+        assert_eq!(state.ex_height, 100);
+        assert_eq!(state.target_height, 648);
+        assert_eq!(state.img_height, 720);
+        assert_eq!(state.img_width, 1280);
+        assert_eq!(state.input_size, 640);
 
         // Test downscaling
         let _ = downscale(&mut state, channel_vision_mgmt_tx.clone());
         assert_eq!(state.ex_height, 100);
-        assert_eq!(state.target_height, 216);
-        assert_eq!(state.img_height, 240);
-        assert_eq!(state.img_width, 320);
+        assert_eq!(state.target_height, 648);
+        assert_eq!(state.input_size, 320);
     }
 }
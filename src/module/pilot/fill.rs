@@ -26,19 +26,25 @@
 // # General flow
 //
 // Start
+//   | (Edge lap, optional)
 //   | (CCW laps)
 // InvertPhase
 //   | (CW laps)
 // MissionComplete
+//
+// The edge lap goes along the pylon line slowly without shrinking the lap.
+// With lap counting enabled, the rest is set from the laps counted by the sequence of OCR ids.
 
 use std::sync::mpsc::Sender;
 
 use crate::module::{
-    device::motor::Motor,
     device::Roktrack,
     pilot::base,
-    pilot::{Phase, RoktrackState},
-    util::{conf::Config, init::RoktrackProperty},
+    pilot::{FillStage, Phase, RoktrackState},
+    util::{
+        conf::{Config, Lap},
+        init::RoktrackProperty,
+    },
     vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
     vision::{VisionMgmtCommand, VisualInfo},
 };
@@ -71,20 +77,27 @@ impl PilotHandler for Fill {
     ) {
         log::debug!("Start Fill Handle");
         // Assess and handle system safety
-        let system_risk = match assess_system_risk(state, device) {
-            Some(SystemRisk::StateOff) => Some(base::stop(device)),
-            Some(SystemRisk::HighTemp) => {
-                let res = base::stop(device);
-                device.speak("high_temp");
-                Some(res)
-            }
-            Some(SystemRisk::Bumped) => {
-                let res = base::escape(state, device);
-                device.speak("bumped");
-                Some(res)
-            }
-            None => None,
-        };
+        let system_risk =
+            match assess_system_risk(state, device, visual_info, property.conf.clone()) {
+                Some(SystemRisk::StateOff) => Some(base::stop(device)),
+                Some(SystemRisk::HighTemp) => {
+                    let res = base::stop(device);
+                    device.speak("high_temp");
+                    Some(res)
+                }
+                Some(SystemRisk::Bumped) => {
+                    let res = base::escape(state, device);
+                    device.speak("bumped");
+                    Some(res)
+                }
+                Some(SystemRisk::Stuck) => Some(base::recover_stuck(
+                    state,
+                    device,
+                    tx.clone(),
+                    property.clone(),
+                )),
+                None => None,
+            };
         if system_risk.is_some() {
             log::warn!("System Risk Exists. Continue.");
             return; // Risk exists, continue
@@ -93,14 +106,18 @@ impl PilotHandler for Fill {
         let mut detections = visual_info.detections.clone();
 
         // Assess and handle vision safety
-        let vision_risk = match assess_vision_risk(&mut detections, property.conf.clone()) {
-            Some(VisionRisk::PersonDetected) => {
-                let res = base::stop(device);
-                device.speak("person_detecting");
-                Some(res)
+        let vision_risk = match assess_vision_risk(state, &mut detections, property.conf.clone()) {
+            Some(VisionRisk::Person(response)) => {
+                match base::respond_person(state, device, Some(response)) {
+                    true => Some(Ok(())),
+                    false => None,
+                }
             }
             Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
-            None => None,
+            None => {
+                base::respond_person(state, device, None);
+                None
+            }
         };
         if vision_risk.is_some() {
             log::warn!("Vision Risk Exists. Continue.");
@@ -130,12 +147,25 @@ impl PilotHandler for Fill {
         };
 
         // Get the first detected marker or a default one
-        let marker = select_marker(property, state, detections, device, tx.clone());
+        let marker = select_marker(property.clone(), state, detections, device, tx.clone());
         state.marker_height = marker.h;
         log::info!("Marker Selected: {:?}", marker);
 
         // Turn on the work motor
-        device.inner.clone().lock().unwrap().work_motor.cw();
+        device.inner.clone().lock().unwrap().set_work(true);
+
+        // Slow down in the edge lap
+        if state.is_edge_pass() {
+            let binding = device.inner.clone();
+            let mut device_lock = binding.lock().unwrap();
+            let power = property.conf.edge.power;
+            if power < device_lock.drive_motor_left.power {
+                device_lock.drive_motor_left.power = power;
+            }
+            if power < device_lock.drive_motor_right.power {
+                device_lock.drive_motor_right.power = power;
+            }
+        }
 
         // Calculate constants based on marker and image height
         state.constant = base::calc_constant(state.constant, state.img_height, marker.h);
@@ -149,11 +179,13 @@ impl PilotHandler for Fill {
             Some(ActPhase::TurnMarkerInvisible) => base::reset_ex_height(state, device),
             Some(ActPhase::TurnMarkerFound) => base::set_new_target(state, device, marker),
             Some(ActPhase::InvertPhase) => base::invert_phase(state, device),
-            Some(ActPhase::MissionComplete) => base::mission_complete(state, device),
+            Some(ActPhase::MissionComplete) => {
+                base::mission_complete(state, device, property.clone())
+            }
             Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
             Some(ActPhase::Stand) => base::stand(state, tx),
             Some(ActPhase::StartTurn) => base::start_turn(state, device),
-            Some(ActPhase::ReachMarker) => base::reach_marker(state, device, marker),
+            Some(ActPhase::ReachMarker) => reach(state, device, marker, &property.conf),
             Some(ActPhase::Proceed) => base::proceed(state, device, marker, tx),
             None => Ok(()),
         };
@@ -161,6 +193,86 @@ impl PilotHandler for Fill {
     }
 }
 
+/// Reach a marker, then end the edge lap or set the rest from the laps.
+///
+/// The edge lap ends at a completed lap, or when as many pylons as the edge markers
+/// have been reached in this mission.
+///
+/// # Arguments
+///
+/// * `state` - A mutable reference to the `RoktrackState` representing the current state of the pilot.
+/// * `device` - A mutable reference to the `Roktrack` device.
+/// * `marker` - The `Detection` structure representing the reached marker.
+/// * `conf` - The configuration of the app.
+///
+/// # Returns
+///
+/// A `Result` indicating success or an error.
+fn reach(
+    state: &mut RoktrackState,
+    device: &mut Roktrack,
+    marker: Detection,
+    conf: &Config,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let laps = state.mission.laps;
+    let res = base::reach_marker(state, device, marker);
+    if state.is_edge_pass() {
+        let markers = edge_markers(state, conf);
+        if laps < state.mission.laps || (0 < markers && markers <= state.mission.pylons_reached()) {
+            finish_edge_pass(state, device, conf.clone());
+        }
+    }
+    if laps < state.mission.laps && conf.lap.enable {
+        sync_rest(state, &conf.lap);
+    }
+    res
+}
+
+/// Number of pylons to reach to end the edge lap, 0 if unknown.
+///
+/// Without the config and the OCR ids, the lap is only detected by dead reckoning,
+/// so the pylons mapped at the site in the earlier runs are used as well.
+fn edge_markers(state: &RoktrackState, conf: &Config) -> u32 {
+    match (conf.edge.markers, conf.vision.ocr) {
+        (0, false) => state.map.known() as u32,
+        (markers, _) => markers,
+    }
+}
+
+/// Switch from the edge lap to the shrinking laps.
+///
+/// The drive motor power limited in the edge lap is restored.
+fn finish_edge_pass(state: &mut RoktrackState, device: &mut Roktrack, conf: Config) {
+    log::info!(
+        "Edge Lap Completed. reached: {}",
+        state.mission.pylons_reached()
+    );
+    state.fill_stage = FillStage::Spiral;
+    let binding = device.inner.clone();
+    let mut device_lock = binding.lock().unwrap();
+    device_lock.drive_motor_left.power = conf.pwm.pwm_power_left;
+    device_lock.drive_motor_right.power = conf.pwm.pwm_power_right;
+    // The shrinking laps are counted from here
+    state.lap_counter.restart();
+}
+
+/// Set the rest from the counted laps.
+///
+/// The shrink per pylon is derived from the number of pylons observed in a lap,
+/// so the completion does not depend on the constant.
+fn sync_rest(state: &mut RoktrackState, conf: &Lap) {
+    if let Some(pylons) = state.lap_counter.pylons() {
+        state.constant = conf.shrink / pylons as f32;
+        state.rest = 1.0 - conf.shrink * state.lap_counter.laps as f32;
+        log::info!(
+            "Rest Synchronized. laps: {}, pylons: {}, rest: {}",
+            state.lap_counter.laps,
+            pylons,
+            state.rest
+        );
+    }
+}
+
 /// System Risks
 ///
 #[derive(Debug, Clone)]
@@ -168,16 +280,24 @@ enum SystemRisk {
     StateOff,
     HighTemp,
     Bumped,
+    Stuck,
 }
 /// Identify system-related risks
 ///
-fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
+fn assess_system_risk(
+    state: &mut RoktrackState,
+    device: &Roktrack,
+    visual_info: &VisualInfo,
+    conf: Config,
+) -> Option<SystemRisk> {
     if !state.state {
         Some(SystemRisk::StateOff)
     } else if state.pi_temp > 70.0 {
         Some(SystemRisk::HighTemp)
     } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
         Some(SystemRisk::Bumped)
+    } else if base::assess_stuck(state, device, visual_info, conf) {
+        Some(SystemRisk::Stuck)
     } else {
         None
     }
@@ -186,20 +306,19 @@ fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<System
 ///
 #[derive(Debug, Clone)]
 enum VisionRisk {
-    PersonDetected,
+    Person(base::PersonResponse),
     RoktrackDetected,
 }
 /// Identify vision-related risks
 ///
-fn assess_vision_risk(dets: &mut [Detection], conf: Config) -> Option<VisionRisk> {
-    if !RoktrackClasses::filter(
-        dets,
-        RoktrackClasses::PERSON.to_u32(),
-        conf.detectthreshold.person,
-    )
-    .is_empty()
-    {
-        Some(VisionRisk::PersonDetected)
+fn assess_vision_risk(
+    state: &mut RoktrackState,
+    dets: &mut [Detection],
+    conf: Config,
+) -> Option<VisionRisk> {
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+    if let Some(response) = base::assess_person(state, dets, conf.clone(), now) {
+        Some(VisionRisk::Person(response))
     } else if !RoktrackClasses::filter(
         dets,
         RoktrackClasses::ROKTRACK.to_u32(),
@@ -260,3 +379,143 @@ fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhas
         Some(ActPhase::Proceed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::device::Roktrack;
+    use crate::module::map::{odometry::Pose, FieldMap};
+    use crate::module::vision::detector::{fake::ScriptedDetector, Detector};
+    use std::sync::mpsc;
+
+    #[test]
+    fn edge_markers_test() {
+        let dir = "/tmp/roktracktest/";
+        std::fs::create_dir_all(dir).unwrap();
+        let mut conf = crate::module::util::conf::toml::load(dir).unwrap();
+        conf.vision.ocr = false;
+        conf.edge.markers = 0;
+        let mut state = RoktrackState::new(conf.clone());
+        // Nothing mapped at the site yet
+        state.map = FieldMap::new("test_edge");
+        assert_eq!(edge_markers(&state, &conf), 0);
+        // Four pylons mapped in the earlier run
+        for i in 0..4 {
+            let pose = Pose {
+                x: 10.0 * i as f32,
+                ..Pose::default()
+            };
+            state.map.record(None, &pose, 1.0);
+        }
+        state.map.save(dir).unwrap();
+        state.map = FieldMap::load_or_new(dir, "test_edge");
+        assert_eq!(edge_markers(&state, &conf), 4);
+        // The pylons of this run don't count
+        state.map.record(None, &Pose::default(), 50.0);
+        assert_eq!(edge_markers(&state, &conf), 4);
+        // The laps are counted by the OCR ids
+        conf.vision.ocr = true;
+        assert_eq!(edge_markers(&state, &conf), 0);
+        // The config comes first
+        conf.edge.markers = 6;
+        assert_eq!(edge_markers(&state, &conf), 6);
+    }
+
+    #[test]
+    fn edge_lap_test() {
+        let dir = "/tmp/roktracktest/";
+        std::fs::create_dir_all(dir).unwrap();
+        let mut conf = crate::module::util::conf::toml::load(dir).unwrap();
+        conf.vision.ocr = false;
+        conf.edge.markers = 0;
+        conf.lap.enable = false;
+        let mut state = RoktrackState::new(conf.clone());
+        let mut device = Roktrack::new(conf.clone());
+        let pose = |i: u32| Pose {
+            x: 10.0 * i as f32,
+            ..Pose::default()
+        };
+        // Four pylons visited twice in the earlier runs
+        state.map = FieldMap::new("test_edge_lap");
+        for _ in 0..2 {
+            for i in 0..4 {
+                state.map.record(None, &pose(i), 1.0);
+            }
+        }
+        state.map.save(dir).unwrap();
+        state.map = FieldMap::load_or_new(dir, "test_edge_lap");
+        assert!(state.map.pylons.iter().all(|p| 1 < p.visits));
+        state.fill_stage = FillStage::Edge;
+        let marker = Detection {
+            h: state.target_height as u32,
+            distance: Some(1.0),
+            ..Default::default()
+        };
+        // The edge lap goes on until all the pylons have been reached in this mission
+        for i in 0..4 {
+            assert!(state.is_edge_pass());
+            state.odometry.pose = pose(i);
+            reach(&mut state, &mut device, marker.clone(), &conf).unwrap();
+            assert_eq!(state.mission.laps, 0);
+        }
+        assert!(!state.is_edge_pass());
+        // Back at the first pylon
+        state.odometry.pose = pose(0);
+        reach(&mut state, &mut device, marker, &conf).unwrap();
+        assert_eq!(state.mission.laps, 1);
+        base::stop(&mut device).unwrap();
+    }
+
+    #[test]
+    fn lap_count_test() {
+        let mut property = crate::module::util::init::resource::init();
+        property.conf.vision.ocr = true;
+        property.conf.lap.enable = true;
+        property.conf.edge.enable = false;
+        let mut state = RoktrackState::new(property.conf.clone());
+        state.fill_stage = FillStage::Spiral;
+        let mut device = Roktrack::new(property.conf.clone());
+        let (tx, _rx) = mpsc::channel();
+        let mut fill = Fill::new();
+        let pylon = |id: u8, h: u32| Detection {
+            cls: RoktrackClasses::PYLON.to_u32(),
+            prob: 0.9,
+            xc: state.img_width as f32 / 2.0,
+            h,
+            ids: vec![id],
+            ..Default::default()
+        };
+        let (far, near) = (
+            state.reach_height as u32 / 4,
+            state.reach_height as u32 + 10,
+        );
+        // Reach each pylon, then find the next one while turning
+        let ids = [4, 7, 2, 9, 4];
+        let mut frames = vec![vec![pylon(ids[0], near)]];
+        for id in &ids[1..] {
+            frames.push(vec![pylon(*id, far)]);
+            frames.push(vec![pylon(*id, near)]);
+        }
+        let detector = ScriptedDetector::new(frames.clone()).with_ocr();
+        for _ in 0..frames.len() {
+            let dets = detector.infer("", "").unwrap();
+            let mut visual_info = VisualInfo {
+                detections: detector.ocr("", dets, property.clone()).unwrap(),
+                ..VisualInfo::default()
+            };
+            fill.handle(
+                &mut state,
+                &mut device,
+                &mut visual_info,
+                tx.clone(),
+                property.clone(),
+            );
+        }
+        // Back at the first pylon, the sequence of all the pylons is learned
+        assert_eq!(state.marker_id, None);
+        assert_eq!(state.lap_counter.sequence, vec![4, 7, 2, 9]);
+        assert_eq!(state.mission.laps, 1);
+        assert_eq!(state.rest, 1.0 - property.conf.lap.shrink);
+        base::stop(&mut device).unwrap();
+    }
+}
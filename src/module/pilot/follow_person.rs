@@ -5,20 +5,80 @@ use std::sync::mpsc::Sender;
 
 use super::PilotHandler;
 use crate::module::{
+    com::ChildMsg,
     device::Chassis,
     device::Roktrack,
     pilot::base,
     pilot::RoktrackState,
-    util::init::RoktrackProperty,
+    util::{
+        conf::{Config, Follow},
+        init::RoktrackProperty,
+    },
     vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
     vision::{VisionMgmtCommand, VisualInfo},
 };
 
-pub struct FollowPerson {}
+pub struct FollowPerson {
+    bearing: f32, // Smoothed horizontal position of the person (-1.0: left edge -> 1.0: right edge)
+    last_seen_time: Option<u64>, // Milliseconds, None until the mode starts
+    last_seen_side: Side, // The side where the person was last seen
+}
 
 impl FollowPerson {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            bearing: 0.0,
+            last_seen_time: None,
+            last_seen_side: Side::Left,
+        }
+    }
+
+    /// Decide the next maneuver from the selected person.
+    ///
+    /// The person's bbox height relative to the image height is used as a proxy for the distance.
+    /// Between `near` and `far` the mower holds its position, closer than `near` it backs off
+    /// and further than `far` it approaches. The bearing is smoothed to avoid zigzagging.
+    fn decide(
+        &mut self,
+        person: Option<&Detection>,
+        img_width: u32,
+        img_height: u32,
+        now: u64,
+        conf: &Follow,
+    ) -> FollowAction {
+        let person = match person {
+            Some(person) => person,
+            None => {
+                // The timeout runs from the start of the mode until someone is seen
+                let last_seen_time = *self.last_seen_time.get_or_insert(now);
+                return if last_seen_time + conf.lost_timeout < now {
+                    FollowAction::TimedOut
+                } else {
+                    // Search in the direction the person was last seen
+                    FollowAction::Search(self.last_seen_side.clone())
+                };
+            }
+        };
+        // Update the smoothed bearing
+        let half_width = img_width as f32 / 2.0;
+        let raw = (person.xc - half_width) / half_width;
+        self.bearing = conf.smoothing * self.bearing + (1.0 - conf.smoothing) * raw;
+        self.last_seen_time = Some(now);
+        self.last_seen_side = if raw < 0.0 { Side::Left } else { Side::Right };
+
+        let ratio = person.h as f32 / img_height as f32;
+        log::debug!("Follow. ratio: {}, bearing: {}", ratio, self.bearing);
+        if conf.near < ratio {
+            FollowAction::BackOff
+        } else if self.bearing < -conf.turn_threshold {
+            FollowAction::Turn(Side::Left)
+        } else if conf.turn_threshold < self.bearing {
+            FollowAction::Turn(Side::Right)
+        } else if conf.far <= ratio {
+            FollowAction::Hold
+        } else {
+            FollowAction::Approach
+        }
     }
 }
 
@@ -40,20 +100,31 @@ impl PilotHandler for FollowPerson {
     ) {
         log::debug!("Start FollowPerson Handle");
         // Assess and handle system safety
-        let system_risk = match assess_system_risk(state, device) {
-            Some(SystemRisk::StateOff) => Some(base::stop(device)),
-            Some(SystemRisk::HighTemp) => {
-                let res = base::stop(device);
-                device.speak("high_temp");
-                Some(res)
-            }
-            Some(SystemRisk::Bumped) => {
-                let res = base::escape(state, device);
-                device.speak("bumped");
-                Some(res)
-            }
-            None => None,
-        };
+        let system_risk =
+            match assess_system_risk(state, device, visual_info, property.conf.clone()) {
+                Some(SystemRisk::StateOff) => {
+                    // Restart the timeout when the mode is turned on again
+                    self.last_seen_time = None;
+                    Some(base::stop(device))
+                }
+                Some(SystemRisk::HighTemp) => {
+                    let res = base::stop(device);
+                    device.speak("high_temp");
+                    Some(res)
+                }
+                Some(SystemRisk::Bumped) => {
+                    let res = base::escape(state, device);
+                    device.speak("bumped");
+                    Some(res)
+                }
+                Some(SystemRisk::Stuck) => Some(base::recover_stuck(
+                    state,
+                    device,
+                    tx.clone(),
+                    property.clone(),
+                )),
+                None => None,
+            };
         if system_risk.is_some() {
             log::warn!("System Risk Exists. Continue.");
             return; // Risk exists, continue
@@ -69,44 +140,78 @@ impl PilotHandler for FollowPerson {
             return; // wait for next image
         }
 
-        // Sort markers based on the current phase
+        // Select the biggest person
         let detections = sort::big(&mut detections);
         let detections = RoktrackClasses::filter(
             &mut detections.clone(),
             (RoktrackClasses::PERSON).to_u32(),
             property.conf.detectthreshold.person,
         );
+        let person = detections.first();
+        state.marker_height = person.map(|p| p.h).unwrap_or(0);
+        log::info!("Person Selected: {:?}", person);
 
-        // Get the first detected marker or a default one
-        let marker = detections.first().cloned().unwrap_or_default();
-        state.marker_height = marker.h;
-        log::info!("Marker Selected: {:?}", marker);
-
-        let action = assess_situation(state, &marker);
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let action = self.decide(
+            person,
+            state.img_width,
+            state.img_height,
+            now,
+            &property.conf.follow,
+        );
         log::info!("Action is {:?}", action);
+        state.diff = -self.bearing;
 
-        // Handle the current phase
-        let _ = match action {
-            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
-            Some(ActPhase::TurnMarkerInvisible) => base::reset_ex_height(state, device),
-            Some(ActPhase::TurnMarkerFound) => base::set_new_target(state, device, marker),
-            Some(ActPhase::InvertPhase) => base::invert_phase(state, device),
-            Some(ActPhase::MissionComplete) => base::mission_complete(state, device),
-            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
-            Some(ActPhase::Stand) => base::stand(state, tx),
-            Some(ActPhase::StartTurn) => base::start_turn(state, device),
-            Some(ActPhase::ReachMarker) => {
-                log::info!("Reach Marker pausing.");
-                device.inner.lock().unwrap().pause();
-                Ok(())
+        let binding = device.inner.clone();
+        let mut device_lock = binding.lock().unwrap();
+        match action {
+            FollowAction::BackOff => device_lock.backward(300),
+            FollowAction::Hold => device_lock.pause(),
+            FollowAction::Turn(Side::Left) => device_lock.left(100),
+            FollowAction::Turn(Side::Right) => device_lock.right(100),
+            FollowAction::Approach => {
+                // Fine adjustment of the direction while moving
+                let val = (0.1 * self.bearing).abs() as f64;
+                if self.bearing < 0.0 {
+                    device_lock.adjust_power(-val, val);
+                } else {
+                    device_lock.adjust_power(val, -val);
+                }
+                device_lock.forward(0);
+            }
+            FollowAction::Search(Side::Left) => device_lock.left(300),
+            FollowAction::Search(Side::Right) => device_lock.right(300),
+            FollowAction::TimedOut => {
+                log::warn!("Person Lost. Halted!");
+                device_lock.stop();
+                state.state = false;
+                state.msg = ChildMsg::to_u8(ChildMsg::TargetLost);
+                tx.send(VisionMgmtCommand::Off).unwrap();
             }
-            Some(ActPhase::Proceed) => base::proceed(state, device, marker, tx),
-            None => Ok(()),
         };
         log::debug!("End FollowPerson Handle");
     }
 }
 
+/// Sides
+///
+#[derive(Debug, Clone, PartialEq)]
+enum Side {
+    Left,
+    Right,
+}
+/// Maneuvers for Follow Person Pilot
+///
+#[derive(Debug, Clone, PartialEq)]
+enum FollowAction {
+    BackOff,
+    Hold,
+    Turn(Side),
+    Approach,
+    Search(Side),
+    TimedOut,
+}
+
 /// System Risks
 ///
 #[derive(Debug, Clone)]
@@ -114,65 +219,72 @@ enum SystemRisk {
     StateOff,
     HighTemp,
     Bumped,
+    Stuck,
 }
 /// Identify system-related risks
 ///
-fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
+fn assess_system_risk(
+    state: &mut RoktrackState,
+    device: &Roktrack,
+    visual_info: &VisualInfo,
+    conf: Config,
+) -> Option<SystemRisk> {
     if !state.state {
         Some(SystemRisk::StateOff)
     } else if state.pi_temp > 70.0 {
         Some(SystemRisk::HighTemp)
     } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
         Some(SystemRisk::Bumped)
+    } else if base::assess_stuck(state, device, visual_info, conf) {
+        Some(SystemRisk::Stuck)
     } else {
         None
     }
 }
-/// Actions for Fill Drive Pilot
-///
-#[derive(Debug, Clone)]
-enum ActPhase {
-    TurnCountExceeded,
-    TurnMarkerInvisible,
-    TurnMarkerFound,
-    InvertPhase,
-    MissionComplete,
-    TurnKeep,
-    Stand,
-    StartTurn,
-    ReachMarker,
-    Proceed,
-}
-/// Function to assess the current situation and determine the appropriate action phase
-fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhase> {
-    if 10 <= state.turn_count {
-        Some(ActPhase::TurnCountExceeded)
-    } else if 0 < state.turn_count {
-        if marker.h == 0 {
-            Some(ActPhase::TurnMarkerInvisible)
-        } else if (marker.h as f32) < state.ex_height as f32 - state.img_height as f32 * 0.015 {
-            if state.rest < 0.0 {
-                match state.phase {
-                    super::Phase::CW => Some(ActPhase::MissionComplete),
-                    super::Phase::CCW => Some(ActPhase::InvertPhase),
-                }
-            } else {
-                Some(ActPhase::TurnMarkerFound)
-            }
-        } else {
-            Some(ActPhase::TurnKeep)
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn person(xc: f32, h: u32) -> Detection {
+        Detection {
+            xc,
+            h,
+            cls: RoktrackClasses::PERSON.to_u32(),
+            prob: 0.9,
+            ..Default::default()
         }
-    } else if marker.h == 0 {
-        if state.turn_count == -1 {
-            Some(ActPhase::Stand)
-        } else if state.turn_count == 0 {
-            Some(ActPhase::StartTurn)
-        } else {
-            None
+    }
+
+    #[test]
+    fn follow_decide_test() {
+        let conf = Follow::default();
+        let mut pilot = FollowPerson::new();
+        // Nobody in sight at the start, the timeout starts with the mode
+        let action = pilot.decide(None, 320, 240, 500, &conf);
+        assert_eq!(action, FollowAction::Search(Side::Left));
+        let action = pilot.decide(None, 320, 240, 500 + conf.lost_timeout + 1, &conf);
+        assert_eq!(action, FollowAction::TimedOut);
+        let mut pilot = FollowPerson::new();
+        // Far and straight ahead
+        let action = pilot.decide(Some(&person(160.0, 48)), 320, 240, 1000, &conf);
+        assert_eq!(action, FollowAction::Approach);
+        // In the distance band
+        let action = pilot.decide(Some(&person(160.0, 168)), 320, 240, 1100, &conf);
+        assert_eq!(action, FollowAction::Hold);
+        // Too close
+        let action = pilot.decide(Some(&person(160.0, 230)), 320, 240, 1200, &conf);
+        assert_eq!(action, FollowAction::BackOff);
+        // Moved to the right edge
+        let mut action = FollowAction::Hold;
+        for i in 0..5 {
+            action = pilot.decide(Some(&person(310.0, 48)), 320, 240, 1300 + i, &conf);
         }
-    } else if state.target_height <= marker.h as u16 {
-        Some(ActPhase::ReachMarker)
-    } else {
-        Some(ActPhase::Proceed)
+        assert_eq!(action, FollowAction::Turn(Side::Right));
+        // Lost on the right
+        let action = pilot.decide(None, 320, 240, 2000, &conf);
+        assert_eq!(action, FollowAction::Search(Side::Right));
+        let action = pilot.decide(None, 320, 240, 2000 + conf.lost_timeout, &conf);
+        assert_eq!(action, FollowAction::TimedOut);
     }
 }
@@ -4,7 +4,6 @@ use std::sync::mpsc::Sender;
 
 use super::PilotHandler;
 use crate::module::{
-    device::motor::Motor,
     device::Roktrack,
     pilot::base,
     pilot::{Phase, RoktrackState},
@@ -39,20 +38,27 @@ impl PilotHandler for OneWay {
     ) {
         log::debug!("Start OneWay Handle");
         // Assess and handle system safety
-        let system_risk = match assess_system_risk(state, device) {
-            Some(SystemRisk::StateOff) => Some(base::stop(device)),
-            Some(SystemRisk::HighTemp) => {
-                let res = base::stop(device);
-                device.speak("high_temp");
-                Some(res)
-            }
-            Some(SystemRisk::Bumped) => {
-                let res = base::escape(state, device);
-                device.speak("bumped");
-                Some(res)
-            }
-            None => None,
-        };
+        let system_risk =
+            match assess_system_risk(state, device, visual_info, property.conf.clone()) {
+                Some(SystemRisk::StateOff) => Some(base::stop(device)),
+                Some(SystemRisk::HighTemp) => {
+                    let res = base::stop(device);
+                    device.speak("high_temp");
+                    Some(res)
+                }
+                Some(SystemRisk::Bumped) => {
+                    let res = base::escape(state, device);
+                    device.speak("bumped");
+                    Some(res)
+                }
+                Some(SystemRisk::Stuck) => Some(base::recover_stuck(
+                    state,
+                    device,
+                    tx.clone(),
+                    property.clone(),
+                )),
+                None => None,
+            };
         if system_risk.is_some() {
             log::warn!("System Risk Exists. Continue.");
             return; // Risk exists, continue
@@ -70,14 +76,18 @@ impl PilotHandler for OneWay {
         }
 
         // Assess and handle vision safety
-        let vision_risk = match assess_vision_risk(&mut detections, property.conf.clone()) {
-            Some(VisionRisk::PersonDetected) => {
-                let res = base::stop(device);
-                device.speak("person_detecting");
-                Some(res)
+        let vision_risk = match assess_vision_risk(state, &mut detections, property.conf.clone()) {
+            Some(VisionRisk::Person(response)) => {
+                match base::respond_person(state, device, Some(response)) {
+                    true => Some(Ok(())),
+                    false => None,
+                }
             }
             Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
-            None => None,
+            None => {
+                base::respond_person(state, device, None);
+                None
+            }
         };
         if vision_risk.is_some() {
             log::warn!("Vision Risk Exists. Continue.");
@@ -99,6 +109,8 @@ impl PilotHandler for OneWay {
                 Phase::CW => sort::left(&mut detections),
             },
         };
+        // Stay on the locked pylon
+        let detections = base::lock_track(state, detections);
 
         // Get the first detected marker or a default one
         let marker = detections.first().cloned().unwrap_or_default();
@@ -106,7 +118,7 @@ impl PilotHandler for OneWay {
         log::info!("Marker Selected: {:?}", marker);
 
         // Turn on the work motor
-        device.inner.clone().lock().unwrap().work_motor.cw();
+        device.inner.clone().lock().unwrap().set_work(true);
 
         let action = assess_situation(state, &marker);
         log::info!("Action is {:?}", action);
@@ -134,16 +146,24 @@ enum SystemRisk {
     StateOff,
     HighTemp,
     Bumped,
+    Stuck,
 }
 /// Identify system-related risks
 ///
-fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
+fn assess_system_risk(
+    state: &mut RoktrackState,
+    device: &Roktrack,
+    visual_info: &VisualInfo,
+    conf: Config,
+) -> Option<SystemRisk> {
     if !state.state {
         Some(SystemRisk::StateOff)
     } else if state.pi_temp > 70.0 {
         Some(SystemRisk::HighTemp)
     } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
         Some(SystemRisk::Bumped)
+    } else if base::assess_stuck(state, device, visual_info, conf) {
+        Some(SystemRisk::Stuck)
     } else {
         None
     }
@@ -152,20 +172,19 @@ fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<System
 ///
 #[derive(Debug, Clone)]
 enum VisionRisk {
-    PersonDetected,
+    Person(base::PersonResponse),
     RoktrackDetected,
 }
 /// Identify vision-related risks
 ///
-fn assess_vision_risk(dets: &mut [Detection], conf: Config) -> Option<VisionRisk> {
-    if !RoktrackClasses::filter(
-        dets,
-        RoktrackClasses::PERSON.to_u32(),
-        conf.detectthreshold.person,
-    )
-    .is_empty()
-    {
-        Some(VisionRisk::PersonDetected)
+fn assess_vision_risk(
+    state: &mut RoktrackState,
+    dets: &mut [Detection],
+    conf: Config,
+) -> Option<VisionRisk> {
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+    if let Some(response) = base::assess_person(state, dets, conf.clone(), now) {
+        Some(VisionRisk::Person(response))
     } else if !RoktrackClasses::filter(
         dets,
         RoktrackClasses::ROKTRACK.to_u32(),
@@ -0,0 +1,479 @@
+//! Patrol Pilot
+//!
+//! Drives the outer pylon loop slowly with the work motor off,
+//! and scans 360 degrees for people and animals at each pylon.
+
+// # Normal flow
+//
+// Drive (Proceed * n -> ReachMarker)
+//    |
+// Scan Person * scan_steps  <- Turn on the spot and look for people at each step.
+//    |
+// Scan Animal * scan_steps  <- Switch to the animal session and do it again.
+//    |
+// Drive (TurnKeep -> TurnMarkerFound -> Proceed * n)
+
+use std::sync::mpsc::Sender;
+
+use super::PilotHandler;
+use crate::module::{
+    com::ChildMsg,
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::{Phase, RoktrackState},
+    util::{common::send_line_notify_with_image, conf::Config, init::RoktrackProperty},
+    vision::detector::{sort, AnimalClasses, Detection, FilterClass, RoktrackClasses},
+    vision::{VisionMgmtCommand, VisualInfo},
+};
+
+pub struct Patrol {
+    stage: Stage,
+    last_person_notified: u64, // Milliseconds
+    last_animal_notified: u64, // Milliseconds
+}
+
+impl Patrol {
+    pub fn new() -> Self {
+        Self {
+            stage: Stage::Drive,
+            last_person_notified: 0,
+            last_animal_notified: 0,
+        }
+    }
+
+    /// Scan around the pylon step by step.
+    ///
+    /// At each step, the latest static image is checked for the current target,
+    /// then the mower turns by 360 / scan_steps degrees.
+    fn scan(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        visual_info: &VisualInfo,
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        let (step, target, since) = match &self.stage {
+            Stage::Scan {
+                step,
+                target,
+                since,
+            } => (*step, target.clone(), *since),
+            Stage::Drive => return,
+        };
+        // Wait for the turn to finish and a new image to be taken
+        if device.inner.clone().lock().unwrap().is_turning()
+            || visual_info.shooting_start_time < since
+        {
+            log::debug!("Waiting for Static Image.");
+            return;
+        }
+
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let conf = property.conf.clone();
+        match target {
+            ScanTarget::Person => {
+                let persons = RoktrackClasses::filter(
+                    &mut visual_info.detections.clone(),
+                    RoktrackClasses::PERSON.to_u32(),
+                    conf.detectthreshold.person,
+                );
+                if !persons.is_empty() {
+                    log::warn!("Person Detected while Patrolling!!");
+                    device.speak("person_detecting_warn");
+                    state.msg = ChildMsg::to_u8(ChildMsg::PersonFoundWarn);
+                    if self.last_person_notified + conf.patrol.notify_interval < now {
+                        self.last_person_notified = now;
+                        let msg = format!("Person detected at {}.", location(state));
+                        let _ = send_line_notify_with_image(
+                            &msg,
+                            &property.path.img.last,
+                            conf.clone(),
+                        );
+                    }
+                }
+            }
+            ScanTarget::Animal => {
+                let animals = filter_animal(&visual_info.detections, conf.clone());
+                if let Some(animal) = animals.first() {
+                    log::warn!("Animal Detected while Patrolling!!");
+                    device.speak("animal_detecting");
+                    state.msg = ChildMsg::to_u8(ChildMsg::AnimalFound);
+                    if self.last_animal_notified + conf.patrol.notify_interval < now {
+                        self.last_animal_notified = now;
+                        let msg = format!(
+                            "{} detected at {}.",
+                            AnimalClasses::from_u32(animal.cls)
+                                .map_or(String::from("Animal"), |cls| format!("{:?}", cls)),
+                            location(state)
+                        );
+                        let _ = send_line_notify_with_image(
+                            &msg,
+                            &property.path.img.last,
+                            conf.clone(),
+                        );
+                    }
+                }
+            }
+        }
+
+        // Next step
+        let step = step + 1;
+        if step < conf.patrol.scan_steps {
+            let binding = device.inner.clone();
+            let mut device_lock = binding.lock().unwrap();
+            match state.phase {
+                Phase::CCW => device_lock.left(scan_turn_time(&conf)),
+                Phase::CW => device_lock.right(scan_turn_time(&conf)),
+            };
+            self.stage = Stage::Scan {
+                step,
+                target,
+                since: device_lock.target_time + 300,
+            };
+        } else if target == ScanTarget::Person && conf.patrol.animal {
+            log::info!("Person Scan Completed. Start Animal Scan.");
+            tx.send(VisionMgmtCommand::SwitchSessionAnimal).unwrap();
+            self.stage = Stage::Scan {
+                step: 0,
+                target: ScanTarget::Animal,
+                since: now,
+            };
+        } else {
+            log::info!("Scan Completed. Resume Patrol.");
+            if target == ScanTarget::Animal {
+                switch_pylon_session(tx, conf);
+            }
+            self.stage = Stage::Drive;
+        }
+    }
+}
+
+impl Default for Patrol {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Patrol {
+    /// Function called from a thread to handle the Patrol Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        visual_info: &mut VisualInfo,
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Patrol Handle");
+        // Assess and handle system safety
+        let system_risk =
+            match assess_system_risk(state, device, visual_info, property.conf.clone()) {
+                Some(SystemRisk::StateOff) => Some(base::stop(device)),
+                Some(SystemRisk::HighTemp) => {
+                    let res = base::stop(device);
+                    device.speak("high_temp");
+                    Some(res)
+                }
+                Some(SystemRisk::Bumped) => {
+                    let res = base::escape(state, device);
+                    device.speak("bumped");
+                    Some(res)
+                }
+                Some(SystemRisk::Stuck) => Some(base::recover_stuck(
+                    state,
+                    device,
+                    tx.clone(),
+                    property.clone(),
+                )),
+                None => None,
+            };
+        if system_risk.is_some() {
+            log::warn!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Keep the work motor off and drive slowly
+        {
+            let binding = device.inner.clone();
+            let mut device_lock = binding.lock().unwrap();
+            device_lock.set_work(false);
+            let power = property.conf.patrol.power;
+            if power < device_lock.drive_motor_left.power {
+                device_lock.drive_motor_left.power = power;
+            }
+            if power < device_lock.drive_motor_right.power {
+                device_lock.drive_motor_right.power = power;
+            }
+        }
+
+        if let Stage::Scan { .. } = self.stage {
+            self.scan(state, device, visual_info, tx, property);
+            return;
+        }
+
+        let mut detections = visual_info.detections.clone();
+
+        // Assess and handle vision safety
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let vision_risk = match assess_vision_risk(state, &mut detections, property.conf.clone()) {
+            Some(VisionRisk::Person(response)) => {
+                let close = response == base::PersonResponse::Stop;
+                match base::respond_person(state, device, Some(response)) {
+                    true => {
+                        if close
+                            && self.last_person_notified + property.conf.patrol.notify_interval
+                                < now
+                        {
+                            self.last_person_notified = now;
+                            let msg = format!("Person detected near {}.", location(state));
+                            let _ = send_line_notify_with_image(
+                                &msg,
+                                &property.path.img.last,
+                                property.conf.clone(),
+                            );
+                        }
+                        Some(Ok(()))
+                    }
+                    false => None,
+                }
+            }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
+            None => {
+                base::respond_person(state, device, None);
+                None
+            }
+        };
+        if vision_risk.is_some() {
+            log::warn!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Skip during turning(Images taken while turning are blurred.)
+        if device.inner.clone().lock().unwrap().is_turning()
+            && visual_info.shooting_start_time
+                < device.inner.clone().lock().unwrap().target_time + 300
+        {
+            log::debug!("Waiting for Static Image.");
+            return; // wait for next image
+        }
+
+        // Filter Only Marker
+        detections = RoktrackClasses::filter(
+            &mut detections,
+            RoktrackClasses::PYLON.to_u32(),
+            property.conf.detectthreshold.pylon,
+        );
+
+        // Sort markers based on the current phase
+        let detections = match state.phase {
+            Phase::CCW => sort::right(&mut detections),
+            Phase::CW => sort::left(&mut detections),
+        };
+
+        // Get the first detected marker or a default one
+        let marker = base::select_marker(property.clone(), state, detections, device, tx.clone());
+        state.marker_height = marker.h;
+        log::info!("Marker Selected: {:?}", marker);
+
+        // Keep going around the outermost lap
+        state.constant = 0.0;
+
+        let action = assess_situation(state, &marker);
+        log::info!("Action is {:?}", action);
+
+        // Handle the current phase
+        let _ = match action {
+            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
+            Some(ActPhase::TurnMarkerInvisible) => base::reset_ex_height(state, device),
+            Some(ActPhase::TurnMarkerFound) => base::set_new_target(state, device, marker),
+            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
+            Some(ActPhase::Stand) => base::stand(state, tx),
+            Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::ReachMarker) => {
+                let res = base::reach_marker(state, device, marker);
+                // Stop on the spot and look around before heading to the next pylon
+                device.inner.clone().lock().unwrap().pause();
+                log::info!("Start Scan at Pylon.");
+                self.stage = Stage::Scan {
+                    step: 0,
+                    target: ScanTarget::Person,
+                    since: chrono::Utc::now().timestamp_millis() as u64,
+                };
+                res
+            }
+            Some(ActPhase::Proceed) => base::proceed(state, device, marker, tx),
+            None => Ok(()),
+        };
+        log::debug!("End Patrol Handle");
+    }
+}
+
+/// Stages of the Patrol Pilot
+///
+#[derive(Debug, Clone, PartialEq)]
+enum Stage {
+    Drive,
+    Scan {
+        step: u8,           // Number of steps done
+        target: ScanTarget, // What to look for
+        since: u64,         // Only images taken after this time are checked (milliseconds)
+    },
+}
+/// Targets of the scan
+///
+#[derive(Debug, Clone, PartialEq)]
+enum ScanTarget {
+    Person,
+    Animal,
+}
+
+/// Milliseconds of a turn for a scan step.
+///
+/// The turning speed for dead reckoning is used to divide 360 degrees into `scan_steps`.
+fn scan_turn_time(conf: &Config) -> u64 {
+    let steps = conf.patrol.scan_steps.max(1) as f32;
+    (360.0 / steps / conf.map.turn_speed * 1000.0) as u64
+}
+
+/// Detections from the animal session above the threshold.
+fn filter_animal(detections: &[Detection], conf: Config) -> Vec<Detection> {
+    detections
+        .iter()
+        .filter(|det| conf.detectthreshold.animal <= det.prob)
+        .cloned()
+        .collect()
+}
+
+/// Human readable location for notifications.
+fn location(state: &RoktrackState) -> String {
+    match state.marker_id {
+        Some(id) => format!("pylon #{}", id),
+        None => format!(
+            "({:.1}, {:.1})",
+            state.odometry.pose.x, state.odometry.pose.y
+        ),
+    }
+}
+
+/// Return to the pylon session after the animal scan.
+fn switch_pylon_session(tx: Sender<VisionMgmtCommand>, conf: Config) {
+    match conf.vision.ocr {
+        true => tx.send(VisionMgmtCommand::SwitchSessionPylonOcr).unwrap(),
+        false => tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap(),
+    }
+}
+
+/// System Risks
+///
+#[derive(Debug, Clone)]
+enum SystemRisk {
+    StateOff,
+    HighTemp,
+    Bumped,
+    Stuck,
+}
+/// Identify system-related risks
+///
+fn assess_system_risk(
+    state: &mut RoktrackState,
+    device: &Roktrack,
+    visual_info: &VisualInfo,
+    conf: Config,
+) -> Option<SystemRisk> {
+    if !state.state {
+        Some(SystemRisk::StateOff)
+    } else if state.pi_temp > 70.0 {
+        Some(SystemRisk::HighTemp)
+    } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
+        Some(SystemRisk::Bumped)
+    } else if base::assess_stuck(state, device, visual_info, conf) {
+        Some(SystemRisk::Stuck)
+    } else {
+        None
+    }
+}
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    Person(base::PersonResponse),
+    RoktrackDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(
+    state: &mut RoktrackState,
+    dets: &mut [Detection],
+    conf: Config,
+) -> Option<VisionRisk> {
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+    if let Some(response) = base::assess_person(state, dets, conf.clone(), now) {
+        Some(VisionRisk::Person(response))
+    } else if !RoktrackClasses::filter(
+        dets,
+        RoktrackClasses::ROKTRACK.to_u32(),
+        conf.detectthreshold.roktrack,
+    )
+    .is_empty()
+    {
+        Some(VisionRisk::RoktrackDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Patrol Pilot
+///
+#[derive(Debug, Clone)]
+enum ActPhase {
+    TurnCountExceeded,
+    TurnMarkerInvisible,
+    TurnMarkerFound,
+    TurnKeep,
+    Stand,
+    StartTurn,
+    ReachMarker,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhase> {
+    if 10 <= state.turn_count {
+        Some(ActPhase::TurnCountExceeded)
+    } else if 0 < state.turn_count {
+        if marker.h == 0 {
+            Some(ActPhase::TurnMarkerInvisible)
+        } else if (marker.h as f32) < state.ex_height as f32 - state.img_height as f32 * 0.015 {
+            Some(ActPhase::TurnMarkerFound)
+        } else {
+            Some(ActPhase::TurnKeep)
+        }
+    } else if marker.h == 0 {
+        if state.turn_count == -1 {
+            Some(ActPhase::Stand)
+        } else if state.turn_count == 0 {
+            Some(ActPhase::StartTurn)
+        } else {
+            None
+        }
+    } else if state.target_height <= marker.h as u16 {
+        Some(ActPhase::ReachMarker)
+    } else {
+        Some(ActPhase::Proceed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_turn_time_test() {
+        std::fs::create_dir_all("/tmp/roktracktest/").unwrap();
+        let mut conf = crate::module::util::conf::toml::load("/tmp/roktracktest/").unwrap();
+        conf.map.turn_speed = 60.0;
+        conf.patrol.scan_steps = 8;
+        assert_eq!(scan_turn_time(&conf), 750);
+        conf.patrol.scan_steps = 0;
+        assert_eq!(scan_turn_time(&conf), 6000);
+    }
+}
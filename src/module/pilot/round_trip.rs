@@ -1,13 +1,18 @@
-//! Roundtrip Pilot between marker and person.
+//! Roundtrip Pilot between two endpoints.
+//!
+//! Each endpoint is a class with an optional OCR id, e.g. from pylon #3 to pylon #7.
 
 use std::sync::mpsc::Sender;
 
 use super::PilotHandler;
 use crate::module::{
-    device::Roktrack,
+    device::{Chassis, Roktrack},
     pilot::base,
     pilot::RoktrackState,
-    util::init::RoktrackProperty,
+    util::{
+        conf::{Config, Endpoint},
+        init::RoktrackProperty,
+    },
     vision::VisionMgmtCommand,
     vision::{
         detector::{sort, Detection, FilterClass, RoktrackClasses},
@@ -16,13 +21,17 @@ use crate::module::{
 };
 
 pub struct RoundTrip {
-    target_object: RoundTripObject,
+    target: RoundTripEnd, // The endpoint currently heading to
+    trips: u32,           // Completed round trips
+    dwell_until: u64,     // Stay at the endpoint until this time (milliseconds)
 }
 
 impl RoundTrip {
     pub fn new() -> Self {
         Self {
-            target_object: RoundTripObject::Marker,
+            target: RoundTripEnd::Start,
+            trips: 0,
+            dwell_until: 0,
         }
     }
 }
@@ -34,6 +43,11 @@ impl Default for RoundTrip {
 }
 
 impl PilotHandler for RoundTrip {
+    /// Start the round trips over from the start endpoint.
+    fn reset(&mut self, _state: &mut RoktrackState) {
+        *self = Self::new();
+    }
+
     /// Function called from a thread to handle the OneWay Drive Pilot logic
     fn handle(
         &mut self,
@@ -45,20 +59,27 @@ impl PilotHandler for RoundTrip {
     ) {
         log::debug!("Start RoundTrip Handle");
         // Assess and handle system safety
-        let system_risk = match assess_system_risk(state, device) {
-            Some(SystemRisk::StateOff) => Some(base::stop(device)),
-            Some(SystemRisk::HighTemp) => {
-                let res = base::stop(device);
-                device.speak("high_temp");
-                Some(res)
-            }
-            Some(SystemRisk::Bumped) => {
-                let res = base::escape(state, device);
-                device.speak("bumped");
-                Some(res)
-            }
-            None => None,
-        };
+        let system_risk =
+            match assess_system_risk(state, device, visual_info, property.conf.clone()) {
+                Some(SystemRisk::StateOff) => Some(base::stop(device)),
+                Some(SystemRisk::HighTemp) => {
+                    let res = base::stop(device);
+                    device.speak("high_temp");
+                    Some(res)
+                }
+                Some(SystemRisk::Bumped) => {
+                    let res = base::escape(state, device);
+                    device.speak("bumped");
+                    Some(res)
+                }
+                Some(SystemRisk::Stuck) => Some(base::recover_stuck(
+                    state,
+                    device,
+                    tx.clone(),
+                    property.clone(),
+                )),
+                None => None,
+            };
         if system_risk.is_some() {
             log::warn!("System Risk Exists. Continue.");
             return; // Risk exists, continue
@@ -75,20 +96,21 @@ impl PilotHandler for RoundTrip {
             return; // wait for next image
         }
 
-        // Sort markers based on the current target object
-        let detections = sort::big(&mut detections);
-        let detections = match self.target_object {
-            RoundTripObject::Marker => RoktrackClasses::filter(
-                &mut detections.clone(),
-                (RoktrackClasses::PYLON).to_u32(),
-                property.conf.detectthreshold.pylon,
-            ),
-            RoundTripObject::Person => RoktrackClasses::filter(
-                &mut detections.clone(),
-                (RoktrackClasses::PERSON).to_u32(),
-                property.conf.detectthreshold.person,
-            ),
+        // Stay at the endpoint for a while
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        if now < self.dwell_until {
+            log::debug!("Dwelling. rest_time: {}", self.dwell_until - now);
+            return;
+        }
+
+        // Filter the current endpoint and sort by size
+        let endpoint = match self.target {
+            RoundTripEnd::Start => property.conf.round_trip.start.clone(),
+            RoundTripEnd::End => property.conf.round_trip.end.clone(),
         };
+        let detections = sort::big(&mut detections);
+        let detections = filter_endpoint(&detections, &endpoint, property.conf.clone());
+        let detections = base::lock_track(state, detections);
 
         // Get the first detected marker or a default one
         let marker = detections.first().cloned().unwrap_or_default();
@@ -107,17 +129,32 @@ impl PilotHandler for RoundTrip {
             Some(ActPhase::Stand) => base::stand(state, tx),
             Some(ActPhase::StartTurn) => base::start_turn(state, device),
             Some(ActPhase::ReachMarker) => {
-                self.target_object = match self.target_object {
-                    RoundTripObject::Marker => {
-                        log::info!("Target Object Switch. Marker -> Person");
-                        RoundTripObject::Person
+                let res = base::reach_marker(state, device, marker);
+                // Stay here before heading to the other endpoint
+                device.inner.lock().unwrap().pause();
+                self.dwell_until = now + property.conf.round_trip.dwell;
+                self.target = match self.target {
+                    RoundTripEnd::Start => {
+                        log::info!("Target Endpoint Switch. Start -> End");
+                        RoundTripEnd::End
                     }
-                    RoundTripObject::Person => {
-                        log::info!("Target Object Switch. Person -> Marker");
-                        RoundTripObject::Marker
+                    RoundTripEnd::End => {
+                        self.trips += 1;
+                        log::info!(
+                            "Target Endpoint Switch. End -> Start. trips: {}",
+                            self.trips
+                        );
+                        RoundTripEnd::Start
                     }
                 };
-                base::reach_marker(state, device, marker)
+                let trips = property.conf.round_trip.trips;
+                if 0 < trips && trips <= self.trips {
+                    // The next start counts the trips from zero
+                    self.reset(state);
+                    base::mission_complete(state, device, property.clone())
+                } else {
+                    res
+                }
             }
             Some(ActPhase::Proceed) => base::proceed(state, device, marker, tx),
             None => Ok(()),
@@ -126,21 +163,36 @@ impl PilotHandler for RoundTrip {
     }
 }
 
-//// Target Object
-#[derive(Debug, Clone)]
-pub enum RoundTripObject {
-    Marker,
-    Person,
+/// Endpoints of the round trip
+#[derive(Debug, Clone, PartialEq)]
+enum RoundTripEnd {
+    Start,
+    End,
 }
 
-impl RoundTripObject {
-    /// Convert RoundTripObject to RoktrackClasses
-    pub fn to_cls(target: RoundTripObject) -> RoktrackClasses {
-        match target {
-            RoundTripObject::Marker => RoktrackClasses::PYLON,
-            RoundTripObject::Person => RoktrackClasses::PERSON,
+/// Filter detections matching the endpoint.
+///
+/// The class must match, and if the endpoint has an id, the OCR ids must contain it.
+fn filter_endpoint(detections: &[Detection], endpoint: &Endpoint, conf: Config) -> Vec<Detection> {
+    let cls = match RoktrackClasses::from_name(&endpoint.class) {
+        Some(cls) => cls,
+        None => {
+            log::error!("Unknown Endpoint Class: {}", endpoint.class);
+            return vec![];
         }
-    }
+    };
+    let threshold = match cls {
+        RoktrackClasses::PYLON => conf.detectthreshold.pylon,
+        RoktrackClasses::PERSON => conf.detectthreshold.person,
+        RoktrackClasses::ROKTRACK => conf.detectthreshold.roktrack,
+    };
+    RoktrackClasses::filter(&mut detections.to_vec(), cls.to_u32(), threshold)
+        .into_iter()
+        .filter(|det| match endpoint.id {
+            Some(id) => det.ids.contains(&id),
+            None => true,
+        })
+        .collect()
 }
 
 /// System Risks
@@ -150,16 +202,24 @@ enum SystemRisk {
     StateOff,
     HighTemp,
     Bumped,
+    Stuck,
 }
 /// Identify system-related risks
 ///
-fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
+fn assess_system_risk(
+    state: &mut RoktrackState,
+    device: &Roktrack,
+    visual_info: &VisualInfo,
+    conf: Config,
+) -> Option<SystemRisk> {
     if !state.state {
         Some(SystemRisk::StateOff)
     } else if state.pi_temp > 70.0 {
         Some(SystemRisk::HighTemp)
     } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
         Some(SystemRisk::Bumped)
+    } else if base::assess_stuck(state, device, visual_info, conf) {
+        Some(SystemRisk::Stuck)
     } else {
         None
     }
@@ -203,3 +263,51 @@ fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhas
         Some(ActPhase::Proceed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_endpoint_test() {
+        std::fs::create_dir_all("/tmp/roktracktest/").unwrap();
+        let conf = crate::module::util::conf::toml::load("/tmp/roktracktest/").unwrap();
+        let pylon3 = Detection {
+            cls: RoktrackClasses::PYLON.to_u32(),
+            prob: 0.9,
+            ids: vec![3],
+            ..Default::default()
+        };
+        let pylon7 = Detection {
+            ids: vec![7],
+            ..pylon3.clone()
+        };
+        let person = Detection {
+            cls: RoktrackClasses::PERSON.to_u32(),
+            prob: 0.9,
+            ..Default::default()
+        };
+        let dets = vec![pylon3.clone(), pylon7.clone(), person.clone()];
+        let endpoint = Endpoint {
+            class: String::from("pylon"),
+            id: Some(7),
+        };
+        assert_eq!(
+            filter_endpoint(&dets, &endpoint, conf.clone()),
+            vec![pylon7]
+        );
+        let endpoint = Endpoint {
+            class: String::from("person"),
+            id: None,
+        };
+        let mut state = RoktrackState::new(conf.clone());
+        assert_eq!(filter_endpoint(&dets, &endpoint, conf), vec![person]);
+        // The trips are counted from zero after the reset
+        let mut round_trip = RoundTrip::new();
+        round_trip.trips = 2;
+        round_trip.target = RoundTripEnd::End;
+        round_trip.reset(&mut state);
+        assert_eq!(round_trip.trips, 0);
+        assert_eq!(round_trip.target, RoundTripEnd::Start);
+    }
+}
@@ -0,0 +1,458 @@
+//! Route Pilot over OCR-numbered pylons.
+//!
+//! Visits the pylons in the configured order, e.g. #1 -> #4 -> #2,
+//! and carries out the action attached to each waypoint.
+
+use std::sync::mpsc::Sender;
+
+use super::PilotHandler;
+use crate::module::{
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::RoktrackState,
+    util::{
+        common::send_line_notify_with_image,
+        conf::{Config, Waypoint},
+        init::RoktrackProperty,
+    },
+    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
+    vision::{VisionMgmtCommand, VisualInfo},
+};
+
+pub struct Route {
+    blade: Option<bool>, // Whether the blade runs on the way to the next waypoint
+    laps: u32,           // Completed passes over the route
+    pause_until: u64,    // Stay at the waypoint until this time (milliseconds)
+}
+
+impl Route {
+    pub fn new() -> Self {
+        Self {
+            blade: None,
+            laps: 0,
+            pause_until: 0,
+        }
+    }
+
+    /// Move on to the next waypoint.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - A mutable reference to the `RoktrackState` holding the waypoint index.
+    /// * `len` - Number of waypoints in the route.
+    /// * `laps` - Number of passes over the route before completing (0 for endless).
+    ///
+    /// # Returns
+    ///
+    /// `true` if the route has been completed.
+    fn advance(&mut self, state: &mut RoktrackState, len: usize, laps: u32) -> bool {
+        state.waypoint += 1;
+        if (state.waypoint as usize) < len {
+            return false;
+        }
+        self.laps += 1;
+        log::info!("Route Lap Completed. laps: {}", self.laps);
+        if 0 < laps && laps <= self.laps {
+            // The next start runs the route from the first waypoint
+            self.reset(state);
+            true
+        } else {
+            state.waypoint = 0;
+            false
+        }
+    }
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Route {
+    /// Start the route over from the first waypoint.
+    fn reset(&mut self, state: &mut RoktrackState) {
+        *self = Self::new();
+        state.waypoint = 0;
+    }
+
+    /// Function called from a thread to handle the Route Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        visual_info: &mut VisualInfo,
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Route Handle");
+        // Assess and handle system safety
+        let system_risk =
+            match assess_system_risk(state, device, visual_info, property.conf.clone()) {
+                Some(SystemRisk::StateOff) => Some(base::stop(device)),
+                Some(SystemRisk::HighTemp) => {
+                    let res = base::stop(device);
+                    device.speak("high_temp");
+                    Some(res)
+                }
+                Some(SystemRisk::Bumped) => {
+                    let res = base::escape(state, device);
+                    device.speak("bumped");
+                    Some(res)
+                }
+                Some(SystemRisk::Stuck) => Some(base::recover_stuck(
+                    state,
+                    device,
+                    tx.clone(),
+                    property.clone(),
+                )),
+                None => None,
+            };
+        if system_risk.is_some() {
+            log::warn!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        let mut detections = visual_info.detections.clone();
+
+        // Skip during turning(Images taken while turning are blurred.)
+        if device.inner.clone().lock().unwrap().is_turning()
+            && visual_info.shooting_start_time
+                < device.inner.clone().lock().unwrap().target_time + 300
+        {
+            log::debug!("Waiting for Static Image.");
+            return; // wait for next image
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(state, &mut detections, property.conf.clone()) {
+            Some(VisionRisk::Person(response)) => {
+                match base::respond_person(state, device, Some(response)) {
+                    true => Some(Ok(())),
+                    false => None,
+                }
+            }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
+            None => {
+                base::respond_person(state, device, None);
+                None
+            }
+        };
+        if vision_risk.is_some() {
+            log::warn!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Stay at the waypoint for a while
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        if now < self.pause_until {
+            log::debug!("Pausing. rest_time: {}", self.pause_until - now);
+            return;
+        }
+
+        // Get the current waypoint
+        let waypoints = &property.conf.route.waypoints;
+        let waypoint = match waypoints.get(state.waypoint as usize) {
+            Some(waypoint) => waypoint.clone(),
+            None => {
+                log::error!("No Waypoint. index: {}", state.waypoint);
+                let _ = base::halt(state, device, tx);
+                return;
+            }
+        };
+
+        // Filter the pylon of the current waypoint and sort by size
+        let mut detections = RoktrackClasses::filter(
+            &mut detections,
+            RoktrackClasses::PYLON.to_u32(),
+            property.conf.detectthreshold.pylon,
+        );
+        let detections = sort::big(&mut detections);
+        let detections = filter_waypoint(&detections, &waypoint);
+        let detections = base::lock_track(state, detections);
+
+        // Get the first detected marker or a default one
+        let marker = detections.first().cloned().unwrap_or_default();
+        state.marker_height = marker.h;
+        log::info!("Marker Selected: {:?}", marker);
+
+        // Switch the work motor
+        let blade = *self.blade.get_or_insert(property.conf.route.blade);
+        device.inner.clone().lock().unwrap().set_work(blade);
+
+        let action = assess_situation(state, &marker);
+        log::info!("Action is {:?}", action);
+
+        // Handle the current phase
+        let _ = match action {
+            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
+            Some(ActPhase::TurnMarkerInvisible) => base::reset_ex_height(state, device),
+            Some(ActPhase::TurnMarkerFound) => base::set_new_target(state, device, marker),
+            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
+            Some(ActPhase::Stand) => base::stand(state, tx),
+            Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::ReachMarker) => {
+                let res = base::reach_marker(state, device, marker);
+                log::info!(
+                    "Waypoint Reached. index: {}, id: {}",
+                    state.waypoint,
+                    waypoint.id
+                );
+                // Carry out the waypoint actions
+                if let Some(blade) = waypoint.blade {
+                    self.blade = Some(blade);
+                    device.inner.clone().lock().unwrap().set_work(blade);
+                }
+                if 0 < waypoint.pause {
+                    device.inner.lock().unwrap().pause();
+                    self.pause_until = now + waypoint.pause;
+                }
+                if waypoint.announce {
+                    device.speak("reach_waypoint");
+                    let msg = format!("Waypoint #{} reached.", waypoint.id);
+                    let _ = send_line_notify_with_image(
+                        &msg,
+                        &property.path.img.last,
+                        property.conf.clone(),
+                    );
+                }
+                // Head to the next waypoint
+                if self.advance(state, waypoints.len(), property.conf.route.laps) {
+                    base::mission_complete(state, device, property.clone())
+                } else {
+                    res
+                }
+            }
+            Some(ActPhase::Proceed) => base::proceed(state, device, marker, tx),
+            None => Ok(()),
+        };
+        log::debug!("End Route Handle");
+    }
+}
+
+/// Filter detections carrying the OCR id of the waypoint.
+fn filter_waypoint(detections: &[Detection], waypoint: &Waypoint) -> Vec<Detection> {
+    detections
+        .iter()
+        .filter(|det| det.ids.contains(&waypoint.id))
+        .cloned()
+        .collect()
+}
+
+/// System Risks
+///
+#[derive(Debug, Clone)]
+enum SystemRisk {
+    StateOff,
+    HighTemp,
+    Bumped,
+    Stuck,
+}
+/// Identify system-related risks
+///
+fn assess_system_risk(
+    state: &mut RoktrackState,
+    device: &Roktrack,
+    visual_info: &VisualInfo,
+    conf: Config,
+) -> Option<SystemRisk> {
+    if !state.state {
+        Some(SystemRisk::StateOff)
+    } else if state.pi_temp > 70.0 {
+        Some(SystemRisk::HighTemp)
+    } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
+        Some(SystemRisk::Bumped)
+    } else if base::assess_stuck(state, device, visual_info, conf) {
+        Some(SystemRisk::Stuck)
+    } else {
+        None
+    }
+}
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    Person(base::PersonResponse),
+    RoktrackDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(
+    state: &mut RoktrackState,
+    dets: &mut [Detection],
+    conf: Config,
+) -> Option<VisionRisk> {
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+    if let Some(response) = base::assess_person(state, dets, conf.clone(), now) {
+        Some(VisionRisk::Person(response))
+    } else if !RoktrackClasses::filter(
+        dets,
+        RoktrackClasses::ROKTRACK.to_u32(),
+        conf.detectthreshold.roktrack,
+    )
+    .is_empty()
+    {
+        Some(VisionRisk::RoktrackDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Route Pilot
+///
+#[derive(Debug, Clone)]
+enum ActPhase {
+    TurnCountExceeded,
+    TurnMarkerInvisible,
+    TurnMarkerFound,
+    TurnKeep,
+    Stand,
+    StartTurn,
+    ReachMarker,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhase> {
+    if 10 <= state.turn_count {
+        Some(ActPhase::TurnCountExceeded)
+    } else if 0 < state.turn_count {
+        if marker.h == 0 {
+            Some(ActPhase::TurnMarkerInvisible)
+        } else if (marker.h as f32) < state.ex_height as f32 - state.img_height as f32 * 0.015 {
+            Some(ActPhase::TurnMarkerFound)
+        } else {
+            Some(ActPhase::TurnKeep)
+        }
+    } else if marker.h == 0 {
+        if state.turn_count == -1 {
+            Some(ActPhase::Stand)
+        } else if state.turn_count == 0 {
+            Some(ActPhase::StartTurn)
+        } else {
+            None
+        }
+    } else if state.target_height <= marker.h as u16 {
+        Some(ActPhase::ReachMarker)
+    } else {
+        Some(ActPhase::Proceed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::vision::detector::{fake::ScriptedDetector, Detector};
+    use std::sync::mpsc;
+
+    #[test]
+    fn route_advance_test() {
+        std::fs::create_dir_all("/tmp/roktracktest/").unwrap();
+        let conf = crate::module::util::conf::toml::load("/tmp/roktracktest/").unwrap();
+        let mut state = RoktrackState::new(conf);
+        let mut route = Route::new();
+        // Two passes over three waypoints
+        assert!(!route.advance(&mut state, 3, 2));
+        assert!(!route.advance(&mut state, 3, 2));
+        assert_eq!(state.waypoint, 2);
+        assert!(!route.advance(&mut state, 3, 2));
+        assert_eq!(state.waypoint, 0);
+        assert!(!route.advance(&mut state, 3, 2));
+        assert!(!route.advance(&mut state, 3, 2));
+        assert!(route.advance(&mut state, 3, 2));
+        // The completed route starts over
+        assert_eq!(state.waypoint, 0);
+        assert_eq!(route.laps, 0);
+        // The waypoint actions don't outlive the reset
+        route.blade = Some(false);
+        route.pause_until = u64::MAX;
+        state.waypoint = 1;
+        route.reset(&mut state);
+        assert_eq!(route.blade, None);
+        assert_eq!(route.pause_until, 0);
+        assert_eq!(state.waypoint, 0);
+        // Only the waypoint with the id is selected
+        let pylon = Detection {
+            cls: RoktrackClasses::PYLON.to_u32(),
+            prob: 0.9,
+            ids: vec![4],
+            ..Default::default()
+        };
+        let waypoint = Waypoint {
+            id: 4,
+            ..Default::default()
+        };
+        assert_eq!(
+            filter_waypoint(std::slice::from_ref(&pylon), &waypoint),
+            vec![pylon.clone()]
+        );
+        let waypoint = Waypoint {
+            id: 2,
+            ..Default::default()
+        };
+        assert!(filter_waypoint(&[pylon], &waypoint).is_empty());
+    }
+
+    #[test]
+    fn route_handle_test() {
+        let mut property = crate::module::util::init::resource::init();
+        property.conf.route.waypoints = vec![
+            Waypoint {
+                id: 4,
+                ..Default::default()
+            },
+            Waypoint {
+                id: 2,
+                ..Default::default()
+            },
+        ];
+        let mut state = RoktrackState::new(property.conf.clone());
+        let mut device = Roktrack::new(property.conf.clone());
+        let (tx, _rx) = mpsc::channel();
+        let mut route = Route::new();
+        let pylon = |id: u8, h: u32| Detection {
+            cls: RoktrackClasses::PYLON.to_u32(),
+            prob: 0.9,
+            xc: state.img_width as f32 / 2.0,
+            h,
+            ids: vec![id],
+            ..Default::default()
+        };
+        let (far, near) = (
+            state.target_height as u32 / 4,
+            state.target_height as u32 + 10,
+        );
+        // #2 is closer, but #4 is the first waypoint
+        let detector = ScriptedDetector::new(vec![
+            vec![pylon(2, near), pylon(4, far)],
+            vec![pylon(4, near)],
+        ])
+        .with_ocr();
+        let frame = || {
+            let dets = detector.infer("", "").unwrap();
+            VisualInfo {
+                detections: detector.ocr("", dets, property.clone()).unwrap(),
+                ..VisualInfo::default()
+            }
+        };
+        // Head to #4
+        route.handle(
+            &mut state,
+            &mut device,
+            &mut frame(),
+            tx.clone(),
+            property.clone(),
+        );
+        assert_eq!(state.marker_height, far);
+        assert_eq!(state.waypoint, 0);
+        // Reach #4 and head to #2
+        route.handle(
+            &mut state,
+            &mut device,
+            &mut frame(),
+            tx.clone(),
+            property.clone(),
+        );
+        assert_eq!(state.marker_height, near);
+        assert_eq!(state.waypoint, 1);
+        base::stop(&mut device).unwrap();
+    }
+}
@@ -0,0 +1,459 @@
+//! Survey Pilot
+//!
+//! Turns 360 degrees on the spot before the mission with the work motor off,
+//! lists the visible pylons and warns about gaps and too distant pylons.
+
+// # Normal flow
+//
+// Scan 320 * steps  <- Turn on the spot and record the pylons at each step.
+//    |
+// Scan 640 * steps  <- Upscale and do it again to find the small pylons.
+//    |
+// Done  <- Report the pylons, gaps and too distant pylons.
+
+use std::sync::mpsc::Sender;
+
+use super::PilotHandler;
+use crate::module::{
+    com::ChildMsg,
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::RoktrackState,
+    util::{
+        common::send_line_notify_with_image,
+        conf::{Config, Survey as SurveyConf},
+        init::RoktrackProperty,
+    },
+    vision::detector::{Detection, FilterClass, RoktrackClasses},
+    vision::{geometry::Geometry, VisionMgmtCommand, VisualInfo},
+};
+
+pub struct Survey {
+    stage: Stage,
+    sightings: Vec<Sighting>, // Pylons seen in the scans
+}
+
+impl Survey {
+    pub fn new() -> Self {
+        Self {
+            stage: Stage::Done,
+            sightings: vec![],
+        }
+    }
+
+    /// Scan around step by step.
+    ///
+    /// At each step, the pylons in the latest static image are recorded,
+    /// then the mower turns left by 360 / steps degrees.
+    fn scan(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        visual_info: &VisualInfo,
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        let (step, size, since) = match &self.stage {
+            Stage::Scan { step, size, since } => (*step, size.clone(), *since),
+            Stage::Done => return,
+        };
+        // Wait for the turn to finish and a new image to be taken
+        if device.inner.clone().lock().unwrap().is_turning()
+            || visual_info.shooting_start_time < since
+        {
+            log::debug!("Waiting for Static Image.");
+            return;
+        }
+
+        // Record the pylons
+        let conf = property.conf.clone();
+        let heading = step as f32 * 360.0 / conf.survey.steps.max(1) as f32;
+        let pylons = RoktrackClasses::filter(
+            &mut visual_info.detections.clone(),
+            RoktrackClasses::PYLON.to_u32(),
+            conf.detectthreshold.pylon,
+        );
+        for pylon in pylons.iter() {
+            let sighting = Sighting::new(pylon, heading, state, &conf, size.clone());
+            log::debug!("Pylon Sighted: {:?}", sighting);
+            self.sightings.push(sighting);
+        }
+
+        let step = step + 1;
+        if step < conf.survey.steps {
+            let binding = device.inner.clone();
+            let mut device_lock = binding.lock().unwrap();
+            device_lock.left(scan_turn_time(&conf));
+            self.stage = Stage::Scan {
+                step,
+                size,
+                since: device_lock.target_time + 300,
+            };
+        } else if size == ScanSize::Sz320 {
+            log::info!("320 Scan Completed. Start 640 Scan.");
+            let _ = base::upscale(state, tx);
+            // Turn the last step back to the start heading, so both scans share the bearings
+            let binding = device.inner.clone();
+            let mut device_lock = binding.lock().unwrap();
+            device_lock.left(scan_turn_time(&conf));
+            self.stage = Stage::Scan {
+                step: 0,
+                size: ScanSize::Sz640,
+                since: device_lock.target_time + 300,
+            };
+        } else {
+            log::info!("Survey Completed.");
+            let _ = base::downscale(state, tx);
+            self.stage = Stage::Done;
+            self.report(state, device, property);
+        }
+    }
+
+    /// Report the result of the survey by voice and notification.
+    fn report(&self, state: &mut RoktrackState, device: &mut Roktrack, property: RoktrackProperty) {
+        let result = summarize(&self.sightings, &property.conf.survey);
+        let summary = result.summary();
+        log::info!("{}", summary);
+        state.state = false;
+        device.inner.clone().lock().unwrap().stop();
+        if result.is_clear() {
+            state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+            device.speak("survey_clear");
+        } else {
+            state.msg = ChildMsg::to_u8(ChildMsg::TargetNotFound);
+            if !result.gaps.is_empty() {
+                device.speak("survey_gap");
+            }
+            if !result.far.is_empty() {
+                device.speak("survey_far");
+            }
+        }
+        let _ = send_line_notify_with_image(&summary, &property.path.img.last, property.conf);
+    }
+}
+
+impl Default for Survey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Survey {
+    /// Function called from a thread to handle the Survey Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        visual_info: &mut VisualInfo,
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Survey Handle");
+        // Assess and handle system safety
+        let system_risk = match assess_system_risk(state, device) {
+            Some(SystemRisk::StateOff) => Some(base::stop(device)),
+            Some(SystemRisk::HighTemp) => {
+                let res = base::stop(device);
+                device.speak("high_temp");
+                Some(res)
+            }
+            Some(SystemRisk::Bumped) => {
+                let res = base::escape(state, device);
+                device.speak("bumped");
+                Some(res)
+            }
+            None => None,
+        };
+        if system_risk.is_some() {
+            log::warn!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // The blade never runs in the survey
+        device.inner.clone().lock().unwrap().set_work(false);
+
+        // Start a new survey
+        if self.stage == Stage::Done {
+            log::info!("Start Survey.");
+            device.speak("survey_start");
+            self.sightings.clear();
+            self.stage = Stage::Scan {
+                step: 0,
+                size: ScanSize::Sz320,
+                since: chrono::Utc::now().timestamp_millis() as u64,
+            };
+        }
+        self.scan(state, device, visual_info, tx, property);
+        log::debug!("End Survey Handle");
+    }
+}
+
+/// Stages of the Survey Pilot
+///
+#[derive(Debug, Clone, PartialEq)]
+enum Stage {
+    Scan {
+        step: u8,       // Number of steps done
+        size: ScanSize, // Image size of the session
+        since: u64,     // Only images taken after this time are checked (milliseconds)
+    },
+    Done,
+}
+/// Image sizes of the scan
+///
+#[derive(Debug, Clone, PartialEq)]
+enum ScanSize {
+    Sz320,
+    Sz640,
+}
+
+/// A pylon seen in the scan.
+#[derive(Debug, Clone, PartialEq)]
+struct Sighting {
+    bearing: f32,   // Degrees, counterclockwise from the start heading (0 -> 360)
+    height: f32,    // Pylon height relative to the image height
+    id: Option<u8>, // OCR id, if read
+    size: ScanSize, // Image size of the session it was seen in
+}
+
+impl Sighting {
+    /// Locate the pylon from the heading of the scan step and its horizontal position in the image.
+    fn new(
+        pylon: &Detection,
+        heading: f32,
+        state: &RoktrackState,
+        conf: &Config,
+        size: ScanSize,
+    ) -> Self {
+        let offset = pylon
+            .bearing
+            .unwrap_or_else(|| Geometry::new(conf).bearing(pylon.xc));
+        Self {
+            bearing: (heading - offset).rem_euclid(360.0),
+            height: pylon.h as f32 / state.img_height as f32,
+            id: pylon.ids.first().copied(),
+            size,
+        }
+    }
+}
+
+/// Result of the survey.
+#[derive(Debug, Clone)]
+struct SurveyResult {
+    pylons: Vec<Sighting>, // Visible pylons in the order of the bearing
+    gaps: Vec<(f32, f32)>, // Bearings between which no pylon is visible
+    far: Vec<Sighting>,    // Pylons too distant to be found in the normal session
+}
+
+impl SurveyResult {
+    /// Whether the pylons are ready for the mission.
+    fn is_clear(&self) -> bool {
+        !self.pylons.is_empty() && self.gaps.is_empty() && self.far.is_empty()
+    }
+
+    /// Human readable summary for notifications.
+    fn summary(&self) -> String {
+        let describe = |p: &Sighting| match p.id {
+            Some(id) => format!("#{} at {:.0}deg ({:.0}%)", id, p.bearing, p.height * 100.0),
+            None => format!("? at {:.0}deg ({:.0}%)", p.bearing, p.height * 100.0),
+        };
+        let mut lines = vec![format!("Survey: {} pylons.", self.pylons.len())];
+        lines.extend(self.pylons.iter().map(describe));
+        for (from, to) in self.gaps.iter() {
+            lines.push(format!("Gap: {:.0}deg -> {:.0}deg", from, to));
+        }
+        for pylon in self.far.iter() {
+            lines.push(format!("Too distant: {}", describe(pylon)));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Merge the sightings into pylons and look for gaps and too distant pylons.
+///
+/// # Arguments
+///
+/// * `sightings` - Pylons seen in the scans.
+/// * `conf` - Survey configuration.
+///
+/// # Returns
+///
+/// The result of the survey.
+fn summarize(sightings: &[Sighting], conf: &SurveyConf) -> SurveyResult {
+    let mut sightings = sightings.to_vec();
+    sightings.sort_by(|a, b| a.bearing.partial_cmp(&b.bearing).unwrap());
+    // The same pylon is seen in both sizes and in the neighbouring steps
+    let mut pylons: Vec<(Sighting, bool)> = vec![];
+    for s in sightings.into_iter() {
+        let seen_320 = s.size == ScanSize::Sz320;
+        match pylons.iter_mut().find(|(p, _)| {
+            let d = (p.bearing - s.bearing).abs();
+            d.min(360.0 - d) < conf.merge || (p.id.is_some() && p.id == s.id)
+        }) {
+            Some((p, normal)) => {
+                *normal |= seen_320;
+                if p.height < s.height {
+                    *p = Sighting {
+                        id: s.id.or(p.id),
+                        ..s
+                    };
+                }
+            }
+            None => pylons.push((s, seen_320)),
+        }
+    }
+    let far = pylons
+        .iter()
+        .filter(|(p, normal)| !normal || p.height < conf.min_height)
+        .map(|(p, _)| p.clone())
+        .collect();
+    let pylons: Vec<Sighting> = pylons.into_iter().map(|(p, _)| p).collect();
+    // Gaps between the neighbouring pylons, including the one across 0 degrees
+    let gaps = match pylons.len() {
+        0 => vec![(0.0, 360.0)],
+        n => (0..n)
+            .filter_map(|i| {
+                let (from, to) = (pylons[i].bearing, pylons[(i + 1) % n].bearing);
+                let width = (to - from).rem_euclid(360.0);
+                let width = if n == 1 { 360.0 } else { width };
+                match conf.max_gap < width {
+                    true => Some((from, to)),
+                    false => None,
+                }
+            })
+            .collect(),
+    };
+    SurveyResult { pylons, gaps, far }
+}
+
+/// Milliseconds of a turn for a scan step.
+///
+/// The turning speed for dead reckoning is used to divide 360 degrees into `steps`.
+fn scan_turn_time(conf: &Config) -> u64 {
+    let steps = conf.survey.steps.max(1) as f32;
+    (360.0 / steps / conf.map.turn_speed * 1000.0) as u64
+}
+
+/// System Risks
+///
+#[derive(Debug, Clone)]
+enum SystemRisk {
+    StateOff,
+    HighTemp,
+    Bumped,
+}
+/// Identify system-related risks
+///
+fn assess_system_risk(state: &mut RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
+    if !state.state {
+        Some(SystemRisk::StateOff)
+    } else if state.pi_temp > 70.0 {
+        Some(SystemRisk::HighTemp)
+    } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
+        Some(SystemRisk::Bumped)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn sighting(bearing: f32, height: f32, id: Option<u8>, size: ScanSize) -> Sighting {
+        Sighting {
+            bearing,
+            height,
+            id,
+            size,
+        }
+    }
+
+    #[test]
+    fn summarize_test() {
+        let conf = SurveyConf::default();
+        // Four pylons around, one of them only seen in the 640 session
+        let sightings = vec![
+            sighting(10.0, 0.2, Some(1), ScanSize::Sz320),
+            sighting(12.0, 0.2, None, ScanSize::Sz640),
+            sighting(100.0, 0.1, Some(2), ScanSize::Sz320),
+            sighting(190.0, 0.03, Some(3), ScanSize::Sz640),
+            sighting(280.0, 0.1, Some(4), ScanSize::Sz320),
+            sighting(355.0, 0.1, Some(4), ScanSize::Sz640),
+        ];
+        let result = summarize(&sightings, &conf);
+        assert_eq!(result.pylons.len(), 4);
+        assert_eq!(result.pylons[0].id, Some(1));
+        assert!(result.gaps.is_empty());
+        assert_eq!(result.far.len(), 1);
+        assert_eq!(result.far[0].id, Some(3));
+        assert!(!result.is_clear());
+        // A gap on the left
+        let sightings = vec![
+            sighting(0.0, 0.2, Some(1), ScanSize::Sz320),
+            sighting(90.0, 0.2, Some(2), ScanSize::Sz320),
+        ];
+        let result = summarize(&sightings, &conf);
+        assert_eq!(result.gaps, vec![(90.0, 0.0)]);
+        // Nothing visible
+        let result = summarize(&[], &conf);
+        assert_eq!(result.gaps, vec![(0.0, 360.0)]);
+    }
+
+    #[test]
+    fn scan_test() {
+        let mut property = crate::module::util::init::resource::init();
+        property.conf.survey.steps = 4;
+        let mut device = Roktrack::new(property.conf.clone());
+        let mut state = RoktrackState::new(property.conf.clone());
+        let (tx, _rx) = mpsc::channel();
+        let mut survey = Survey::new();
+        survey.stage = Stage::Scan {
+            step: 0,
+            size: ScanSize::Sz320,
+            since: 0,
+        };
+        // The same pylon straight ahead at the second step of both scans
+        let pylon = Detection {
+            cls: RoktrackClasses::PYLON.to_u32(),
+            prob: 0.9,
+            h: 48,
+            bearing: Some(0.0),
+            ..Detection::default()
+        };
+        for i in 0..6 {
+            let visual_info = VisualInfo {
+                shooting_start_time: u64::MAX,
+                detections: match i % 4 {
+                    1 => vec![pylon.clone()],
+                    _ => vec![],
+                },
+                ..VisualInfo::default()
+            };
+            survey.scan(
+                &mut state,
+                &mut device,
+                &visual_info,
+                tx.clone(),
+                property.clone(),
+            );
+            // Every step turns, including the last one of the 320 scan
+            assert!(device.inner.lock().unwrap().is_turning());
+            base::stop(&mut device).unwrap();
+        }
+        assert!(matches!(
+            survey.stage,
+            Stage::Scan {
+                step: 2,
+                size: ScanSize::Sz640,
+                ..
+            }
+        ));
+        assert_eq!(survey.sightings.len(), 2);
+        assert_eq!(survey.sightings[0].size, ScanSize::Sz320);
+        assert_eq!(survey.sightings[1].size, ScanSize::Sz640);
+        assert_eq!(survey.sightings[0].bearing, 90.0);
+        assert_eq!(survey.sightings[1].bearing, 90.0);
+    }
+}
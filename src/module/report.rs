@@ -0,0 +1,400 @@
+//! Mission Report Module
+//!
+//! This module records the statistics of a mission and writes a report when it is completed.
+
+use chrono::{DateTime, TimeZone, Utc};
+use image::{Rgb, RgbImage};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::module::map::{odometry::Pose, PylonRecord};
+use crate::module::pilot::RoktrackState;
+
+// Pixels per coverage cell in the rendered image
+const PX_PER_CELL: u32 = 4;
+// Margin around the rendered area in cells
+const MARGIN_CELLS: i32 = 4;
+
+/// Cells of the field that the mower has passed over.
+#[derive(Debug, Clone)]
+pub struct Coverage {
+    pub cell_size: f32,             // Cell size in metres
+    pub cells: HashSet<(i32, i32)>, // Visited cells
+}
+
+impl Coverage {
+    /// Creates an empty coverage raster.
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size,
+            cells: HashSet::new(),
+        }
+    }
+
+    /// Mark the cell under the pose as covered.
+    pub fn mark(&mut self, pose: &Pose) {
+        self.cells.insert(self.cell_of(pose.x, pose.y));
+    }
+
+    /// Index of the cell containing the position.
+    pub fn cell_of(&self, x: f32, y: f32) -> (i32, i32) {
+        (
+            (x / self.cell_size).floor() as i32,
+            (y / self.cell_size).floor() as i32,
+        )
+    }
+
+    /// Covered area in square metres.
+    pub fn area(&self) -> f32 {
+        self.cells.len() as f32 * self.cell_size.powi(2)
+    }
+}
+
+/// Statistics of the current mission.
+#[derive(Debug, Clone)]
+pub struct MissionLog {
+    pub start_time: i64,    // Milliseconds
+    pub laps: u32,          // Completed laps
+    pub bumps: u32,         // Number of bumper hits
+    pub person_stops: u32,  // Number of stops for a person
+    person_stopping: bool,  // Currently stopped for a person
+    pub coverage: Coverage, // Covered cells
+
+    pub start_distance: f32, // Odometry distance at the start (metres)
+    started: bool,           // The mode has been turned on for this mission
+    reached: Vec<u32>,       // Orders of the pylons reached in this mission
+
+    pub person_resumes: u32, // Number of resumes after a person has gone
+    pub person_warns: u32,   // Number of warnings for a distant person
+    person_warning: bool,    // Currently warning for a person
+}
+
+impl MissionLog {
+    /// Starts a new mission log.
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            start_time: Utc::now().timestamp_millis(),
+            start_distance: 0.0,
+            started: false,
+            reached: vec![],
+            laps: 0,
+            bumps: 0,
+            person_stops: 0,
+            person_resumes: 0,
+            person_warns: 0,
+            person_stopping: false,
+            person_warning: false,
+            coverage: Coverage::new(cell_size),
+        }
+    }
+
+    /// Start the mission when the mode is turned on.
+    ///
+    /// Turning on again after a pause continues the mission.
+    ///
+    /// # Arguments
+    ///
+    /// * `distance` - Odometry distance travelled so far (metres).
+    pub fn start(&mut self, distance: f32) {
+        if !self.started {
+            self.start_time = Utc::now().timestamp_millis();
+            self.start_distance = distance;
+            self.started = true;
+            self.reached.clear();
+        }
+    }
+
+    /// Distance travelled in the mission (metres).
+    ///
+    /// # Arguments
+    ///
+    /// * `distance` - Odometry distance travelled so far (metres).
+    pub fn distance(&self, distance: f32) -> f32 {
+        distance - self.start_distance
+    }
+
+    /// Count reaching a pylon.
+    ///
+    /// Returns `true` if the mower is back at the first pylon reached in this mission, i.e. a lap is completed.
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - Order of the pylon on the map.
+    pub fn reach(&mut self, order: u32) -> bool {
+        self.reached.push(order);
+        1 < self.reached.len() && self.reached[0] == order
+    }
+
+    /// Number of different pylons reached in this mission.
+    pub fn pylons_reached(&self) -> u32 {
+        let mut orders = self.reached.clone();
+        orders.sort();
+        orders.dedup();
+        orders.len() as u32
+    }
+
+    /// Count a stop for a person. Consecutive frames with the person are counted once.
+    pub fn person_stop(&mut self) {
+        if !self.person_stopping {
+            self.person_stops += 1;
+            self.person_stopping = true;
+        }
+    }
+
+    /// Count a warning for a distant person. Consecutive frames with the person are counted once.
+    ///
+    /// Returns `true` if this is a new warning.
+    pub fn person_warn(&mut self) -> bool {
+        if !self.person_warning {
+            self.person_warns += 1;
+            self.person_warning = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the mower is stopped for a person.
+    pub fn is_person_stopping(&self) -> bool {
+        self.person_stopping
+    }
+
+    /// The person has gone. Count a resume if the mower was stopped.
+    pub fn person_clear(&mut self) {
+        if self.person_stopping {
+            self.person_resumes += 1;
+        }
+        self.person_stopping = false;
+        self.person_warning = false;
+    }
+}
+
+/// Report of a completed mission.
+#[derive(Debug, Clone, Serialize)]
+pub struct MissionReport {
+    pub site: String,
+    pub mode: String,
+    pub start_time: String,
+    pub end_time: String,
+    pub duration_sec: i64,
+    pub laps: u32,
+    pub rest: f32,
+    pub bumps: u32,
+    pub person_stops: u32,
+    pub person_resumes: u32,
+    pub person_warns: u32,
+    pub distance: f32,
+    pub covered_area: f32,
+    pub field_area: f32, // Area enclosed by the pylons (square metres)
+    pub cell_size: f32,
+    pub coverage: Vec<(i32, i32)>,
+    pub pylons: Vec<PylonRecord>,
+}
+
+impl MissionReport {
+    /// Build the report from the current state.
+    pub fn from_state(state: &RoktrackState) -> Self {
+        let end = Utc::now();
+        let start: DateTime<Utc> = Utc
+            .timestamp_millis_opt(state.mission.start_time)
+            .single()
+            .unwrap_or(end);
+        let mut coverage: Vec<(i32, i32)> = state.mission.coverage.cells.iter().copied().collect();
+        coverage.sort();
+        Self {
+            site: state.map.site.clone(),
+            mode: format!("{:?}", state.mode),
+            start_time: start.to_rfc3339(),
+            end_time: end.to_rfc3339(),
+            duration_sec: (end - start).num_seconds(),
+            laps: state.mission.laps,
+            rest: state.rest,
+            bumps: state.mission.bumps,
+            person_stops: state.mission.person_stops,
+            person_resumes: state.mission.person_resumes,
+            person_warns: state.mission.person_warns,
+            distance: state.mission.distance(state.odometry.distance),
+            covered_area: state.mission.coverage.area(),
+            field_area: state.map.area(),
+            cell_size: state.mission.coverage.cell_size,
+            coverage,
+            pylons: state.map.pylons.clone(),
+        }
+    }
+
+    /// One line summary for notifications.
+    pub fn summary(&self) -> String {
+        format!(
+            "Mission complete ({}). {} min, {} laps, rest {:.0}%, {:.1} m travelled, {:.1} m2 covered, {} bumps, {} person stops.",
+            self.mode,
+            self.duration_sec / 60,
+            self.laps,
+            self.rest.max(0.0) * 100.0,
+            self.distance,
+            self.covered_area,
+            self.bumps,
+            self.person_stops,
+        )
+    }
+
+    /// Render the coverage and pylons as an image.
+    ///
+    /// Covered cells are green and pylons are orange. North (+y) is up.
+    pub fn render(&self) -> RgbImage {
+        let pylon_cells: Vec<(i32, i32)> = self
+            .pylons
+            .iter()
+            .map(|p| {
+                (
+                    (p.x / self.cell_size).floor() as i32,
+                    (p.y / self.cell_size).floor() as i32,
+                )
+            })
+            .collect();
+        let all = self.coverage.iter().chain(pylon_cells.iter());
+        let (min_x, max_x, min_y, max_y) =
+            all.fold((0, 0, 0, 0), |(min_x, max_x, min_y, max_y), &(x, y)| {
+                (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+            });
+        let (min_x, min_y) = (min_x - MARGIN_CELLS, min_y - MARGIN_CELLS);
+        let w = (max_x + MARGIN_CELLS - min_x + 1) as u32;
+        let h = (max_y + MARGIN_CELLS - min_y + 1) as u32;
+        let mut img = RgbImage::from_pixel(w * PX_PER_CELL, h * PX_PER_CELL, Rgb([255, 255, 255]));
+        let mut fill = |cell: &(i32, i32), color: Rgb<u8>| {
+            let cx = (cell.0 - min_x) as u32 * PX_PER_CELL;
+            // Flip vertically so that +y is up
+            let cy = (h - 1 - (cell.1 - min_y) as u32) * PX_PER_CELL;
+            for dy in 0..PX_PER_CELL {
+                for dx in 0..PX_PER_CELL {
+                    img.put_pixel(cx + dx, cy + dy, color);
+                }
+            }
+        };
+        for cell in self.coverage.iter() {
+            fill(cell, Rgb([80, 180, 80]));
+        }
+        for cell in pylon_cells.iter() {
+            fill(cell, Rgb([255, 120, 0]));
+        }
+        img
+    }
+
+    /// Write the report as JSON and PNG to the directory.
+    ///
+    /// Returns the path of the rendered image.
+    pub fn save(&self, dir: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let name = format!("report_{}", Utc::now().timestamp());
+        let json_path = Path::new(dir).join(format!("{}.json", name));
+        let png_path = Path::new(dir).join(format!("{}.png", name));
+        fs::write(&json_path, serde_json::to_string_pretty(&self)?)?;
+        self.render().save(&png_path)?;
+        log::info!("Mission Report Saved: {:?}", json_path);
+        Ok(png_path.to_string_lossy().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coverage_test() {
+        let mut coverage = Coverage::new(0.5);
+        coverage.mark(&Pose {
+            x: 0.1,
+            y: 0.1,
+            theta: 0.0,
+        });
+        coverage.mark(&Pose {
+            x: 0.4,
+            y: 0.2,
+            theta: 0.0,
+        });
+        coverage.mark(&Pose {
+            x: -0.1,
+            y: 0.2,
+            theta: 0.0,
+        });
+        assert_eq!(coverage.cells.len(), 2);
+        assert!(coverage.cells.contains(&(-1, 0)));
+        assert_eq!(coverage.area(), 0.5);
+    }
+
+    #[test]
+    fn person_stop_count_test() {
+        let mut log = MissionLog::new(0.25);
+        log.person_stop();
+        log.person_stop();
+        log.person_clear();
+        log.person_stop();
+        assert_eq!(log.person_stops, 2);
+        assert_eq!(log.person_resumes, 1);
+        assert!(log.is_person_stopping());
+        assert!(log.person_warn());
+        assert!(!log.person_warn());
+        log.person_clear();
+        assert!(log.person_warn());
+        assert_eq!(log.person_warns, 2);
+        assert_eq!(log.person_resumes, 2);
+    }
+
+    #[test]
+    fn mission_start_test() {
+        let mut log = MissionLog::new(0.25);
+        log.start_time = 0;
+        log.start(12.0);
+        assert!(0 < log.start_time);
+        assert_eq!(log.distance(20.0), 8.0);
+        // Turning on again after a pause continues the mission
+        let start_time = log.start_time;
+        log.start(20.0);
+        assert_eq!(log.start_time, start_time);
+        assert_eq!(log.distance(20.0), 8.0);
+    }
+
+    #[test]
+    fn reach_test() {
+        let mut log = MissionLog::new(0.25);
+        // The mission starts at a pylon mapped in the earlier runs
+        assert!(!log.reach(2));
+        assert!(!log.reach(3));
+        assert!(!log.reach(0));
+        assert!(log.reach(2));
+        assert!(!log.reach(3));
+        assert!(log.reach(2));
+        assert_eq!(log.pylons_reached(), 3);
+    }
+
+    #[test]
+    fn render_test() {
+        let report = MissionReport {
+            site: String::from("test"),
+            mode: String::from("Fill"),
+            start_time: String::new(),
+            end_time: String::new(),
+            duration_sec: 0,
+            laps: 0,
+            rest: 0.0,
+            bumps: 0,
+            person_stops: 0,
+            person_resumes: 0,
+            person_warns: 0,
+            distance: 0.0,
+            covered_area: 0.0,
+            field_area: 0.0,
+            cell_size: 0.5,
+            coverage: vec![(0, 0), (1, 0), (2, 1)],
+            pylons: vec![],
+        };
+        let img = report.render();
+        // 3 x 2 cells with margins
+        assert_eq!(img.width(), (3 + 8) * PX_PER_CELL);
+        assert_eq!(img.height(), (2 + 8) * PX_PER_CELL);
+        assert_eq!(
+            *img.get_pixel(4 * PX_PER_CELL, (2 + 8 - 1 - 4) * PX_PER_CELL),
+            Rgb([80, 180, 80])
+        );
+    }
+}
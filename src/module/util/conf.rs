@@ -1,6 +1,7 @@
 //! Config Handler.
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Provides TOML config file handling.
 pub mod toml {
@@ -68,6 +69,46 @@ pub struct Config {
     pub vision: Vision,
     pub notification: Notification,
     pub detectthreshold: DetectThreshold,
+    #[serde(default)]
+    pub stuck: Stuck,
+    #[serde(default)]
+    pub map: Map,
+    #[serde(default)]
+    pub report: Report,
+    #[serde(default)]
+    pub follow: Follow,
+    #[serde(default)]
+    pub round_trip: RoundTrip,
+    #[serde(default)]
+    pub patrol: Patrol,
+    #[serde(default)]
+    pub keepout: Keepout,
+    #[serde(default)]
+    pub edge: Edge,
+    #[serde(default)]
+    pub person: Person,
+    #[serde(default)]
+    pub blade: Blade,
+    #[serde(default)]
+    pub work: Work,
+    #[serde(default)]
+    pub speed: Speed,
+    #[serde(default)]
+    pub route: Route,
+    #[serde(default)]
+    pub lap: Lap,
+    #[serde(default)]
+    pub survey: Survey,
+    #[serde(default)]
+    pub nms: Nms,
+    #[serde(default)]
+    pub tracker: Tracker,
+    #[serde(default)]
+    pub geometry: Geometry,
+    #[serde(default)]
+    pub color: Color,
+    #[serde(default)]
+    pub tag: Tag,
 }
 
 /// Represents system-related configuration parameters.
@@ -98,6 +139,15 @@ pub struct Camera {
     pub grab_times: u8,
     pub width: u16,
     pub height: u16,
+    #[serde(default = "default_hfov")]
+    pub hfov: f32,
+    #[serde(default)]
+    pub undistort: bool,
+}
+
+/// Horizontal field of view of the Raspberry Pi Camera Module v2 (degrees).
+fn default_hfov() -> f32 {
+    62.2
 }
 
 /// Represents pin-related configuration parameters.
@@ -142,6 +192,439 @@ pub struct DetectThreshold {
     pub roktrack: f32,
 }
 
+/// Represents stuck detection-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Stuck {
+    pub enable: bool,
+    pub motion_threshold: f32,
+    pub duration: u64,
+    pub max_retry: u8,
+}
+
+impl Default for Stuck {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            motion_threshold: 0.02,
+            duration: 5000,
+            max_retry: 3,
+        }
+    }
+}
+
+/// Represents field map-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Map {
+    pub site: String,
+    pub speed: f32,
+    pub turn_speed: f32,
+    pub reach_distance: f32,
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Self {
+            site: String::from("default"),
+            speed: 0.3,
+            turn_speed: 60.0,
+            reach_distance: 0.5,
+        }
+    }
+}
+
+/// Represents mission report-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Report {
+    pub cell_size: f32,
+    pub notify: bool,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Self {
+            cell_size: 0.25,
+            notify: true,
+        }
+    }
+}
+
+/// Represents person following-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Follow {
+    pub near: f32,
+    pub far: f32,
+    pub smoothing: f32,
+    pub turn_threshold: f32,
+    pub lost_timeout: u64,
+}
+
+impl Default for Follow {
+    fn default() -> Self {
+        Self {
+            near: 0.9,
+            far: 0.6,
+            smoothing: 0.5,
+            turn_threshold: 0.3,
+            lost_timeout: 10000,
+        }
+    }
+}
+
+/// Represents round trip-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RoundTrip {
+    pub dwell: u64,
+    pub trips: u32,
+    pub start: Endpoint,
+    pub end: Endpoint,
+}
+
+/// Represents an endpoint of the round trip.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct Endpoint {
+    pub class: String,
+    pub id: Option<u8>,
+}
+
+impl Default for RoundTrip {
+    fn default() -> Self {
+        Self {
+            dwell: 0,
+            trips: 0,
+            start: Endpoint {
+                class: String::from("pylon"),
+                id: None,
+            },
+            end: Endpoint {
+                class: String::from("person"),
+                id: None,
+            },
+        }
+    }
+}
+
+/// Represents patrol-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Patrol {
+    pub power: f64,
+    pub scan_steps: u8,
+    pub animal: bool,
+    pub notify_interval: u64,
+}
+
+impl Default for Patrol {
+    fn default() -> Self {
+        Self {
+            power: 0.6,
+            scan_steps: 8,
+            animal: true,
+            notify_interval: 60000,
+        }
+    }
+}
+
+/// Represents keep-out pylon-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Keepout {
+    pub ids: Vec<u8>,
+    pub clearance: f32,
+}
+
+impl Default for Keepout {
+    fn default() -> Self {
+        Self {
+            ids: vec![],
+            clearance: 1.5,
+        }
+    }
+}
+
+/// Represents edge trimming-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Edge {
+    pub enable: bool,
+    pub offset: f32,
+    pub power: f64,
+    pub markers: u32,
+}
+
+impl Default for Edge {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            offset: 0.15,
+            power: 0.7,
+            markers: 0,
+        }
+    }
+}
+
+/// Represents person response-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Person {
+    pub stop: f32,
+    pub holdoff: u64,
+}
+
+impl Default for Person {
+    fn default() -> Self {
+        Self {
+            stop: 0.3,
+            holdoff: 5000,
+        }
+    }
+}
+
+/// Represents blade control-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Blade {
+    pub policy: String,
+    pub spin_up: u64,
+    pub spin_down: u64,
+}
+
+impl Default for Blade {
+    fn default() -> Self {
+        Self {
+            policy: String::from("always"),
+            spin_up: 0,
+            spin_down: 0,
+        }
+    }
+}
+
+/// Represents work motor-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Work {
+    pub motors: Vec<WorkMotor>,
+    pub stagger: u64,
+}
+
+/// Represents a work motor.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct WorkMotor {
+    pub pin: u8,
+    pub positive: bool,
+}
+
+/// Represents speed scheduling-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Speed {
+    pub min: f64,
+    pub max: f64,
+    pub turn: f64,
+    pub slow_zone: f64,
+    pub ramp_frames: u32,
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Self {
+            min: 0.6,
+            max: 1.0,
+            turn: 1.0,
+            slow_zone: 0.5,
+            ramp_frames: 3,
+        }
+    }
+}
+
+/// Represents route-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Route {
+    pub waypoints: Vec<Waypoint>,
+    pub laps: u32,
+    pub blade: bool,
+}
+
+/// Represents a waypoint of the route.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Waypoint {
+    pub id: u8,
+    #[serde(default)]
+    pub blade: Option<bool>,
+    #[serde(default)]
+    pub pause: u64,
+    #[serde(default)]
+    pub announce: bool,
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        Self {
+            waypoints: vec![],
+            laps: 1,
+            blade: true,
+        }
+    }
+}
+
+/// Represents lap counting-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Lap {
+    pub enable: bool,
+    pub shrink: f32,
+    pub min_pylons: usize,
+}
+
+impl Default for Lap {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            shrink: 0.1,
+            min_pylons: 3,
+        }
+    }
+}
+
+/// Represents survey-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Survey {
+    pub steps: u8,
+    pub merge: f32,
+    pub max_gap: f32,
+    pub min_height: f32,
+}
+
+impl Default for Survey {
+    fn default() -> Self {
+        Self {
+            steps: 8,
+            merge: 8.0,
+            max_gap: 120.0,
+            min_height: 0.05,
+        }
+    }
+}
+
+/// Represents non-maximum suppression-related configuration parameters of each model.
+///
+/// Unset ones fall back to the defaults of the model manifest.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Nms {
+    #[serde(default)]
+    pub pylon: Option<NmsParam>,
+    #[serde(default)]
+    pub animal: Option<NmsParam>,
+    #[serde(default)]
+    pub ocr: Option<NmsParam>,
+}
+
+/// Represents the thresholds of non-maximum suppression.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct NmsParam {
+    pub score: f32,
+    pub iou: f32,
+    #[serde(default)]
+    pub soft: bool,
+    #[serde(default = "default_sigma")]
+    pub sigma: f32,
+}
+
+/// Gaussian decay of soft-NMS.
+fn default_sigma() -> f32 {
+    0.5
+}
+
+impl Default for NmsParam {
+    fn default() -> Self {
+        Self {
+            score: 0.1,
+            iou: 0.5,
+            soft: false,
+            sigma: default_sigma(),
+        }
+    }
+}
+
+/// Represents tracker-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Tracker {
+    pub iou: f32,
+    pub max_misses: u8,
+    pub smoothing: f32,
+    #[serde(default = "default_votes")]
+    pub votes: u8,
+}
+
+/// Frames reading the same ids to accept them.
+fn default_votes() -> u8 {
+    3
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self {
+            iou: 0.3,
+            max_misses: 3,
+            smoothing: 0.5,
+            votes: default_votes(),
+        }
+    }
+}
+
+/// Represents the known sizes for the distance estimation.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Geometry {
+    pub reach: f32,
+    pub heights: HashMap<String, f32>,
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self {
+            reach: 0.0,
+            heights: HashMap::from([("pylon".to_string(), 0.4), ("person".to_string(), 1.6)]),
+        }
+    }
+}
+
+/// Represents the color-based pylon detector configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Color {
+    pub fallback: bool,
+    pub verify: bool,
+    pub hue_min: f32,
+    pub hue_max: f32,
+    pub saturation: f32,
+    pub value: f32,
+    pub min_area: f32,
+    pub min_ratio: f32,
+}
+
+impl Default for Color {
+    fn default() -> Self {
+        Self {
+            fallback: false,
+            verify: false,
+            hue_min: 0.0,
+            hue_max: 30.0,
+            saturation: 0.5,
+            value: 0.35,
+            min_area: 0.0005,
+            min_ratio: 0.15,
+        }
+    }
+}
+
+/// Represents the fiducial tag configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Tag {
+    pub id_source: String,
+    pub frame: bool,
+    pub max_hamming: u32,
+}
+
+impl Default for Tag {
+    fn default() -> Self {
+        Self {
+            id_source: String::from("ocr"),
+            frame: false,
+            max_hamming: 1,
+        }
+    }
+}
+
 // Default configuration data in TOML format
 const DEFAULT_CONFIG: &str = r#"
 [system]
@@ -154,7 +637,7 @@ const DEFAULT_CONFIG: &str = r#"
 
 [drive]
   default_state = 'on' # Default state of the drive ('on' or 'off')
-  mode = 'fill' # Drive mode ('fill', 'oneway', 'climb')
+  mode = 'fill' # Drive mode ('fill', 'oneway', 'climb', 'patrol', 'route', 'survey')
   minimum_pylon_height = 0 # Minimum pylon height for operations
   turn_adj = 1 # Turn adjustment factor
   motor_driver = 'ZK_5AD' # Motor driver type ('ZK_5AD', 'IRF3205')
@@ -164,6 +647,8 @@ const DEFAULT_CONFIG: &str = r#"
   grab_times = 3 # Number of image grabs
   width = 1280 # Image width
   height = 720 # Image height
+  hfov = 62.2 # Horizontal field of view in degrees
+  undistort = false # Undistort the detections with the lens calibration (run 'roktrack calibrate' first)
 
 [pin]
   left_pin1 = 22 # Left motor control pin 1 (DIGITAL)
@@ -191,6 +676,117 @@ const DEFAULT_CONFIG: &str = r#"
   person = 0.7 # Detection threshold for people
   animal = 0 # Detection threshold for animals
   roktrack = 0.5 # Detection threshold for Roktrack objects
+
+[stuck]
+  enable = true # Enable stuck detection while driving forward
+  motion_threshold = 0.02 # Frame-to-frame change below this is regarded as no motion (0.0 - 1.0)
+  duration = 5000 # Milliseconds without motion before regarded as stuck
+  max_retry = 3 # Number of recovery maneuvers before halting
+
+[map]
+  site = 'default' # Site name used for the map file name
+  speed = 0.3 # Travelling speed at full power (m/s) for dead reckoning
+  turn_speed = 60.0 # Turning speed (deg/s) for dead reckoning
+  reach_distance = 0.5 # Distance to the pylon when the approach ends (m)
+
+[report]
+  cell_size = 0.25 # Cell size of the coverage raster (m)
+  notify = true # Send the mission report through LINE Notify
+
+[follow]
+  near = 0.9 # Back off when the person's height exceeds this ratio of the image height
+  far = 0.6 # Approach when the person's height is below this ratio of the image height
+  smoothing = 0.5 # Smoothing factor of the bearing (0.0: none -> 1.0: frozen)
+  turn_threshold = 0.3 # Turn on the spot when the smoothed bearing exceeds this (-1.0 -> 1.0)
+  lost_timeout = 10000 # Milliseconds to search for the lost person before stopping
+
+[round_trip]
+  dwell = 0 # Milliseconds to stay at each endpoint
+  trips = 0 # Number of round trips before completing (0 for endless)
+  start = { class = 'pylon' } # Start endpoint ('pylon', 'person', 'roktrack'), add id = n to select an OCR id
+  end = { class = 'person' } # End endpoint
+
+[patrol]
+  power = 0.6 # Upper limit of the drive motor power while patrolling (0.4 - 1.0)
+  scan_steps = 8 # Number of stops in the 360 degree scan at each pylon
+  animal = true # Scan with the animal model as well
+  notify_interval = 60000 # Minimum milliseconds between notifications of the same kind
+
+[keepout]
+  ids = [] # OCR ids of pylons surrounding flowerbeds or trees, e.g. [8, 9] (requires vision.ocr)
+  clearance = 1.5 # Distance to pass by the keep-out pylon in pylon widths
+
+[edge]
+  enable = false # Trim the edge along the pylon line in the first lap of Fill
+  offset = 0.15 # Shift to the side of the pylon when approaching (ratio of half the image width, 0.4 in normal laps)
+  power = 0.7 # Upper limit of the drive motor power in the edge lap (0.4 - 1.0)
+  markers = 0 # Number of pylons in the perimeter to end the edge lap (0 to end when the map detects a lap, or without OCR, at the pylons mapped in the earlier runs)
+
+[person]
+  stop = 0.3 # Stop when the person's height exceeds this ratio of the image height, otherwise only warn
+  holdoff = 5000 # Milliseconds without a person before resuming
+
+[blade]
+  policy = 'always' # When the blade runs while mowing ('forward', 'turn' for forward and turns, 'always')
+  spin_up = 0 # Milliseconds to wait before driving forward after the blade starts
+  spin_down = 0 # Milliseconds to wait before reversing after the blade stops
+
+[work]
+  motors = [] # Work motors, e.g. [{ pin = 14, positive = false }, { pin = 17, positive = true }] (empty to use work1_pin and work2_pin)
+  stagger = 0 # Milliseconds between the starts of the work motors to limit the inrush current
+
+[speed]
+  min = 0.6 # Lower limit of the speed scale (the drive motor power is multiplied by the scale, but the duty stays at 0.4 or more)
+  max = 1.0 # Upper limit of the speed scale
+  turn = 1.0 # Speed scale while turning (limited to min - max)
+  slow_zone = 0.5 # Start slowing down when the marker height exceeds this ratio of the target height
+  ramp_frames = 3 # Number of frames tracking the marker before reaching full speed
+
+[route]
+  waypoints = [] # OCR ids of the pylons to visit in order, e.g. [{ id = 1 }, { id = 4, pause = 5000 }, { id = 2, blade = false, announce = true }]
+  laps = 1 # Number of passes over the route before completing (0 for endless)
+  blade = true # Run the blade when starting the route (each waypoint can switch it with blade = true / false)
+
+[lap]
+  enable = false # Shrink the laps of Fill by the counted laps instead of the constant per pylon (requires vision.ocr, which then stays on for all the pylons)
+  shrink = 0.1 # Decrease of rest per lap (0.1 for 10 laps in each direction)
+  min_pylons = 3 # Minimum number of pylons in a lap to learn the sequence of OCR ids
+
+[survey]
+  steps = 8 # Number of stops in the 360 degree scan (for each of 320 and 640)
+  merge = 8.0 # Sightings closer than this in degrees are regarded as the same pylon
+  max_gap = 120.0 # Warn when no pylon is visible over this angle in degrees
+  min_height = 0.05 # Warn when the pylon height is below this ratio of the image height
+
+[nms] # Uncomment to override the defaults of the model manifest (asset/model/manifest.toml)
+  # pylon = { score = 0.1, iou = 0.5 } # Drop candidates below score, suppress overlaps of the same class at iou or more
+  # animal = { score = 0.1, iou = 0.5 } # Add soft = true to decay overlapping candidates by exp(-iou^2 / sigma) instead (sigma = 0.5)
+  # ocr = { score = 0.1, iou = 0.5 } # Digit OCR model
+
+[tracker]
+  iou = 0.3 # Minimum IoU between the predicted track and a detection to associate them
+  max_misses = 3 # Drop a track after this many frames without a detection
+  smoothing = 0.5 # Weight of the new bbox height (1.0 for no smoothing)
+  votes = 3 # Frames that must read the same pylon ids before they are accepted
+
+[geometry]
+  reach = 0.0 # Distance to the pylon to regard it as reached (m, 0.0 for 90% of the image height)
+  heights = { pylon = 0.4, person = 1.6 } # Known heights of the classes for the distance estimation (m)
+
+[color]
+  fallback = false # Detect pylons by color when the pylon model can't be loaded. The blade stays off, persons can't be detected
+  verify = false # Reject the pylons of the model without enough cone color
+  hue_min = 0.0 # Hue range of the cone color in degrees (hue_min > hue_max wraps around red)
+  hue_max = 30.0
+  saturation = 0.5 # Minimum saturation of the cone color (0.0 -> 1.0)
+  value = 0.35 # Minimum brightness of the cone color (0.0 -> 1.0)
+  min_area = 0.0005 # Minimum area of a pylon as a ratio of the image
+  min_ratio = 0.15 # Minimum share of the cone color in a verified pylon box
+
+[tag]
+  id_source = 'ocr' # Source of the pylon ids with vision.ocr ('ocr', 'tag' for AprilTag 16h5 tags on the pylons, or 'both')
+  frame = false # Read the tags on the full frame instead of the crops of the pylons
+  max_hamming = 1 # Bit errors of a tag to correct (0 -> 2)
 "#;
 
 #[cfg(test)]
@@ -206,4 +802,12 @@ mod tests {
         let res = toml::load("/tmp/roktracktest/");
         assert_eq!(res.unwrap().system.lang, "ja");
     }
+
+    #[test]
+    fn missing_section_test() {
+        // Config files written by older versions don't have the newer sections.
+        let conf: Config =
+            ::toml::from_str(&DEFAULT_CONFIG.replace("[stuck]", "[unused]")).unwrap();
+        assert_eq!(conf.stuck.max_retry, Stuck::default().max_retry);
+    }
 }
@@ -125,6 +125,10 @@ pub mod dir {
             .expect("Can't create IMG_DIR");
         let log_dir = create_dir_from_path_list(&[&data_dir, define::path::LOG_DIR])
             .expect("Can't create LOG_DIR");
+        let map_dir = create_dir_from_path_list(&[&data_dir, define::path::MAP_DIR])
+            .expect("Can't create MAP_DIR");
+        let report_dir = create_dir_from_path_list(&[&data_dir, define::path::REPORT_DIR])
+            .expect("Can't create REPORT_DIR");
         let last_img = super::join(&[&tmp_dir, define::path::LAST_IMAGE]);
         let crop_img = super::join(&[&tmp_dir, define::path::CROP_IMAGE]);
         RoktrackPath {
@@ -133,6 +137,8 @@ pub mod dir {
                 tmp: tmp_dir.clone(),
                 img: img_dir,
                 log: log_dir,
+                map: map_dir,
+                report: report_dir,
             },
             img: RoktrackImg {
                 last: super::join(&[tmp_dir.as_str(), last_img.as_str()]),
@@ -166,6 +172,10 @@ pub struct RoktrackDir {
     pub img: String,
     /// Log Directory Path
     pub log: String,
+    /// Map Directory Path
+    pub map: String,
+    /// Report Directory Path
+    pub report: String,
 }
 
 /// Paths of Images
@@ -241,6 +251,12 @@ mod tests {
         // Assert that the log directory was created
         assert!(Path::new("/data/roktrack/log").is_dir());
 
+        // Assert that the map directory was created
+        assert!(Path::new("/data/roktrack/map").is_dir());
+
+        // Assert that the report directory was created
+        assert!(Path::new("/data/roktrack/report").is_dir());
+
         // Assert that the last image path matches the expected path
         assert_eq!(res.img.last, "/run/user/1000/roktrack/vision.jpg");
 
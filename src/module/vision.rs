@@ -11,13 +11,19 @@ use std::{
     time::Duration,             // For representing time intervals
 };
 
-// Import the Detection type from the detector submodule
-use self::detector::Detection;
+// Import the Detection type and the Detector trait from the detector submodule
+use self::detector::{color::ColorDetector, onnx::YoloV8, Detection, Detector};
 // Import the RoktrackProperty type from the init submodule in the util module
 use super::util::init::RoktrackProperty;
 
+pub mod calibration; // Declare the calibration submodule
 pub mod camera; // Declare the camera submodule
 pub mod detector; // Declare the detector submodule
+pub mod geometry; // Declare the geometry submodule
+pub mod manifest; // Declare the manifest submodule
+pub mod motion; // Declare the motion submodule
+pub mod tag; // Declare the tag submodule
+pub mod tracker; // Declare the tracker submodule
 
 /// This enum defines the commands that can be used to control the vision thread.
 pub enum VisionMgmtCommand {
@@ -77,32 +83,50 @@ impl RoktrackVision {
                 }
                 Ok(VisionMgmtCommand::On) => {
                     *local_state.lock().unwrap() = true;
+                    // The scene has changed while off, so don't compare with the old frame
+                    local_self.lock().unwrap().motion.reset();
+                    local_self.lock().unwrap().tracker.reset();
                 } // If the command is On, do nothing and proceed
                 Ok(VisionMgmtCommand::SwitchSessionPylon) => {
                     log::debug!("Vision VisionMgmtCommand::SwitchSessionPylon Received");
-                    let _ = local_self.lock().unwrap().det.build_pylon_sessions();
+                    match pylon_detector(&local_property, false) {
+                        Ok((det, fallback)) => {
+                            let mut inner = local_self.lock().unwrap();
+                            inner.set_detector(det);
+                            inner.fallback = fallback;
+                        }
+                        Err(e) => log::error!("Can't Load Pylon Model: {}", e),
+                    }
                 }
                 Ok(VisionMgmtCommand::SwitchSessionPylonOcr) => {
                     log::debug!("Vision VisionMgmtCommand::SwitchSessionPylonOcr Received");
-                    // If the command is SwitchSessionPylonOcr, lock the inner field and update the detector sessions with the pylon OCR sessions
-                    let _ = local_self.lock().unwrap().det.build_pylon_ocr_sessions();
+                    // If the command is SwitchSessionPylonOcr, lock the inner field and replace the detector with the pylon OCR model family
+                    match pylon_detector(&local_property, true) {
+                        Ok((det, fallback)) => {
+                            let mut inner = local_self.lock().unwrap();
+                            inner.set_detector(det);
+                            inner.fallback = fallback;
+                        }
+                        Err(e) => log::error!("Can't Load Pylon OCR Model: {}", e),
+                    }
                 }
                 Ok(VisionMgmtCommand::SwitchSessionAnimal) => {
                     log::debug!("Vision VisionMgmtCommand::SwitchSessionAnimal Received");
-                    // If the command is SwitchSessionAnimal, lock the inner field and update the detector sessions with the animal sessions
-                    let _ = local_self.lock().unwrap().det.build_animal_sessions();
+                    // If the command is SwitchSessionAnimal, lock the inner field and replace the detector with the animal model family
+                    match YoloV8::animal(&local_property.conf.nms) {
+                        Ok(det) => local_self.lock().unwrap().set_detector(Box::new(det)),
+                        Err(e) => log::error!("Can't Load Animal Model: {}", e),
+                    }
                 }
                 Ok(VisionMgmtCommand::SwitchSz320) => {
                     log::debug!("Vision VisionMgmtCommand::SwitchSz320 Received");
-                    // If the command is SwitchSz320, lock the inner field and update the detector session type with Sz320
-                    local_self.lock().unwrap().det.session_type =
-                        detector::onnx::SessionType::Sz320;
+                    // If the command is SwitchSz320, lock the inner field and update the detector input size with 320
+                    local_self.lock().unwrap().det.set_input_size(320);
                 }
                 Ok(VisionMgmtCommand::SwitchSz640) => {
                     log::debug!("Vision VisionMgmtCommand::SwitchSz640 Received");
-                    // If the command is SwitchSz640, lock the inner field and update the detector session type with Sz640
-                    local_self.lock().unwrap().det.session_type =
-                        detector::onnx::SessionType::Sz640;
+                    // If the command is SwitchSz640, lock the inner field and update the detector input size with 640
+                    local_self.lock().unwrap().det.set_input_size(640);
                 }
                 Err(_) => {} // If there is no command or an error, do nothing and proceed
             }
@@ -116,21 +140,27 @@ impl RoktrackVision {
             // Take an image using the camera
             {
                 let mut visual_info = VisualInfo::new();
+                visual_info.fallback = local_self.lock().unwrap().fallback;
                 log::debug!("Vision Camera Process Start");
                 visual_info.shooting_start_time = chrono::Utc::now().timestamp_millis() as u64;
                 let res_take = local_self.lock().unwrap().cam.take_picture(); // Lock the inner field and call the take method on the camera field
                 visual_info.shooting_end_time = chrono::Utc::now().timestamp_millis() as u64;
                 log::debug!("Vision Camera Process End");
                 if res_take.is_ok() {
-                    let session_type = local_self.lock().unwrap().det.session_type.clone(); // Lock the inner field and clone the session type from the detector field
-                    log::debug!("Session_type:{:?}", session_type.clone());
-                    let dets = local_self // Lock the inner field and call the infer method on the detector field with the image path and session type as arguments
+                    // Measure how much the scene has changed since the last frame
+                    let frame = image::open(&local_property.path.img.last).ok();
+                    if let Some(img) = &frame {
+                        visual_info.motion = local_self.lock().unwrap().motion.update(img);
+                        log::debug!("Vision Motion: {:?}", visual_info.motion);
+                    }
+                    let input_size = local_self.lock().unwrap().det.input_size(); // Lock the inner field and get the input size from the detector field
+                    log::debug!("Input_size:{:?}", input_size);
+                    let dets = local_self // Lock the inner field and call the infer method on the detector field with the image path as an argument
                         .lock()
                         .unwrap()
                         .det
                         .infer(
                             &local_property.path.img.last,
-                            session_type,
                             &format!(
                                 "{}/{}.jpg",
                                 local_property.path.dir.img, visual_info.shooting_end_time,
@@ -138,6 +168,15 @@ impl RoktrackVision {
                         );
                     let mut dets = dets.unwrap();
                     log::debug!("Vision Detected: {:?}", dets.clone(),);
+                    // Reject the pylons without the cone color, e.g. orange toys
+                    if local_property.conf.color.verify {
+                        let inner = local_self.lock().unwrap();
+                        let pylon_model =
+                            inner.det.classes().first().map(String::as_str) == Some("pylon");
+                        if let (true, Some(img)) = (pylon_model, &frame) {
+                            dets = inner.color.verify(img, dets);
+                        }
+                    }
                     // Handle ocr
                     let ocr_support = local_self.lock().unwrap().det.support_ocr();
                     if ocr_support {
@@ -153,6 +192,29 @@ impl RoktrackVision {
                             .unwrap();
                         log::debug!("Vision Detected With Ocr: {:?}", dets.clone());
                     }
+                    // Read the ids from the tags on the pylons
+                    {
+                        let inner = local_self.lock().unwrap();
+                        let pylon_model =
+                            inner.det.classes().first().map(String::as_str) == Some("pylon");
+                        if let (true, true, Some(img)) = (inner.tag.enabled(), pylon_model, &frame)
+                        {
+                            dets = inner.tag.read(img, dets);
+                            log::debug!("Vision Detected With Tags: {:?}", dets.clone());
+                        }
+                    }
+                    // Undistort the detections with the lens calibration
+                    if let Some(calibration) = &local_self.lock().unwrap().calibration {
+                        dets.iter_mut().for_each(|det| calibration.undistort(det));
+                    }
+                    // Associate with the detections of the earlier frames
+                    dets = local_self.lock().unwrap().tracker.update(dets);
+                    log::debug!("Vision Tracked: {:?}", dets.clone());
+                    // Estimate the distance and the bearing
+                    {
+                        let inner = local_self.lock().unwrap();
+                        inner.geometry.estimate(&mut dets, &inner.det.classes());
+                    }
                     visual_info.detections = dets;
                     tx.send(visual_info).unwrap(); // Send the detection results to other threads using the sender
                 }
@@ -166,6 +228,8 @@ pub struct VisualInfo {
     pub shooting_start_time: u64,
     pub shooting_end_time: u64,
     pub detections: Vec<Detection>,
+    pub motion: Option<f32>, // Frame-to-frame change (0.0 -> 1.0), None for the first frame
+    pub fallback: bool, // The pylons are detected by color without the model, so persons can't be detected
 }
 
 impl VisualInfo {
@@ -174,6 +238,8 @@ impl VisualInfo {
             shooting_start_time: 0,
             shooting_end_time: 0,
             detections: vec![],
+            motion: None,
+            fallback: false,
         }
     }
 }
@@ -187,18 +253,116 @@ impl Default for VisualInfo {
 /// This struct contains the fields for the camera and the detector that are used for image processing.
 pub struct RoktrackVisionInner {
     pub cam: camera::V4l2Camera, // The camera field that uses the V4l2 module
-    pub det: detector::onnx::YoloV8, // The detector field that uses the YoloV8 module with onnx runtime
+    pub det: Box<dyn Detector>,  // The detector field, YoloV8 with onnx runtime by default
+    pub motion: motion::MotionEstimator, // The motion field that compares consecutive frames
+    pub tracker: tracker::Tracker, // The tracker field that follows the detections across frames
+    pub geometry: geometry::Geometry, // The geometry field that estimates the distance and the bearing
+    pub calibration: Option<calibration::Calibration>, // The calibration field that undistorts the detections, if enabled
+    pub color: ColorDetector, // The color field that verifies the pylons of the model
+    pub tag: tag::TagReader,  // The tag field that reads the ids from the tags on the pylons
+    pub fallback: bool, // Whether the detector is the color detector standing in for the pylon model
 }
 
 /// This impl block defines the methods for the RoktrackVisionInner struct.
 impl RoktrackVisionInner {
     /// This method creates a new instance of the RoktrackVisionInner struct with the given property.
     pub fn new(property: RoktrackProperty) -> Self {
+        let (det, fallback) =
+            pylon_detector(&property, false).expect("Can't initialize PYLON_MODEL");
         Self {
             // Create a new camera::V4l2 instance by calling the new method on the V4l2 module and passing the property
             cam: camera::V4l2Camera::new(property.clone()),
             // Create a new detector::onnx::YoloV8 instance by calling the new method on the YoloV8 module
-            det: detector::onnx::YoloV8::new(),
+            det,
+            // Create a new motion::MotionEstimator instance with no previous frame
+            motion: motion::MotionEstimator::new(),
+            // Create a new tracker::Tracker instance with no tracks
+            tracker: tracker::Tracker::new(property.conf.tracker.clone()),
+            // Create a new geometry::Geometry instance with the camera and the known heights
+            geometry: geometry::Geometry::new(&property.conf),
+            // Load the lens calibration if the undistortion is enabled
+            calibration: load_calibration(&property),
+            // Create a new ColorDetector instance with the cone color
+            color: ColorDetector::new(property.conf.color.clone()),
+            // Create a new TagReader instance with the id source
+            tag: tag::TagReader::new(property.conf.tag.clone()),
+            fallback,
+        }
+    }
+
+    /// Replace the detector, keeping the input size.
+    pub fn set_detector(&mut self, mut det: Box<dyn Detector>) {
+        det.set_input_size(self.det.input_size());
+        log::debug!("Detector Switched. classes: {:?}", det.classes());
+        self.det = det;
+        self.fallback = false;
+        // The tracks of the other model don't apply
+        self.tracker.reset();
+    }
+}
+
+/// Build the pylon detector.
+///
+/// If only the digit model can't be loaded, the pylons are detected without the ids.
+/// Falls back to the color detector if the pylon model can't be loaded either and the fallback is enabled.
+///
+/// # Arguments
+///
+/// * `property` - RoktrackProperty holding the NMS, color and tag configs.
+/// * `ocr` - Whether to read the ids of the pylons. The digit model isn't loaded if the ids come from the tags only.
+///
+/// # Returns
+///
+/// The detector and whether it is the color detector standing in for the model.
+///
+fn pylon_detector(
+    property: &RoktrackProperty,
+    ocr: bool,
+) -> Result<(Box<dyn Detector>, bool), Box<dyn std::error::Error>> {
+    if ocr && property.conf.tag.id_source != "tag" {
+        match YoloV8::pylon_ocr(&property.conf.nms) {
+            Ok(det) => return Ok((Box::new(det), false)),
+            Err(e) => log::error!("Can't Load Pylon OCR Model. Detect Without Ids: {}", e),
+        }
+    }
+    match YoloV8::pylon(&property.conf.nms) {
+        Ok(det) => Ok((Box::new(det), false)),
+        Err(e) if property.conf.color.fallback => {
+            log::error!("Can't Load Pylon Model. Fall Back to Color Detector: {}", e);
+            Ok((
+                Box::new(ColorDetector::new(property.conf.color.clone())),
+                true,
+            ))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Load the lens calibration if the undistortion is enabled in the config.
+///
+/// The calibration is ignored if it was made with another image size.
+fn load_calibration(property: &RoktrackProperty) -> Option<calibration::Calibration> {
+    if !property.conf.camera.undistort {
+        return None;
+    }
+    match calibration::Calibration::load(&property.path.dir.data) {
+        Ok(calibration)
+            if (calibration.width, calibration.height)
+                == (
+                    property.conf.camera.width as u32,
+                    property.conf.camera.height as u32,
+                ) =>
+        {
+            log::info!("Lens Calibration Loaded: {:?}", calibration);
+            Some(calibration)
+        }
+        Ok(_) => {
+            log::error!("Lens Calibration Size Mismatch. Run Calibration Again.");
+            None
+        }
+        Err(e) => {
+            log::error!("Lens Calibration Not Loaded: {}", e);
+            None
         }
     }
 }
@@ -0,0 +1,844 @@
+//! Lens Calibration
+//!
+//! Wide-angle lenses bend straight lines (barrel distortion), so pylons at the edge of the frame
+//! look shorter than they are. The lens is calibrated from checkerboard images: the radial
+//! distortion (k1, k2) is first estimated by the plumb-line method (the rows and the columns of the
+//! corners must be straight once undistorted), then the focal length, the principal point and the
+//! distortion are refined together so that the undistorted corners match the projection of the
+//! checkerboard.
+//!
+//! The focal length and the principal point need the checkerboard from 3 or more poses (tilted
+//! differently). With fewer images they are kept from `camera.hfov` and the image center.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use image::{imageops::FilterType, DynamicImage, GrayImage};
+use serde::{Deserialize, Serialize};
+
+use super::detector::Detection;
+use crate::module::{define, util::conf::Config, util::init::RoktrackProperty};
+
+/// Camera intrinsics with radial distortion.
+///
+/// A distorted pixel p is undistorted as c + (p - c) * (1 + k1 * r^2 + k2 * r^4),
+/// where r is the distance from the principal point c in focal lengths.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Calibration {
+    pub width: u32,  // Image width of the calibration (pixels)
+    pub height: u32, // Image height of the calibration (pixels)
+    pub fx: f32,     // Focal length (pixels)
+    pub fy: f32,     // Focal length (pixels)
+    pub cx: f32,     // Principal point (pixels)
+    pub cy: f32,     // Principal point (pixels)
+    pub k1: f32,     // Radial distortion coefficient
+    pub k2: f32,     // Radial distortion coefficient
+}
+
+impl Calibration {
+    /// Pinhole camera without distortion from the camera config.
+    pub fn new(conf: &Config) -> Self {
+        let (width, height) = (conf.camera.width as u32, conf.camera.height as u32);
+        let focal = width as f32 / 2.0 / (conf.camera.hfov.to_radians() / 2.0).tan();
+        Self {
+            width,
+            height,
+            fx: focal,
+            fy: focal,
+            cx: width as f32 / 2.0,
+            cy: height as f32 / 2.0,
+            k1: 0.0,
+            k2: 0.0,
+        }
+    }
+
+    /// Path of the calibration file in the data directory.
+    fn path(dir: &str) -> String {
+        Path::new(dir)
+            .join(define::path::CALIBRATION_FILE)
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Loads the calibration from the data directory.
+    pub fn load(dir: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(Self::path(dir))?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    /// Saves the calibration to the data directory.
+    pub fn save(&self, dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+        fs::write(Self::path(dir), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Scale of a distorted point to undistort it.
+    fn factor(&self, x: f32, y: f32) -> f32 {
+        let (xn, yn) = ((x - self.cx) / self.fx, (y - self.cy) / self.fy);
+        let r2 = xn * xn + yn * yn;
+        1.0 + self.k1 * r2 + self.k2 * r2 * r2
+    }
+
+    /// Undistort a pixel.
+    pub fn undistort_point(&self, x: f32, y: f32) -> (f32, f32) {
+        let f = self.factor(x, y);
+        (self.cx + (x - self.cx) * f, self.cy + (y - self.cy) * f)
+    }
+
+    /// Distort a pixel, the inverse of `undistort_point` by fixed-point iteration.
+    pub fn distort_point(&self, x: f32, y: f32) -> (f32, f32) {
+        let (mut xd, mut yd) = (x, y);
+        for _ in 0..20 {
+            let f = self.factor(xd, yd);
+            xd = self.cx + (x - self.cx) / f;
+            yd = self.cy + (y - self.cy) / f;
+        }
+        (xd, yd)
+    }
+
+    /// Undistort the bbox of a detection.
+    ///
+    /// The corners and the midpoints of the edges are undistorted,
+    /// and the new bbox encloses them.
+    pub fn undistort(&self, det: &mut Detection) {
+        let (x1, y1, x2, y2) = (det.x1 as f32, det.y1 as f32, det.x2 as f32, det.y2 as f32);
+        let (xm, ym) = ((x1 + x2) / 2.0, (y1 + y2) / 2.0);
+        let points: Vec<(f32, f32)> = [
+            (x1, y1),
+            (xm, y1),
+            (x2, y1),
+            (x2, ym),
+            (x2, y2),
+            (xm, y2),
+            (x1, y2),
+            (x1, ym),
+        ]
+        .iter()
+        .map(|(x, y)| self.undistort_point(*x, *y))
+        .collect();
+        let min = |f: fn(&(f32, f32)) -> f32| points.iter().map(f).fold(f32::MAX, f32::min);
+        let max = |f: fn(&(f32, f32)) -> f32| points.iter().map(f).fold(f32::MIN, f32::max);
+        det.x1 = min(|p| p.0).max(0.0) as u32;
+        det.y1 = min(|p| p.1).max(0.0) as u32;
+        det.x2 = max(|p| p.0).max(0.0) as u32;
+        det.y2 = max(|p| p.1).max(0.0) as u32;
+        det.w = det.x2 - det.x1;
+        det.h = det.y2 - det.y1;
+        det.xc = (det.x1 + det.x2) as f32 / 2.0;
+        det.yc = (det.y1 + det.y2) as f32 / 2.0;
+    }
+
+    /// Fit the intrinsics and the distortion coefficients to the checkerboards.
+    ///
+    /// The distortion is first estimated by straightening the lines of the grids. Then the focal
+    /// length, the principal point, the distortion and the pose of each board are refined together
+    /// by Levenberg-Marquardt. The focal length and the principal point are kept if there are
+    /// fewer than `MIN_POSES` boards.
+    ///
+    /// # Arguments
+    ///
+    /// * `grids` - Distorted corners of the checkerboards, one grid per image.
+    ///
+    /// # Returns
+    ///
+    /// The remaining RMS error of the corners (pixels).
+    pub fn fit(&mut self, grids: &[Grid]) -> f32 {
+        let lines: Vec<Vec<(f32, f32)>> = grids.iter().flat_map(find_lines).collect();
+        self.fit_distortion(&lines);
+
+        // Initial poses of the boards from their homographies through the current intrinsics
+        let mut params = vec![
+            self.fx as f64,
+            self.fy as f64,
+            self.cx as f64,
+            self.cy as f64,
+            self.k1 as f64,
+            self.k2 as f64,
+        ];
+        let mut boards = vec![];
+        for grid in grids {
+            let [r1, r2, t] = match self.pose(grid) {
+                Some(pose) => pose,
+                None => continue, // Corners on a line
+            };
+            // The board points are rotated by the initial pose once, the fit rotates them further
+            let board: Board = grid
+                .iter()
+                .map(|((i, j), (x, y))| {
+                    let (i, j) = (*i as f64, *j as f64);
+                    let point = [0, 1, 2].map(|n| r1[n] * i + r2[n] * j);
+                    (point, (*x as f64, *y as f64))
+                })
+                .collect();
+            boards.push(board);
+            params.extend([0.0, 0.0, 0.0, t[0], t[1], t[2]]);
+        }
+
+        // The intrinsics are free only with enough poses, the distortion and the poses always are
+        let first = if MIN_POSES <= boards.len() { 0 } else { 4 };
+        let free: Vec<usize> = (first..params.len()).collect();
+        let cost = match levenberg_marquardt(&mut params, &free, |p| residuals(p, &boards)) {
+            Some(cost) => cost,
+            None => return f32::MAX,
+        };
+        (self.fx, self.fy, self.cx, self.cy) = (
+            params[0] as f32,
+            params[1] as f32,
+            params[2] as f32,
+            params[3] as f32,
+        );
+        (self.k1, self.k2) = (params[4] as f32, params[5] as f32);
+        let corners: usize = boards.iter().map(|board| board.len()).sum();
+        (cost / corners.max(1) as f64).sqrt() as f32
+    }
+
+    /// Pose of a board from the homography of its undistorted corners.
+    ///
+    /// # Returns
+    ///
+    /// The directions of the grid axes in the camera and the position of the grid origin,
+    /// or None if the corners don't span a plane.
+    fn pose(&self, grid: &Grid) -> Option<[[f64; 3]; 3]> {
+        // Homography with h33 = 1 by least squares
+        let mut a = vec![vec![0.0; 8]; 8];
+        let mut b = vec![0.0; 8];
+        for ((i, j), (x, y)) in grid {
+            let (ux, uy) = self.undistort_point(*x, *y);
+            let (x, y) = (*i as f64, *j as f64);
+            let u = ((ux - self.cx) / self.fx) as f64;
+            let v = ((uy - self.cy) / self.fy) as f64;
+            let rows = [
+                ([x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y], u),
+                ([0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y], v),
+            ];
+            for (row, rhs) in rows {
+                for p in 0..8 {
+                    b[p] += row[p] * rhs;
+                    for q in 0..8 {
+                        a[p][q] += row[p] * row[q];
+                    }
+                }
+            }
+        }
+        let h = solve(a, b)?;
+        let (m1, m2, m3) = ([h[0], h[3], h[6]], [h[1], h[4], h[7]], [h[2], h[5], 1.0]);
+        // The columns are the axes and the origin up to a scale
+        let scale = 2.0 / (dot(m1, m1).sqrt() + dot(m2, m2).sqrt());
+        let r1 = m1.map(|e| e / dot(m1, m1).sqrt());
+        let d = dot(r1, m2);
+        let r2 = [0, 1, 2].map(|n| m2[n] - d * r1[n]);
+        let r2 = r2.map(|e| e / dot(r2, r2).sqrt());
+        Some([r1, r2, m3.map(|e| e * scale)])
+    }
+
+    /// Fit the distortion coefficients to straighten the lines.
+    ///
+    /// # Arguments
+    ///
+    /// * `lines` - Distorted points that are on straight lines in the scene.
+    ///
+    /// # Returns
+    ///
+    /// The remaining crookedness of the lines (0.0 for perfectly straight).
+    fn fit_distortion(&mut self, lines: &[Vec<(f32, f32)>]) -> f32 {
+        // Coordinate descent with a shrinking step
+        let mut best = self.crookedness(lines);
+        let mut step = 0.1;
+        while 1e-5 < step {
+            let mut improved = false;
+            for (dk1, dk2) in [(step, 0.0), (-step, 0.0), (0.0, step), (0.0, -step)] {
+                let candidate = Self {
+                    k1: self.k1 + dk1,
+                    k2: self.k2 + dk2,
+                    ..self.clone()
+                };
+                let e = candidate.crookedness(lines);
+                if e < best {
+                    best = e;
+                    *self = candidate;
+                    improved = true;
+                }
+            }
+            if !improved {
+                step /= 2.0;
+            }
+        }
+        best
+    }
+
+    /// Mean crookedness of the undistorted lines.
+    ///
+    /// The crookedness of a line is the ratio of the minor to the major variance of its points,
+    /// which doesn't depend on the scale of the image.
+    fn crookedness(&self, lines: &[Vec<(f32, f32)>]) -> f32 {
+        let mut sum = 0.0;
+        for line in lines {
+            let mut points = vec![];
+            for (x, y) in line {
+                // The undistortion must not fold the image
+                if self.factor(*x, *y) <= 0.0 {
+                    return f32::MAX;
+                }
+                points.push(self.undistort_point(*x, *y));
+            }
+            let (minor, major) = principal_variances(&points);
+            sum += minor / major.max(f32::EPSILON);
+        }
+        sum / lines.len().max(1) as f32
+    }
+}
+
+/// Minor and major variances of the points.
+fn principal_variances(points: &[(f32, f32)]) -> (f32, f32) {
+    let n = points.len() as f32;
+    let mx = points.iter().map(|p| p.0).sum::<f32>() / n;
+    let my = points.iter().map(|p| p.1).sum::<f32>() / n;
+    let (mut sxx, mut syy, mut sxy) = (0.0, 0.0, 0.0);
+    for (x, y) in points {
+        sxx += (x - mx) * (x - mx) / n;
+        syy += (y - my) * (y - my) / n;
+        sxy += (x - mx) * (y - my) / n;
+    }
+    // Eigenvalues of the covariance matrix
+    let mean = (sxx + syy) / 2.0;
+    let d = (((sxx - syy) / 2.0).powi(2) + sxy * sxy).sqrt();
+    ((mean - d).max(0.0), mean + d)
+}
+
+// Boards in different poses to fit the focal length and the principal point
+const MIN_POSES: usize = 3;
+
+/// Corners of a checkerboard: the index in the grid and the pixel of each corner.
+pub type Grid = Vec<((i32, i32), (f32, f32))>;
+
+// Corners of a board in the fit: the board point rotated by the initial pose and the pixel
+type Board = Vec<([f64; 3], (f64, f64))>;
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+/// Rotate a point by a rotation vector (Rodrigues' formula).
+fn rotate(w: &[f64], p: [f64; 3]) -> [f64; 3] {
+    let theta = dot([w[0], w[1], w[2]], [w[0], w[1], w[2]]).sqrt();
+    if theta < 1e-12 {
+        return p;
+    }
+    let k = [w[0] / theta, w[1] / theta, w[2] / theta];
+    let (s, c) = theta.sin_cos();
+    let (kxp, kp) = (cross(k, p), dot(k, p));
+    [0, 1, 2].map(|n| p[n] * c + kxp[n] * s + k[n] * kp * (1.0 - c))
+}
+
+/// Errors of the undistorted corners from the projection of the boards (pixels).
+///
+/// # Arguments
+///
+/// * `params` - fx, fy, cx, cy, k1, k2 and the rotation vector and the translation of each board.
+/// * `boards` - The rotated board points and the distorted corners of each board.
+///
+/// # Returns
+///
+/// The x and y errors of each corner, or None if a board is behind the camera or the image folds.
+fn residuals(params: &[f64], boards: &[Board]) -> Option<Vec<f64>> {
+    let (fx, fy, cx, cy, k1, k2) = (
+        params[0], params[1], params[2], params[3], params[4], params[5],
+    );
+    let mut residual = vec![];
+    for (n, board) in boards.iter().enumerate() {
+        let pose = &params[6 + 6 * n..12 + 6 * n];
+        for (point, (x, y)) in board {
+            let p = rotate(&pose[..3], *point);
+            let z = p[2] + pose[5];
+            if z <= 0.0 {
+                return None;
+            }
+            let (xn, yn) = ((x - cx) / fx, (y - cy) / fy);
+            let r2 = xn * xn + yn * yn;
+            let factor = 1.0 + k1 * r2 + k2 * r2 * r2;
+            if factor <= 0.0 {
+                return None;
+            }
+            residual.push((x - cx) * factor - fx * (p[0] + pose[3]) / z);
+            residual.push((y - cy) * factor - fy * (p[1] + pose[4]) / z);
+        }
+    }
+    Some(residual)
+}
+
+/// Minimize the squared sum of the residuals by Levenberg-Marquardt.
+///
+/// # Arguments
+///
+/// * `params` - The initial parameters, updated to the fitted ones.
+/// * `free` - Indices of the parameters to fit, the others are kept.
+/// * `f` - The residuals of the parameters, None where they are invalid.
+///
+/// # Returns
+///
+/// The remaining squared sum, or None if the initial parameters are invalid.
+fn levenberg_marquardt(
+    params: &mut [f64],
+    free: &[usize],
+    f: impl Fn(&[f64]) -> Option<Vec<f64>>,
+) -> Option<f64> {
+    let mut residual = f(params)?;
+    let mut cost: f64 = residual.iter().map(|r| r * r).sum();
+    let mut damping = 1e-3;
+    for _ in 0..100 {
+        // Jacobian of the free parameters by central differences
+        let mut jacobian = vec![];
+        for k in free {
+            let h = 1e-6 * (1.0 + params[*k].abs());
+            let mut plus = params.to_vec();
+            let mut minus = params.to_vec();
+            plus[*k] += h;
+            minus[*k] -= h;
+            match (f(&plus), f(&minus)) {
+                (Some(a), Some(b)) => jacobian.push(
+                    a.iter()
+                        .zip(b.iter())
+                        .map(|(a, b)| (a - b) / (2.0 * h))
+                        .collect::<Vec<f64>>(),
+                ),
+                _ => return Some(cost), // On the border of the valid parameters
+            }
+        }
+        // Normal equations
+        let n = free.len();
+        let mut a = vec![vec![0.0; n]; n];
+        let mut g = vec![0.0; n];
+        for p in 0..n {
+            g[p] = -jacobian[p]
+                .iter()
+                .zip(&residual)
+                .map(|(j, r)| j * r)
+                .sum::<f64>();
+            for q in p..n {
+                a[p][q] = jacobian[p]
+                    .iter()
+                    .zip(&jacobian[q])
+                    .map(|(a, b)| a * b)
+                    .sum();
+                a[q][p] = a[p][q];
+            }
+        }
+        // Raise the damping until the step improves
+        let previous = cost;
+        loop {
+            let mut damped = a.clone();
+            (0..n).for_each(|k| damped[k][k] *= 1.0 + damping);
+            if let Some(step) = solve(damped, g.clone()) {
+                let mut candidate = params.to_vec();
+                free.iter().zip(&step).for_each(|(k, s)| candidate[*k] += s);
+                if let Some(r) = f(&candidate) {
+                    let c: f64 = r.iter().map(|r| r * r).sum();
+                    if c < cost {
+                        params.copy_from_slice(&candidate);
+                        (residual, cost) = (r, c);
+                        damping = (damping / 10.0).max(1e-12);
+                        break;
+                    }
+                }
+            }
+            damping *= 10.0;
+            if 1e12 < damping {
+                return Some(cost);
+            }
+        }
+        if previous - cost <= 1e-12 * previous {
+            break;
+        }
+    }
+    Some(cost)
+}
+
+/// Solve a linear system by Gaussian elimination with partial pivoting.
+///
+/// # Returns
+///
+/// The solution, or None if the matrix is singular.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|p, q| a[*p][col].abs().total_cmp(&a[*q][col].abs()))?;
+        if a[pivot][col].abs() < 1e-300 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let (pivot_row, pivot_rhs) = (a[col].clone(), b[col]);
+        for (row, rhs) in a.iter_mut().zip(b.iter_mut()).skip(col + 1) {
+            let ratio = row[col] / pivot_row[col];
+            row.iter_mut()
+                .zip(&pivot_row)
+                .skip(col)
+                .for_each(|(e, p)| *e -= ratio * p);
+            *rhs -= ratio * pivot_rhs;
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    x.iter().all(|e| e.is_finite()).then_some(x)
+}
+
+// Ring of 16 samples around a corner candidate, by 22.5 degrees
+const RING: [(i32, i32); 16] = [
+    (5, 0),
+    (5, 2),
+    (4, 4),
+    (2, 5),
+    (0, 5),
+    (-2, 5),
+    (-4, 4),
+    (-5, 2),
+    (-5, 0),
+    (-5, -2),
+    (-4, -4),
+    (-2, -5),
+    (0, -5),
+    (2, -5),
+    (4, -4),
+    (5, -2),
+];
+
+// Width of the image to look for the corners in
+const DETECT_WIDTH: u32 = 640;
+
+/// Find the checkerboard corners (saddle points of the brightness).
+///
+/// The response of the ChESS detector is high where the ring around a pixel
+/// is bright and dark in opposite quarters.
+///
+/// # Returns
+///
+/// The corners in the pixels of the image.
+pub fn find_corners(img: &DynamicImage) -> Vec<(f32, f32)> {
+    let scale = img.width() as f32 / DETECT_WIDTH as f32;
+    let gray: GrayImage = img
+        .resize(DETECT_WIDTH, u32::MAX, FilterType::Triangle)
+        .to_luma8();
+    let (w, h) = (gray.width() as i32, gray.height() as i32);
+    let at = |x: i32, y: i32| gray.get_pixel(x as u32, y as u32)[0] as f32;
+
+    // Response of each pixel
+    let margin = 6;
+    let mut response = vec![0.0; (w * h) as usize];
+    for y in margin..h - margin {
+        for x in margin..w - margin {
+            let ring: Vec<f32> = RING.iter().map(|(dx, dy)| at(x + dx, y + dy)).collect();
+            let sum: f32 = (0..4)
+                .map(|n| ((ring[n] + ring[n + 8]) - (ring[n + 4] + ring[n + 12])).abs())
+                .sum();
+            let diff: f32 = (0..8).map(|n| (ring[n] - ring[n + 8]).abs()).sum();
+            let ring_mean = ring.iter().sum::<f32>() / 16.0;
+            let local_mean =
+                (at(x, y) + at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1)) / 5.0;
+            response[(y * w + x) as usize] = sum - diff - 16.0 * (ring_mean - local_mean).abs();
+        }
+    }
+
+    // Local maxima above a share of the strongest response
+    let threshold = response.iter().cloned().fold(0.0, f32::max) * 0.3;
+    let radius = 4;
+    let mut corners = vec![];
+    for y in margin..h - margin {
+        for x in margin..w - margin {
+            let r = response[(y * w + x) as usize];
+            if r <= threshold {
+                continue;
+            }
+            let mut is_max = true;
+            let (mut sx, mut sy, mut sw) = (0.0, 0.0, 0.0);
+            for ny in (y - radius).max(0)..(y + radius + 1).min(h) {
+                for nx in (x - radius).max(0)..(x + radius + 1).min(w) {
+                    let nr = response[(ny * w + nx) as usize];
+                    // Ties go to the first pixel in the scan order
+                    if nr > r || (nr == r && (ny, nx) < (y, x)) {
+                        is_max = false;
+                    }
+                    // Weighted centroid of the peak for the subpixel position
+                    if (nx - x).abs() <= 1 && (ny - y).abs() <= 1 && 0.0 < nr {
+                        (sx, sy, sw) = (sx + nx as f32 * nr, sy + ny as f32 * nr, sw + nr);
+                    }
+                }
+            }
+            if is_max {
+                corners.push((sx / sw * scale, sy / sw * scale));
+            }
+        }
+    }
+    corners
+}
+
+/// Arrange the corners into the grid of the checkerboard.
+///
+/// Starting from the corner nearest to the center, the neighbours are predicted
+/// from the spacing of the grid and the nearest corner is taken.
+///
+/// # Returns
+///
+/// The corners with their indices in the grid, the start corner is (0, 0).
+pub fn find_grid(corners: &[(f32, f32)]) -> Grid {
+    if corners.len() < 5 {
+        return vec![];
+    }
+    let dist = |a: (f32, f32), b: (f32, f32)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+    let n = corners.len() as f32;
+    let center = (
+        corners.iter().map(|c| c.0).sum::<f32>() / n,
+        corners.iter().map(|c| c.1).sum::<f32>() / n,
+    );
+    let start = (0..corners.len())
+        .min_by(|a, b| dist(corners[*a], center).total_cmp(&dist(corners[*b], center)))
+        .unwrap();
+
+    // Basis of the grid from the nearest neighbours of the start
+    let mut near: Vec<usize> = (0..corners.len()).filter(|i| *i != start).collect();
+    near.sort_by(|a, b| {
+        dist(corners[*a], corners[start]).total_cmp(&dist(corners[*b], corners[start]))
+    });
+    let vector = |i: usize| {
+        (
+            corners[i].0 - corners[start].0,
+            corners[i].1 - corners[start].1,
+        )
+    };
+    let u = vector(near[0]);
+    let v = near
+        .iter()
+        .take(4)
+        .map(|i| vector(*i))
+        .min_by(|a, b| {
+            let cos = |p: &(f32, f32)| (p.0 * u.0 + p.1 * u.1).abs() / dist(*p, (0.0, 0.0));
+            cos(a).total_cmp(&cos(b))
+        })
+        .unwrap();
+
+    // Grow the grid
+    let mut grid: HashMap<(i32, i32), usize> = HashMap::from([((0, 0), start)]);
+    let mut used = vec![false; corners.len()];
+    used[start] = true;
+    let mut queue = vec![(0, 0)];
+    while let Some((i, j)) = queue.pop() {
+        let p = corners[grid[&(i, j)]];
+        for (di, dj) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let next = (i + di, j + dj);
+            if grid.contains_key(&next) {
+                continue;
+            }
+            // Continue the spacing of the grid where it is known
+            let step = match grid.get(&(i - di, j - dj)) {
+                Some(k) => (p.0 - corners[*k].0, p.1 - corners[*k].1),
+                None => (
+                    u.0 * di as f32 + v.0 * dj as f32,
+                    u.1 * di as f32 + v.1 * dj as f32,
+                ),
+            };
+            let predicted = (p.0 + step.0, p.1 + step.1);
+            let tolerance = dist(step, (0.0, 0.0)) * 0.3;
+            let found = (0..corners.len())
+                .filter(|k| !used[*k])
+                .min_by(|a, b| {
+                    dist(corners[*a], predicted).total_cmp(&dist(corners[*b], predicted))
+                })
+                .filter(|k| dist(corners[*k], predicted) < tolerance);
+            if let Some(k) = found {
+                used[k] = true;
+                grid.insert(next, k);
+                queue.push(next);
+            }
+        }
+    }
+
+    let mut grid: Grid = grid.into_iter().map(|(ij, k)| (ij, corners[k])).collect();
+    grid.sort_by_key(|(ij, _)| *ij);
+    grid
+}
+
+/// Rows and columns of the grid.
+///
+/// # Returns
+///
+/// The rows and the columns with 4 or more corners.
+pub fn find_lines(grid: &Grid) -> Vec<Vec<(f32, f32)>> {
+    let mut rows: HashMap<i32, Vec<(i32, usize)>> = HashMap::new();
+    let mut cols: HashMap<i32, Vec<(i32, usize)>> = HashMap::new();
+    for (k, ((i, j), _)) in grid.iter().enumerate() {
+        rows.entry(*j).or_default().push((*i, k));
+        cols.entry(*i).or_default().push((*j, k));
+    }
+    rows.into_values()
+        .chain(cols.into_values())
+        .filter(|line| 4 <= line.len())
+        .map(|mut line| {
+            line.sort();
+            line.iter().map(|(_, k)| grid[*k].1).collect()
+        })
+        .collect()
+}
+
+/// Calibrate the lens from the checkerboard images in a directory and save it to the data directory.
+///
+/// The focal length and the principal point need 3 or more images, see the module documentation.
+///
+/// # Arguments
+///
+/// * `property` - RoktrackProperty holding the camera config and the data directory.
+/// * `dir` - Directory of the checkerboard images taken by the camera.
+///
+pub fn run(property: &RoktrackProperty, dir: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut calibration = Calibration::new(&property.conf);
+    let mut grids = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let img = match image::open(&path) {
+            Ok(img) => img,
+            Err(_) => continue, // Not an image
+        };
+        if (img.width(), img.height()) != (calibration.width, calibration.height) {
+            log::warn!("Image Size Mismatch. Skipped. path: {:?}", path);
+            continue;
+        }
+        let grid = find_grid(&find_corners(&img));
+        let lines = find_lines(&grid).len();
+        log::info!(
+            "Checkerboard Lines Found. path: {:?}, lines: {}",
+            path,
+            lines
+        );
+        if lines != 0 {
+            grids.push(grid);
+        }
+    }
+    if grids.is_empty() {
+        return Err("No checkerboard found.".into());
+    }
+    if grids.len() < MIN_POSES {
+        log::warn!(
+            "Too Few Checkerboards to Fit the Focal Length and the Principal Point. images: {}",
+            grids.len()
+        );
+    }
+    let error = calibration.fit(&grids);
+    calibration.save(&property.path.dir.data)?;
+    log::info!(
+        "Calibration Saved. fx: {}, fy: {}, cx: {}, cy: {}, k1: {}, k2: {}, error: {} pixels",
+        calibration.fx,
+        calibration.fy,
+        calibration.cx,
+        calibration.cy,
+        calibration.k1,
+        calibration.k2,
+        error
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_test() {
+        let conf = crate::module::util::init::resource::init().conf;
+        // The lens differs from the config in every parameter
+        let truth = Calibration {
+            fx: 1150.0,
+            fy: 1170.0,
+            cx: 660.0,
+            cy: 345.0,
+            k1: 0.2,
+            k2: 0.05,
+            ..Calibration::new(&conf)
+        };
+        // The point round trip
+        let (x, y) = truth.distort_point(100.0, 50.0);
+        let (x, y) = truth.undistort_point(x, y);
+        assert!((x - 100.0).abs() < 0.01 && (y - 50.0).abs() < 0.01);
+        // A checkerboard of 9 * 6 corners seen through the lens in 4 poses
+        let poses = [
+            ([0.3, 0.0, 0.0], [0.3, -0.2, 12.0]),
+            ([0.0, 0.4, 0.0], [-0.5, 0.3, 11.0]),
+            ([-0.3, 0.2, 0.1], [0.2, 0.4, 12.5]),
+            ([0.2, -0.35, -0.1], [0.6, -0.3, 11.5]),
+        ];
+        let grids: Vec<Grid> = poses
+            .iter()
+            .map(|(w, t)| {
+                let corners: Vec<(f32, f32)> = (0..9)
+                    .flat_map(|i| (0..6).map(move |j| [i as f64 - 4.0, j as f64 - 2.5, 0.0]))
+                    .map(|point| {
+                        let p = rotate(w, point);
+                        let (x, y, z) = (p[0] + t[0], p[1] + t[1], p[2] + t[2]);
+                        truth.distort_point(
+                            (truth.fx as f64 * x / z) as f32 + truth.cx,
+                            (truth.fy as f64 * y / z) as f32 + truth.cy,
+                        )
+                    })
+                    .collect();
+                find_grid(&corners)
+            })
+            .collect();
+        for grid in &grids {
+            assert_eq!(grid.len(), 9 * 6);
+            assert_eq!(find_lines(grid).len(), 6 + 9);
+        }
+        // The intrinsics and the distortion are recovered
+        let mut calibration = Calibration::new(&conf);
+        assert!(calibration.fit(&grids) < 0.01);
+        assert!((calibration.fx - truth.fx).abs() < 2.0 && (calibration.fy - truth.fy).abs() < 2.0);
+        assert!((calibration.cx - truth.cx).abs() < 2.0 && (calibration.cy - truth.cy).abs() < 2.0);
+        for (x, y) in [(0.0, 0.0), (1280.0, 720.0), (200.0, 600.0)] {
+            let (ex, ey) = truth.undistort_point(x, y);
+            let (ax, ay) = calibration.undistort_point(x, y);
+            assert!((ex - ax).abs() < 1.0 && (ey - ay).abs() < 1.0);
+        }
+        // A single pose keeps the intrinsics of the config
+        let mut single = Calibration::new(&conf);
+        single.fit(&grids[..1]);
+        assert_eq!(single.fx, Calibration::new(&conf).fx);
+        assert_eq!(single.cx, Calibration::new(&conf).cx);
+        // A pylon at the edge of the frame gets taller
+        let mut pylon = Detection {
+            x1: 1180,
+            y1: 300,
+            x2: 1240,
+            y2: 400,
+            ..Default::default()
+        };
+        truth.undistort(&mut pylon);
+        assert!(100 < pylon.h && 1240 < pylon.x2);
+    }
+
+    #[test]
+    fn find_corners_test() {
+        // 12 * 7 squares of 100 pixels, 11 * 6 corners inside
+        let img = image::GrayImage::from_fn(1280, 720, |x, y| {
+            let (i, j) = ((x as i32 - 40) / 100, (y as i32 - 10) / 100);
+            match x < 40 || y < 10 || 11 < i || 6 < j {
+                true => image::Luma([200]),
+                false => image::Luma([if (i + j) % 2 == 0 { 20 } else { 230 }]),
+            }
+        });
+        let corners = find_corners(&DynamicImage::ImageLuma8(img));
+        assert_eq!(corners.len(), 11 * 6);
+        let grid = find_grid(&corners);
+        assert_eq!(grid.len(), 11 * 6);
+        let lines = find_lines(&grid);
+        assert_eq!(lines.len(), 11 + 6);
+        // Straight without distortion
+        let calibration = Calibration::new(&crate::module::util::init::resource::init().conf);
+        assert!(calibration.crookedness(&lines) < 1e-4);
+    }
+}
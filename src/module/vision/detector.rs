@@ -1,8 +1,48 @@
 //! Provide Object Detection
 //!
+use crate::module::util::init::RoktrackProperty;
+
+/// A trait for object detectors behind the vision thread
+///
+/// A model family (e.g. pylon, pylon with OCR, animal) is an implementation of this trait,
+/// so a new one can be added without editing the vision thread.
+pub trait Detector: Send {
+    /// Detect objects in the image with the current input size.
+    fn infer(
+        &self,
+        impath: &str,
+        record_path: &str,
+    ) -> Result<Vec<Detection>, Box<dyn std::error::Error>>;
+    /// Whether the detector reads the ids of the markers.
+    fn support_ocr(&self) -> bool;
+    /// Read the ids of the markers.
+    fn ocr(
+        &self,
+        impath: &str,
+        dets: Vec<Detection>,
+        property: RoktrackProperty,
+    ) -> Result<Vec<Detection>, Box<dyn std::error::Error>>;
+    /// Class names in the order of the class ids.
+    fn classes(&self) -> Vec<String>;
+    /// Input size of the model in pixels.
+    fn input_size(&self) -> u32;
+    /// Switch the input size of the model.
+    fn set_input_size(&mut self, size: u32);
+}
+
 pub mod onnx {
-    use crate::module::{define, util::init::RoktrackProperty};
-    use image::{imageops::FilterType, io::Reader, ImageBuffer, Pixel, Rgb};
+    use crate::module::{
+        define,
+        util::{
+            conf::{Nms, NmsParam},
+            init::RoktrackProperty,
+        },
+    };
+    use image::{
+        imageops::{self, FilterType},
+        io::Reader,
+        DynamicImage, Pixel, Rgb, RgbImage,
+    };
     use ndarray::{s, Array, Axis, IxDyn};
     use ort::{
         environment::Environment, value::Value, ExecutionProvider, GraphOptimizationLevel,
@@ -10,50 +50,45 @@ pub mod onnx {
     };
     use std::path::Path;
 
-    use super::Detection;
+    use super::{AnimalClasses, Detection, RoktrackClasses};
+    use crate::module::vision::manifest::{Manifest, Model, ModelFamily};
+
+    // Digits of a number are closer than this, relative to the digit height
+    const MAX_DIGIT_GAP: f32 = 0.5;
+    // Digits of a number overlap vertically more than this, relative to the digit height
+    const MIN_DIGIT_OVERLAP: f32 = 0.5;
 
     /// Session Types
     ///
+    /// The input sizes come from the manifest, 320, 640 and 96 for the shipped models.
     #[derive(Debug, Clone, PartialEq)]
     pub enum SessionType {
-        Sz320, // basic 320 * 320 inference
-        Sz640, // basic 640 * 640 inference
-        Ocr,   // ocr 96 * 96 inference
-    }
-    /// Session Type methods
-    ///
-    impl SessionType {
-        fn get_imgsz(&self) -> u32 {
-            match self {
-                Self::Sz320 => 320,
-                Self::Sz640 => 640,
-                Self::Ocr => 96,
-            }
-        }
+        Sz320, // basic inference with the smallest model of the family
+        Sz640, // basic inference with the largest model of the family
+        Ocr,   // ocr inference with the digit model
     }
-    /// Bundled Sessions
-    ///
-    pub enum Sessions {
-        Pylon {
-            sz320: Session,
-            sz640: Session,
-        },
-        PylonOcr {
-            sz320: Session,
-            sz640: Session,
-            ocr: Session,
-        },
-        Animal {
-            sz320: Session,
-            sz640: Session,
-        },
+
+    /// A session and its input size.
+    struct SizedSession {
+        session: Session,
+        size: u32, // Input size (pixels)
     }
 
     /// YoloV8 session store.
     ///
+    /// A model family is a pair of small and large sessions, optionally with a digit OCR session.
     pub struct YoloV8 {
-        pub sessions: Sessions,
         pub session_type: SessionType,
+
+        sz320: SizedSession,
+        sz640: SizedSession,
+        ocr: Option<SizedSession>,
+        classes: Vec<String>,
+
+        nms: NmsParam,     // NMS of the detection model
+        ocr_nms: NmsParam, // NMS of the digit OCR model
+
+        ocr_classes: Vec<String>, // Digits in the order of the class ids of the OCR model
     }
 
     impl Default for YoloV8 {
@@ -68,15 +103,92 @@ pub mod onnx {
         /// yolov8's constructor.
         ///
         pub fn new() -> Self {
-            Self {
-                sessions: Sessions::Pylon {
-                    sz320: Self::get_session("pylon_sz320", define::path::PYLON_320_MODEL)
-                        .expect("Can't initialize PYLON_320_MODEL"),
-                    sz640: Self::get_session("pylon_sz640", define::path::PYLON_640_MODEL)
-                        .expect("Can't initialize PYLON_640_MODEL"),
-                },
+            Self::pylon(&Nms::default()).expect("Can't initialize PYLON_MODEL")
+        }
+        /// Build a model family.
+        ///
+        /// # Arguments
+        ///
+        /// * `name` - Name of the model family.
+        /// * `family` - Models and classes of the family in the manifest.
+        /// * `nms` - NMS of the detection model, or the default of the manifest.
+        /// * `ocr` - Digit OCR family and its NMS, if any.
+        ///
+        pub fn build(
+            name: &str,
+            family: &ModelFamily,
+            nms: Option<NmsParam>,
+            ocr: Option<(&ModelFamily, Option<NmsParam>)>,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            let classes = family.load_classes()?;
+            let (ocr, ocr_classes, ocr_nms) = match ocr {
+                Some((digit, ocr_nms)) => {
+                    let digits = digit.load_classes()?;
+                    let session =
+                        Self::load_model(&format!("{}_ocr", name), digit.small()?, digits.len())?;
+                    (Some(session), digits, ocr_nms.unwrap_or(digit.nms.clone()))
+                }
+                None => (None, vec![], NmsParam::default()),
+            };
+            Ok(Self {
+                sz320: Self::load_model(
+                    &format!("{}_sz320", name),
+                    family.small()?,
+                    classes.len(),
+                )?,
+                sz640: Self::load_model(
+                    &format!("{}_sz640", name),
+                    family.large()?,
+                    classes.len(),
+                )?,
+                ocr,
+                classes,
+                ocr_classes,
+                nms: nms.unwrap_or(family.nms.clone()),
+                ocr_nms,
                 session_type: SessionType::Sz320,
-            }
+            })
+        }
+        /// Build Pylon Model Family
+        ///
+        pub fn pylon(nms: &Nms) -> Result<Self, Box<dyn std::error::Error>> {
+            let manifest = Manifest::load(define::path::MODEL_MANIFEST)?;
+            check_classes(&manifest.pylon.load_classes()?, roktrack_id)?;
+            Self::build("pylon", &manifest.pylon, nms.pylon.clone(), None)
+        }
+        /// Build Pylon OCR Model Family
+        ///
+        pub fn pylon_ocr(nms: &Nms) -> Result<Self, Box<dyn std::error::Error>> {
+            let manifest = Manifest::load(define::path::MODEL_MANIFEST)?;
+            check_classes(&manifest.pylon.load_classes()?, roktrack_id)?;
+            Self::build(
+                "pylon",
+                &manifest.pylon,
+                nms.pylon.clone(),
+                Some((&manifest.digit, nms.ocr.clone())),
+            )
+        }
+        /// Build Animal Model Family
+        ///
+        pub fn animal(nms: &Nms) -> Result<Self, Box<dyn std::error::Error>> {
+            let manifest = Manifest::load(define::path::MODEL_MANIFEST)?;
+            check_classes(&manifest.animal.load_classes()?, animal_id)?;
+            Self::build("animal", &manifest.animal, nms.animal.clone(), None)
+        }
+        /// Load a model of the family and check its shapes.
+        ///
+        fn load_model(
+            name: &str,
+            model: &Model,
+            classes: usize,
+        ) -> Result<SizedSession, Box<dyn std::error::Error>> {
+            let session = Self::get_session(name, &model.path)?;
+            validate_session(&session, model.size, classes)
+                .map_err(|e| format!("{}: {}", model.path, e))?;
+            Ok(SizedSession {
+                session,
+                size: model.size,
+            })
         }
         /// get session
         ///
@@ -96,37 +208,6 @@ pub mod onnx {
                 .with_model_from_file(model_path)?;
             Ok(session)
         }
-        /// Build Pylon Session Bundle
-        ///
-        pub fn build_pylon_sessions(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-            let sessions = Sessions::Pylon {
-                sz320: Self::get_session("pylon_sz320", define::path::PYLON_320_MODEL)?,
-                sz640: Self::get_session("pylon_sz640", define::path::PYLON_640_MODEL)?,
-            };
-            self.sessions = sessions;
-            Ok(())
-        }
-        /// Build Pylon OCR Session Bundle
-        ///
-        pub fn build_pylon_ocr_sessions(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-            let sessions = Sessions::PylonOcr {
-                sz320: Self::get_session("pylon_sz320", define::path::PYLON_320_MODEL)?,
-                sz640: Self::get_session("pylon_sz640", define::path::PYLON_640_MODEL)?,
-                ocr: Self::get_session("pylon_ocr", define::path::DIGIT_OCR_96_MODEL)?,
-            };
-            self.sessions = sessions;
-            Ok(())
-        }
-        /// Build Animal Session Bundle
-        ///
-        pub fn build_animal_sessions(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-            let sessions = Sessions::Animal {
-                sz320: Self::get_session("animal_sz320", define::path::ANIMAL_320_MODEL)?,
-                sz640: Self::get_session("animal_sz640", define::path::ANIMAL_640_MODEL)?,
-            };
-            self.sessions = sessions;
-            Ok(())
-        }
         /// Infer
         ///
         pub fn infer(
@@ -135,11 +216,15 @@ pub mod onnx {
             session_type: SessionType,
             record_path: &str,
         ) -> Result<Vec<super::Detection>, Box<dyn std::error::Error>> {
-            let sz = session_type.get_imgsz();
-            // Load image and resize to model's shape, converting to RGB format
-            let img: ImageBuffer<Rgb<u8>, Vec<u8>> = image::open(Path::new(impath))?
-                .resize_exact(sz, sz, FilterType::Nearest)
-                .to_rgb8();
+            let (session, nms) = match session_type {
+                SessionType::Sz320 => (&self.sz320, &self.nms),
+                SessionType::Sz640 => (&self.sz640, &self.nms),
+                SessionType::Ocr => (self.ocr.as_ref().ok_or("No OCR Session")?, &self.ocr_nms),
+            };
+            let sz = session.size;
+            // Load image and letterbox to model's shape, converting to RGB format
+            let original = image::open(Path::new(impath))?;
+            let (img, letterbox) = Letterbox::apply(&original, sz);
 
             // As a Recoder
             if !record_path.is_empty() {
@@ -157,27 +242,9 @@ pub mod onnx {
                 .into_dyn(),
             );
 
-            let session = match &self.sessions {
-                Sessions::Pylon { sz320, sz640 } => match session_type {
-                    SessionType::Sz320 => sz320,
-                    SessionType::Sz640 => sz640,
-                    _ => panic!("Invalid Session Type"),
-                },
-                Sessions::PylonOcr { sz320, sz640, ocr } => match session_type {
-                    SessionType::Sz320 => sz320,
-                    SessionType::Sz640 => sz640,
-                    SessionType::Ocr => ocr,
-                },
-                Sessions::Animal { sz320, sz640 } => match session_type {
-                    SessionType::Sz320 => sz320,
-                    SessionType::Sz640 => sz640,
-                    _ => panic!("Invalid Session Type"),
-                },
-            };
+            let tensor = vec![Value::from_array(session.session.allocator(), &array)?];
 
-            let tensor = vec![Value::from_array(session.allocator(), &array)?];
-
-            let outs = session.run(tensor)?;
+            let outs = session.session.run(tensor)?;
             let out = outs
                 .get(0)
                 .unwrap()
@@ -185,22 +252,18 @@ pub mod onnx {
                 .view()
                 .t()
                 .into_owned();
-            convert_yolo_fmt(out)
-        }
-
-        /// Whether the current session supports OCR
-        pub fn support_ocr(&self) -> bool {
-            match self.sessions {
-                Sessions::Pylon { .. } => false,
-                Sessions::PylonOcr { .. } => true,
-                Sessions::Animal { .. } => false,
-            }
+            // Back to the pixels of the original image
+            let dets = non_max_suppression(convert_yolo_fmt(out, nms.score)?, nms)
+                .iter()
+                .map(|det| letterbox.to_original(det, original.width(), original.height()))
+                .collect();
+            Ok(dets)
         }
 
         /// Detects numbers in the vicinity of the marker.
         ///
-        /// The bbox of the marker detected in low resolution is extracted
-        /// from the high resolution image by ratio and applied to OCR.
+        /// The bbox of the marker, in the pixels of the original image,
+        /// is cropped from the full resolution image and applied to OCR.
         pub fn ocr(
             &self,
             impath: &str,
@@ -210,60 +273,12 @@ pub mod onnx {
             // For result
             let mut new_dets = dets.clone();
             log::debug!("[In Ocr] Dets: {:?}", new_dets.clone());
-
-            // Resolution of the image used for detection
-            let (iw, ih) = match self.session_type {
-                SessionType::Sz320 => (320.0, 320.0),
-                SessionType::Sz640 => (640.0, 640.0),
-                _ => (0.0, 0.0),
-            };
-            log::debug!("[In Ocr] iw: {:?}, ih: {:?}", iw, ih);
-            // Original resolution
-            let (ow, oh) = (
-                property.conf.camera.width as f64,
-                property.conf.camera.height as f64,
-            );
-            log::debug!("[In Ocr] ow: {:?}, oh: {:?}", ow, oh);
             // Load original image (full resolution)
             let mut img = Reader::open(impath)?.decode()?;
             // Iterates dets.
             for (i, det) in dets.iter().enumerate() {
-                // Get the relative position of bbox
-                let (rx1, ry1, rx2, ry2) = (
-                    det.x1 as f64 / iw,
-                    det.y1 as f64 / ih,
-                    det.x2 as f64 / iw,
-                    det.y2 as f64 / ih,
-                );
-                // Ralative width and height
-                let (rw, rh) = (rx2 - rx1, ry2 - ry1);
-                // let x = if 0.0 < (ow * rx1 - (ow * 0.05)) {
-                //     ow * rx1 - (ow * 0.05)
-                // } else {
-                //     ow * rx1
-                // } as u32;
-                // let y = if 0.0 < oh * ry1 - (oh * 0.05) {
-                //     oh * ry1 - (oh * 0.05)
-                // } else {
-                //     oh * ry1
-                // } as u32;
-                // let w = if (ow * rw + (ow * 0.1)) < ow {
-                //     ow * rw + (ow * 0.1)
-                // } else {
-                //     ow * rw
-                // } as u32;
-                // let h = if (oh * rh + (oh * 0.1)) < oh {
-                //     oh * rh + (oh * 0.1)
-                // } else {
-                //     oh * rh
-                // } as u32;
-                // Crop original image by ratio
-                let crop = img.crop(
-                    (ow * rx1) as u32,
-                    (oh * ry1) as u32,
-                    (ow * rw) as u32,
-                    (oh * rh) as u32,
-                );
+                // Crop original image by the bbox
+                let crop = img.crop(det.x1, det.y1, det.w, det.h);
                 log::debug!(
                     "[In Ocr] crop_cls: {:?}, crop_height: {:?}, crop_width:{:?}",
                     det.cls,
@@ -275,34 +290,205 @@ pub mod onnx {
                     // Save the crop image to the specified file path.
                     let _save_res = crop.save(property.path.img.crop.clone());
 
-                    // let fname = format!(
-                    //     "/data/roktrack/img/{:?}.jpg",
-                    //     chrono::Utc::now().timestamp()
-                    // );
-                    // let _ = crop.save(fname.clone());
-
                     let ocr_dets = self.infer(
                         property.path.img.crop.clone().as_str(),
                         SessionType::Ocr,
                         "",
                     )?;
 
-                    // Collect detected digits
+                    // Collect detected digits by the class names of the OCR model
                     let mut digits = vec![];
                     for ocr_det in ocr_dets {
-                        digits.push(ocr_det.cls as u8);
+                        match self.ocr_classes.get(ocr_det.cls as usize) {
+                            Some(digit) => digits.push((ocr_det, digit.parse::<u8>()?)),
+                            None => log::warn!("Unknown Digit Class: {}", ocr_det.cls),
+                        }
                     }
-                    // log::debug!("[In Ocr] res: {:?}, fname: {:?}", digits.clone(), fname);
-                    new_dets[i].ids = digits;
+                    // Read the digits as numbers
+                    let numbers = assemble(digits);
+                    new_dets[i].ids = numbers.iter().map(|n| n.0).collect();
+                    new_dets[i].id_prob = numbers.iter().map(|n| n.1).reduce(f32::min);
                 }
             }
             Ok(new_dets)
         }
     }
 
-    #[warn(clippy::manual_retain)]
+    /// Assemble the digits into the numbers on the marker.
+    ///
+    /// The digits are grouped into lines by their vertical overlap and read left to right in each line.
+    /// The ones side by side on a line make a number.
+    ///
+    /// # Arguments
+    ///
+    /// * `digits` - Digit detections of the OCR model with their values.
+    ///
+    /// # Returns
+    ///
+    /// The numbers line by line from the top and left to right, with the lowest probability of their digits.
+    pub fn assemble(mut digits: Vec<(Detection, u8)>) -> Vec<(u8, f32)> {
+        digits.sort_by(|a, b| a.0.yc.total_cmp(&b.0.yc));
+        // Lines of digits overlapping vertically, from the top
+        let mut lines: Vec<Vec<(Detection, u8)>> = vec![];
+        for digit in digits {
+            let on_line = |line: &Vec<(Detection, u8)>| {
+                line.iter().any(|(det, _)| {
+                    let height = det.h.min(digit.0.h) as f32;
+                    let overlap = det.y2.min(digit.0.y2) as f32 - det.y1.max(digit.0.y1) as f32;
+                    height * MIN_DIGIT_OVERLAP < overlap
+                })
+            };
+            match lines.iter_mut().find(|line| on_line(line)) {
+                Some(line) => line.push(digit),
+                None => lines.push(vec![digit]),
+            }
+        }
+        // Numbers in each line, split by a gap
+        let mut groups: Vec<Vec<(Detection, u8)>> = vec![];
+        for mut line in lines {
+            line.sort_by(|a, b| a.0.xc.total_cmp(&b.0.xc));
+            let mut last: Option<Detection> = None;
+            for digit in line {
+                let next_to = last.as_ref().is_some_and(|last| {
+                    let height = last.h.min(digit.0.h) as f32;
+                    (digit.0.x1 as f32 - last.x2 as f32) < height * MAX_DIGIT_GAP
+                });
+                last = Some(digit.0.clone());
+                match next_to {
+                    true => groups.last_mut().unwrap().push(digit),
+                    false => groups.push(vec![digit]),
+                }
+            }
+        }
+        groups
+            .iter()
+            .filter_map(|group| {
+                let number = group.iter().fold(0u32, |n, (_, d)| n * 10 + *d as u32);
+                let prob = group.iter().map(|(det, _)| det.prob).fold(1.0, f32::min);
+                match u8::try_from(number) {
+                    Ok(number) => Some((number, prob)),
+                    Err(_) => {
+                        log::warn!("Number Out of Range: {}", number);
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    /// YoloV8 with onnx runtime as a detector.
+    ///
+    impl super::Detector for YoloV8 {
+        fn infer(
+            &self,
+            impath: &str,
+            record_path: &str,
+        ) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+            YoloV8::infer(self, impath, self.session_type.clone(), record_path)
+        }
+        fn support_ocr(&self) -> bool {
+            self.ocr.is_some()
+        }
+        fn ocr(
+            &self,
+            impath: &str,
+            dets: Vec<Detection>,
+            property: RoktrackProperty,
+        ) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+            YoloV8::ocr(self, impath, dets, property)
+        }
+        fn classes(&self) -> Vec<String> {
+            self.classes.clone()
+        }
+        fn input_size(&self) -> u32 {
+            match self.session_type {
+                SessionType::Sz640 => self.sz640.size,
+                _ => self.sz320.size,
+            }
+        }
+        fn set_input_size(&mut self, size: u32) {
+            // Any size above the small model upscales to the large one
+            self.session_type = match self.sz320.size < size {
+                true => SessionType::Sz640,
+                false => SessionType::Sz320,
+            };
+        }
+    }
+
+    /// Letterbox geometry.
+    ///
+    /// The image is resized keeping the aspect ratio and padded to a square,
+    /// so that the pylons are not squashed.
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Letterbox {
+        pub scale: f32, // Model pixels per original pixel
+        pub pad_x: f32, // Padding on the left in model pixels
+        pub pad_y: f32, // Padding on the top in model pixels
+    }
+
+    impl Letterbox {
+        /// Calculate the geometry to fit the original image into the square.
+        ///
+        /// # Arguments
+        ///
+        /// * `width` - Width of the original image.
+        /// * `height` - Height of the original image.
+        /// * `sz` - Side of the square input of the model.
+        ///
+        pub fn new(width: u32, height: u32, sz: u32) -> Self {
+            let scale = (sz as f32 / width as f32).min(sz as f32 / height as f32);
+            Self {
+                scale,
+                pad_x: (sz as f32 - width as f32 * scale) / 2.0,
+                pad_y: (sz as f32 - height as f32 * scale) / 2.0,
+            }
+        }
+
+        /// Resize the image with bilinear filtering and pad it with gray.
+        pub fn apply(img: &DynamicImage, sz: u32) -> (RgbImage, Self) {
+            let letterbox = Self::new(img.width(), img.height(), sz);
+            let resized = img
+                .resize_exact(
+                    ((img.width() as f32 * letterbox.scale).round() as u32).max(1),
+                    ((img.height() as f32 * letterbox.scale).round() as u32).max(1),
+                    FilterType::Triangle,
+                )
+                .to_rgb8();
+            let mut canvas = RgbImage::from_pixel(sz, sz, Rgb([114, 114, 114]));
+            imageops::overlay(
+                &mut canvas,
+                &resized,
+                letterbox.pad_x as i64,
+                letterbox.pad_y as i64,
+            );
+            (canvas, letterbox)
+        }
+
+        /// Map a detection in model pixels to the pixels of the original image.
+        pub fn to_original(&self, det: &Detection, width: u32, height: u32) -> Detection {
+            let x = |v: f32| ((v - self.pad_x) / self.scale).clamp(0.0, width as f32);
+            let y = |v: f32| ((v - self.pad_y) / self.scale).clamp(0.0, height as f32);
+            let (x1, x2) = (x(det.x1 as f32), x(det.x2 as f32));
+            let (y1, y2) = (y(det.y1 as f32), y(det.y2 as f32));
+            Detection {
+                x1: x1 as u32,
+                y1: y1 as u32,
+                x2: x2 as u32,
+                y2: y2 as u32,
+                xc: (x1 + x2) / 2.0,
+                yc: (y1 + y2) / 2.0,
+                w: (x2 - x1) as u32,
+                h: (y2 - y1) as u32,
+                ..det.clone()
+            }
+        }
+    }
+
+    /// Convert the output of the model into the candidates above the score threshold.
+    ///
     fn convert_yolo_fmt(
         out: Array<f32, IxDyn>,
+        score: f32,
     ) -> Result<Vec<super::Detection>, Box<dyn std::error::Error>> {
         // https://github.com/AndreyGermanov/yolov8_onnx_rust
         let mut bboxes = vec![];
@@ -316,7 +502,7 @@ pub mod onnx {
                 .map(|(index, value)| (index, *value))
                 .reduce(|accum, row| if row.1 > accum.1 { row } else { accum })
                 .unwrap();
-            if prob < 0.1 {
+            if prob < score {
                 continue;
             }
             let cls = class_id as u32;
@@ -341,16 +527,93 @@ pub mod onnx {
                 w,
                 h,
                 ids,
+                id_prob: None,
+                track_id: None,
+                distance: None,
+                bearing: None,
             })
         }
-        bboxes.sort_by(|box1, box2| box2.prob.total_cmp(&box1.prob));
-        Ok(merge_bboxes(bboxes))
+        Ok(bboxes)
+    }
+
+    /// Check the class names of a model against the class ids the pilots rely on.
+    ///
+    /// A retrained model must keep the names of `RoktrackClasses` or `AnimalClasses` in the same order.
+    ///
+    /// # Arguments
+    ///
+    /// * `classes` - Class names in the class names file.
+    /// * `id_of` - Class id of a name in the code, if known.
+    ///
+    fn check_classes(
+        classes: &[String],
+        id_of: fn(&str) -> Option<u32>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for (i, name) in classes.iter().enumerate() {
+            if id_of(name) != Some(i as u32) {
+                return Err(format!("Unexpected model class {}: {}", i, name).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Class id of a pylon model class name.
+    fn roktrack_id(name: &str) -> Option<u32> {
+        RoktrackClasses::from_name(name).map(|cls| cls.to_u32())
+    }
+
+    /// Class id of an animal model class name.
+    fn animal_id(name: &str) -> Option<u32> {
+        AnimalClasses::from_name(name).map(|cls| cls.to_u32())
+    }
+
+    /// Check the shapes of a YOLOv8 model.
+    ///
+    /// The input is (batch, 3, size, size) and the output is (batch, 4 + classes, candidates).
+    /// Dynamic dimensions are accepted.
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - Session of the model.
+    /// * `size` - Input size in the manifest.
+    /// * `classes` - Number of the classes in the class names file.
+    ///
+    fn validate_session(
+        session: &Session,
+        size: u32,
+        classes: usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let input = session.inputs.first().ok_or("No input")?;
+        let expected = [None, Some(3), Some(size), Some(size)];
+        let input_ok = input.dimensions.len() == expected.len()
+            && input
+                .dimensions
+                .iter()
+                .zip(expected)
+                .all(|(dim, exp)| dim.is_none() || exp.is_none() || *dim == exp);
+        if !input_ok {
+            return Err(format!(
+                "Input shape {:?} doesn't match the size {}",
+                input.dimensions, size
+            )
+            .into());
+        }
+        let output = session.outputs.first().ok_or("No output")?;
+        match output.dimensions.get(1) {
+            Some(Some(dim)) if *dim as usize != 4 + classes => Err(format!(
+                "Output shape {:?} doesn't match {} classes",
+                output.dimensions, classes
+            )
+            .into()),
+            Some(_) => Ok(()),
+            None => Err(format!("Unexpected output shape {:?}", output.dimensions).into()),
+        }
     }
 
     /// Function to compute the IoU of two rectangles.
     /// https://python-ai-learn.com/2021/02/06/iou/
     ///
-    fn iou(r1: Detection, r2: Detection) -> f64 {
+    pub fn iou(r1: &Detection, r2: &Detection) -> f64 {
         let x1 = r1.x1.max(r2.x1) as f64;
         let y1 = r1.y1.max(r2.y1) as f64;
         let x2 = r1.x2.min(r2.x2) as f64;
@@ -364,51 +627,406 @@ pub mod onnx {
         intersection / union
     }
 
-    /// Merges bounding boxes whose IoU is greater than or equal to 0.7.
+    /// Suppress the overlapping candidates of the same class.
     ///
-    fn merge_bboxes(bboxes: Vec<Detection>) -> Vec<Detection> {
-        let mut merged_bboxes = Vec::new();
-        let mut used = vec![false; bboxes.len()];
-        for i in 0..bboxes.len() {
-            if used[i] {
-                continue;
-            }
-            let mut merged_bbox = bboxes[i].clone();
-            used[i] = true;
-            for j in 0..bboxes.len() {
-                if used[j] || bboxes[i].cls != bboxes[j].cls {
+    /// The candidates are taken in the order of the probability, and the ones of the same class
+    /// overlapping a taken one by `iou` or more are dropped. With soft-NMS, their probability
+    /// is decayed by exp(-IoU^2 / sigma) instead, and they are dropped below `score`.
+    ///
+    /// # Arguments
+    ///
+    /// * `bboxes` - Candidates from the model.
+    /// * `param` - Score and IoU thresholds.
+    ///
+    pub fn non_max_suppression(mut bboxes: Vec<Detection>, param: &NmsParam) -> Vec<Detection> {
+        let mut kept = vec![];
+        bboxes.retain(|b| param.score <= b.prob);
+        while !bboxes.is_empty() {
+            bboxes.sort_by(|box1, box2| box2.prob.total_cmp(&box1.prob));
+            let best = bboxes.remove(0);
+            bboxes = bboxes
+                .into_iter()
+                .filter_map(|mut b| {
+                    if b.cls != best.cls {
+                        return Some(b);
+                    }
+                    let overlap = iou(&best, &b);
+                    if param.soft {
+                        b.prob *= (-(overlap * overlap) / param.sigma as f64).exp() as f32;
+                        (param.score <= b.prob).then_some(b)
+                    } else {
+                        ((overlap as f32) < param.iou).then_some(b)
+                    }
+                })
+                .collect();
+            kept.push(best);
+        }
+        kept
+    }
+}
+
+pub mod color {
+    //! Color-based pylon detector
+    //!
+    //! Segments the traffic-cone orange in HSV and returns the blobs as pylons.
+    //! Much lighter than the model, it serves as a fallback when the model can't be loaded,
+    //! and as a verifier that rejects the pylons of the model without the cone color.
+
+    use image::{imageops::FilterType, DynamicImage, Rgb};
+
+    use super::{Detection, Detector, RoktrackClasses};
+    use crate::module::util::{conf::Color, init::RoktrackProperty};
+
+    // Width of the image to segment. Pylons are big and orange, so a small image is enough.
+    const WORK_WIDTH: u32 = 160;
+    // Pylons are taller than wide. Minimum height / width of a blob.
+    const MIN_ASPECT: f32 = 0.8;
+
+    /// Detects pylons by the cone color.
+    ///
+    pub struct ColorDetector {
+        conf: Color,
+        size: u32, // Input size requested by the pilots, unused by the segmentation
+    }
+
+    impl ColorDetector {
+        /// ColorDetector's constructor.
+        ///
+        pub fn new(conf: Color) -> Self {
+            Self { conf, size: 320 }
+        }
+
+        /// Whether a pixel has the cone color.
+        fn is_cone(&self, pixel: &Rgb<u8>) -> bool {
+            let (h, s, v) = rgb_to_hsv(pixel);
+            let hue = match self.conf.hue_min <= self.conf.hue_max {
+                true => self.conf.hue_min <= h && h <= self.conf.hue_max,
+                false => self.conf.hue_min <= h || h <= self.conf.hue_max, // Around red
+            };
+            hue && self.conf.saturation <= s && self.conf.value <= v
+        }
+
+        /// Detect the pylons in an image.
+        ///
+        /// # Returns
+        ///
+        /// The pylons in the pixels of the image. The probability is the share of the cone color in the box.
+        pub fn detect(&self, img: &DynamicImage) -> Vec<Detection> {
+            let work = img
+                .resize(WORK_WIDTH, u32::MAX, FilterType::Triangle)
+                .to_rgb8();
+            let (w, h) = (work.width() as usize, work.height() as usize);
+            let mask: Vec<bool> = work.pixels().map(|p| self.is_cone(p)).collect();
+
+            // Connected blobs of the cone color
+            let mut blobs = vec![];
+            let mut seen = vec![false; w * h];
+            for start in 0..w * h {
+                if !mask[start] || seen[start] {
                     continue;
                 }
-                if iou(bboxes[i].clone(), bboxes[j].clone()) >= 0.7 {
-                    let x1 = merged_bbox.x1.min(bboxes[j].x1);
-                    let y1 = merged_bbox.y1.min(bboxes[j].y1);
-                    let x2 = merged_bbox.x2.max(bboxes[j].x2);
-                    let y2 = merged_bbox.y2.max(bboxes[j].y2);
-                    let w = x2 - x1;
-                    let h = y2 - y1;
-                    let xc = x1 as f32 + w as f32 / 2.0;
-                    let yc = y1 as f32 + h as f32 / 2.0;
-                    let ids = vec![];
-
-                    merged_bbox = Detection {
+                seen[start] = true;
+                let mut stack = vec![start];
+                let mut blob = Blob::new(start % w, start / w);
+                while let Some(i) = stack.pop() {
+                    let (x, y) = (i % w, i / w);
+                    blob.add(x, y);
+                    let mut neighbours = vec![];
+                    if 0 < x {
+                        neighbours.push(i - 1);
+                    }
+                    if x + 1 < w {
+                        neighbours.push(i + 1);
+                    }
+                    if 0 < y {
+                        neighbours.push(i - w);
+                    }
+                    if y + 1 < h {
+                        neighbours.push(i + w);
+                    }
+                    for n in neighbours {
+                        if mask[n] && !seen[n] {
+                            seen[n] = true;
+                            stack.push(n);
+                        }
+                    }
+                }
+                blobs.push(blob);
+            }
+
+            // The white bands split a pylon into stacked blobs
+            let blobs = merge_stacked(blobs);
+
+            // Back to the pixels of the image
+            let (sx, sy) = (
+                img.width() as f32 / w as f32,
+                img.height() as f32 / h as f32,
+            );
+            let min_count = self.conf.min_area * (w * h) as f32;
+            blobs
+                .iter()
+                .filter(|b| min_count <= b.count as f32)
+                .filter(|b| MIN_ASPECT * b.width() as f32 <= b.height() as f32)
+                .map(|b| {
+                    let x1 = (b.x1 as f32 * sx) as u32;
+                    let y1 = (b.y1 as f32 * sy) as u32;
+                    let x2 = ((b.x2 + 1) as f32 * sx) as u32;
+                    let y2 = ((b.y2 + 1) as f32 * sy) as u32;
+                    Detection {
                         x1,
                         y1,
                         x2,
                         y2,
-                        xc,
-                        yc,
-                        cls: merged_bbox.cls,
-                        prob: merged_bbox.prob,
-                        w,
-                        h,
-                        ids,
-                    };
-                    used[j] = true;
+                        xc: (x1 + x2) as f32 / 2.0,
+                        yc: (y1 + y2) as f32 / 2.0,
+                        w: x2 - x1,
+                        h: y2 - y1,
+                        cls: RoktrackClasses::PYLON.to_u32(),
+                        prob: b.count as f32 / (b.width() * b.height()) as f32,
+                        ..Default::default()
+                    }
+                })
+                .collect()
+        }
+
+        /// Reject the pylons without enough cone color in the box.
+        ///
+        /// # Arguments
+        ///
+        /// * `img` - The image the detections are in.
+        /// * `dets` - Detections of the pylon model. Other classes are kept as they are.
+        ///
+        pub fn verify(&self, img: &DynamicImage, dets: Vec<Detection>) -> Vec<Detection> {
+            let rgb = img.to_rgb8();
+            dets.into_iter()
+                .filter(|det| {
+                    if det.cls != RoktrackClasses::PYLON.to_u32() {
+                        return true;
+                    }
+                    // Sample about 32 * 32 pixels in the box
+                    let x2 = det.x2.min(rgb.width());
+                    let y2 = det.y2.min(rgb.height());
+                    let step_x = (det.w / 32).max(1) as usize;
+                    let step_y = (det.h / 32).max(1) as usize;
+                    let (mut cone, mut total) = (0, 0);
+                    for y in (det.y1..y2).step_by(step_y) {
+                        for x in (det.x1..x2).step_by(step_x) {
+                            total += 1;
+                            if self.is_cone(rgb.get_pixel(x, y)) {
+                                cone += 1;
+                            }
+                        }
+                    }
+                    let ratio = cone as f32 / total.max(1) as f32;
+                    if ratio < self.conf.min_ratio {
+                        log::debug!("Pylon Without Cone Color Rejected. ratio: {}", ratio);
+                    }
+                    self.conf.min_ratio <= ratio
+                })
+                .collect()
+        }
+    }
+
+    /// The color detector as a detector of the pylon model family.
+    ///
+    impl Detector for ColorDetector {
+        fn infer(
+            &self,
+            impath: &str,
+            _record_path: &str,
+        ) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+            Ok(self.detect(&image::open(impath)?))
+        }
+        fn support_ocr(&self) -> bool {
+            false
+        }
+        fn ocr(
+            &self,
+            _impath: &str,
+            dets: Vec<Detection>,
+            _property: RoktrackProperty,
+        ) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+            Ok(dets)
+        }
+        fn classes(&self) -> Vec<String> {
+            vec![String::from("pylon")]
+        }
+        fn input_size(&self) -> u32 {
+            self.size
+        }
+        fn set_input_size(&mut self, size: u32) {
+            self.size = size;
+        }
+    }
+
+    /// Bounding box of connected pixels.
+    ///
+    #[derive(Debug, Clone)]
+    struct Blob {
+        x1: usize,
+        y1: usize,
+        x2: usize, // Inclusive
+        y2: usize, // Inclusive
+        count: usize,
+    }
+
+    impl Blob {
+        fn new(x: usize, y: usize) -> Self {
+            Self {
+                x1: x,
+                y1: y,
+                x2: x,
+                y2: y,
+                count: 0,
+            }
+        }
+        fn add(&mut self, x: usize, y: usize) {
+            self.x1 = self.x1.min(x);
+            self.y1 = self.y1.min(y);
+            self.x2 = self.x2.max(x);
+            self.y2 = self.y2.max(y);
+            self.count += 1;
+        }
+        fn width(&self) -> usize {
+            self.x2 - self.x1 + 1
+        }
+        fn height(&self) -> usize {
+            self.y2 - self.y1 + 1
+        }
+        /// Whether the other blob is right above or below, within half the height.
+        fn is_stacked(&self, other: &Blob) -> bool {
+            let overlap = self.x2.min(other.x2) as i64 - self.x1.max(other.x1) as i64 + 1;
+            let gap = self.y1.max(other.y1) as i64 - self.y2.min(other.y2) as i64 - 1;
+            let narrower = self.width().min(other.width()) as i64;
+            let taller = self.height().max(other.height()) as i64;
+            narrower <= overlap * 2 && gap * 2 <= taller
+        }
+    }
+
+    /// Merge the blobs stacked on each other.
+    fn merge_stacked(mut blobs: Vec<Blob>) -> Vec<Blob> {
+        let mut merged = true;
+        while merged {
+            merged = false;
+            'outer: for i in 0..blobs.len() {
+                for j in i + 1..blobs.len() {
+                    if blobs[i].is_stacked(&blobs[j]) {
+                        let other = blobs.remove(j);
+                        let blob = &mut blobs[i];
+                        blob.x1 = blob.x1.min(other.x1);
+                        blob.y1 = blob.y1.min(other.y1);
+                        blob.x2 = blob.x2.max(other.x2);
+                        blob.y2 = blob.y2.max(other.y2);
+                        blob.count += other.count;
+                        merged = true;
+                        break 'outer;
+                    }
                 }
             }
-            merged_bboxes.push(merged_bbox);
         }
-        merged_bboxes
+        blobs
+    }
+
+    /// Convert a pixel to hue (degrees), saturation and value (0.0 -> 1.0).
+    pub fn rgb_to_hsv(pixel: &Rgb<u8>) -> (f32, f32, f32) {
+        let [r, g, b] = pixel.0.map(|c| c as f32 / 255.0);
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let d = max - min;
+        let h = if d == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / d).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / d + 2.0)
+        } else {
+            60.0 * ((r - g) / d + 4.0)
+        };
+        let s = if max == 0.0 { 0.0 } else { d / max };
+        (h, s, max)
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    //! Scripted detector for tests
+    //!
+    //! Returns the scripted detections frame by frame instead of running a model.
+    //! The ids in the script stand for the result of OCR.
+
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    use super::{Detection, Detector};
+    use crate::module::util::init::RoktrackProperty;
+
+    pub struct ScriptedDetector {
+        frames: Mutex<VecDeque<Vec<Detection>>>, // Detections of the upcoming frames
+        classes: Vec<String>,
+        ocr: bool,
+        size: u32,
+    }
+
+    impl ScriptedDetector {
+        /// Creates a scripted detector.
+        ///
+        /// # Arguments
+        ///
+        /// * `frames` - Detections of each frame in order. Frames after the script have no detections.
+        ///
+        pub fn new(frames: Vec<Vec<Detection>>) -> Self {
+            Self {
+                frames: Mutex::new(frames.into()),
+                classes: vec![
+                    String::from("pylon"),
+                    String::from("person"),
+                    String::from("roktrack"),
+                ],
+                ocr: false,
+                size: 320,
+            }
+        }
+
+        /// Report the ids in the script as read by OCR.
+        pub fn with_ocr(mut self) -> Self {
+            self.ocr = true;
+            self
+        }
+
+        /// Append a frame to the script.
+        pub fn push(&self, frame: Vec<Detection>) {
+            self.frames.lock().unwrap().push_back(frame);
+        }
+    }
+
+    impl Detector for ScriptedDetector {
+        fn infer(
+            &self,
+            _impath: &str,
+            _record_path: &str,
+        ) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+            Ok(self.frames.lock().unwrap().pop_front().unwrap_or_default())
+        }
+        fn support_ocr(&self) -> bool {
+            self.ocr
+        }
+        fn ocr(
+            &self,
+            _impath: &str,
+            dets: Vec<Detection>,
+            _property: RoktrackProperty,
+        ) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+            Ok(dets)
+        }
+        fn classes(&self) -> Vec<String> {
+            self.classes.clone()
+        }
+        fn input_size(&self) -> u32 {
+            self.size
+        }
+        fn set_input_size(&mut self, size: u32) {
+            self.size = size;
+        }
     }
 }
 
@@ -429,6 +1047,14 @@ pub enum RoktrackClasses {
 /// Convert int to RoktrackClasses
 ///
 impl RoktrackClasses {
+    pub fn from_name(name: &str) -> Option<RoktrackClasses> {
+        match name {
+            "pylon" => Some(RoktrackClasses::PYLON),
+            "person" => Some(RoktrackClasses::PERSON),
+            "roktrack" => Some(RoktrackClasses::ROKTRACK),
+            _ => None,
+        }
+    }
     pub fn from_u32(i: u32) -> Option<RoktrackClasses> {
         match i {
             0 => Some(RoktrackClasses::PYLON),
@@ -473,12 +1099,31 @@ pub enum AnimalClasses {
     DOG,
     FOX,
     HARE,
+    MICE,
     RACOON,
     SQUIRREL,
 }
 /// AnimalClasses methods
 ///
 impl AnimalClasses {
+    pub fn from_name(name: &str) -> Option<AnimalClasses> {
+        match name {
+            "bear" => Some(AnimalClasses::BEAR),
+            "deer" => Some(AnimalClasses::DEER),
+            "monkey" => Some(AnimalClasses::MONKEY),
+            "boar" => Some(AnimalClasses::BOAR),
+            "badger" => Some(AnimalClasses::BADGER),
+            "cat" => Some(AnimalClasses::CAT),
+            "civet" => Some(AnimalClasses::CIVET),
+            "dog" => Some(AnimalClasses::DOG),
+            "fox" => Some(AnimalClasses::FOX),
+            "hare" => Some(AnimalClasses::HARE),
+            "mice" => Some(AnimalClasses::MICE),
+            "racoon" => Some(AnimalClasses::RACOON),
+            "squirrel" => Some(AnimalClasses::SQUIRREL),
+            _ => None,
+        }
+    }
     pub fn from_u32(i: u32) -> Option<AnimalClasses> {
         match i {
             0 => Some(AnimalClasses::BEAR),
@@ -491,8 +1136,9 @@ impl AnimalClasses {
             7 => Some(AnimalClasses::DOG),
             8 => Some(AnimalClasses::FOX),
             9 => Some(AnimalClasses::HARE),
-            10 => Some(AnimalClasses::RACOON),
-            11 => Some(AnimalClasses::SQUIRREL),
+            10 => Some(AnimalClasses::MICE),
+            11 => Some(AnimalClasses::RACOON),
+            12 => Some(AnimalClasses::SQUIRREL),
             _ => None,
         }
     }
@@ -508,8 +1154,9 @@ impl AnimalClasses {
             AnimalClasses::DOG => 7,
             AnimalClasses::FOX => 8,
             AnimalClasses::HARE => 9,
-            AnimalClasses::RACOON => 10,
-            AnimalClasses::SQUIRREL => 11,
+            AnimalClasses::MICE => 10,
+            AnimalClasses::RACOON => 11,
+            AnimalClasses::SQUIRREL => 12,
         }
     }
 }
@@ -540,6 +1187,13 @@ pub struct Detection {
     pub w: u32,
     pub h: u32,
     pub ids: Vec<u8>,
+
+    pub track_id: Option<u32>, // Id of the track across frames, None until tracked
+
+    pub distance: Option<f32>, // Range to the object (metres), None if its height is unknown
+    pub bearing: Option<f32>,  // Degrees to the right of the camera axis, None until estimated
+
+    pub id_prob: Option<f32>, // Confidence of the ids (0.0 -> 1.0), None if not read by the OCR
 }
 /// Detection default method.
 ///
@@ -566,6 +1220,10 @@ impl Detection {
             w: 0,
             h: 0,
             ids: vec![],
+            id_prob: None,
+            track_id: None,
+            distance: None,
+            bearing: None,
         }
     }
 }
@@ -633,6 +1291,10 @@ mod tests {
             w: 10,
             h: 10,
             ids: vec![],
+            id_prob: None,
+            track_id: None,
+            distance: None,
+            bearing: None,
         };
         // left top big
         let d1 = Detection {
@@ -647,6 +1309,10 @@ mod tests {
             w: 10,
             h: 15,
             ids: vec![],
+            id_prob: None,
+            track_id: None,
+            distance: None,
+            bearing: None,
         };
         // right bottom small
         let d2 = Detection {
@@ -661,6 +1327,10 @@ mod tests {
             w: 10,
             h: 5,
             ids: vec![],
+            id_prob: None,
+            track_id: None,
+            distance: None,
+            bearing: None,
         };
         let mut dets = [d0.clone(), d1.clone(), d2.clone()];
         let right = sort::right(&mut dets).first().unwrap().clone();
@@ -693,10 +1363,184 @@ mod tests {
         assert!(dets.unwrap().len() == 1);
     }
 
+    #[test]
+    fn letterbox_test() {
+        // 1280x720 into 320x320: 0.25 scale and 70 pixels of padding on the top and bottom
+        let letterbox = onnx::Letterbox::new(1280, 720, 320);
+        assert_eq!(letterbox.scale, 0.25);
+        assert_eq!(letterbox.pad_x, 0.0);
+        assert_eq!(letterbox.pad_y, 70.0);
+        let img = image::DynamicImage::new_rgb8(1280, 720);
+        let (resized, _) = onnx::Letterbox::apply(&img, 320);
+        assert_eq!(resized.dimensions(), (320, 320));
+        assert_eq!(resized.get_pixel(0, 0).0, [114, 114, 114]);
+        assert_eq!(resized.get_pixel(160, 160).0, [0, 0, 0]);
+        // A pylon in the middle keeps its aspect ratio
+        let det = Detection {
+            x1: 150,
+            y1: 140,
+            x2: 170,
+            y2: 180,
+            xc: 160.0,
+            yc: 160.0,
+            w: 20,
+            h: 40,
+            ..Default::default()
+        };
+        let det = letterbox.to_original(&det, 1280, 720);
+        assert_eq!((det.x1, det.y1, det.x2, det.y2), (600, 280, 680, 440));
+        assert_eq!((det.w, det.h), (80, 160));
+        assert_eq!((det.xc, det.yc), (640.0, 360.0));
+    }
+
+    #[test]
+    fn nms_test() {
+        let pylon = |x1: u32, prob: f32| Detection {
+            x1,
+            y1: 0,
+            x2: x1 + 99,
+            y2: 99,
+            cls: RoktrackClasses::PYLON.to_u32(),
+            prob,
+            w: 100,
+            h: 100,
+            ..Default::default()
+        };
+        let person = Detection {
+            cls: RoktrackClasses::PERSON.to_u32(),
+            ..pylon(5, 0.8)
+        };
+        let mut param = crate::module::util::conf::NmsParam::default();
+        let candidates = vec![
+            pylon(10, 0.6),
+            pylon(0, 0.9),
+            pylon(300, 0.7),
+            person.clone(),
+            pylon(600, 0.05),
+        ];
+        // The overlapping pylon is suppressed without inflating the best one
+        let dets = onnx::non_max_suppression(candidates.clone(), &param);
+        assert_eq!(dets, vec![pylon(0, 0.9), person.clone(), pylon(300, 0.7)]);
+        // Soft-NMS keeps the overlapping pylon with a lower probability
+        param.soft = true;
+        let dets = onnx::non_max_suppression(candidates, &param);
+        assert_eq!(dets.len(), 4);
+        assert_eq!(dets[3].x1, 10);
+        assert!(dets[3].prob < 0.6);
+    }
+
+    #[test]
+    fn fake_detector_test() {
+        let pylon = Detection {
+            cls: RoktrackClasses::PYLON.to_u32(),
+            prob: 0.9,
+            ids: vec![3],
+            ..Default::default()
+        };
+        let mut detector = fake::ScriptedDetector::new(vec![vec![pylon.clone()], vec![]]);
+        assert!(!detector.support_ocr());
+        assert_eq!(detector.infer("", "").unwrap(), vec![pylon.clone()]);
+        assert!(detector.infer("", "").unwrap().is_empty());
+        // After the script
+        assert!(detector.infer("", "").unwrap().is_empty());
+        detector.push(vec![pylon.clone(), pylon.clone()]);
+        assert_eq!(detector.infer("", "").unwrap().len(), 2);
+        detector.set_input_size(640);
+        assert_eq!(detector.input_size(), 640);
+        assert_eq!(detector.classes()[0], "pylon");
+        let detector = detector.with_ocr();
+        assert!(detector.support_ocr());
+    }
+
+    #[test]
+    fn assemble_test() {
+        let digit = |x1: u32, y1: u32, prob: f32, value: u8| {
+            let det = Detection {
+                x1,
+                y1,
+                x2: x1 + 10,
+                y2: y1 + 20,
+                xc: x1 as f32 + 5.0,
+                yc: y1 as f32 + 10.0,
+                w: 10,
+                h: 20,
+                prob,
+                ..Default::default()
+            };
+            (det, value)
+        };
+        // "12" read right to left, and a "7" apart from it
+        let numbers = onnx::assemble(vec![
+            digit(42, 10, 0.8, 2),
+            digit(80, 10, 0.9, 7),
+            digit(30, 12, 0.6, 1),
+        ]);
+        assert_eq!(numbers, vec![(12, 0.6), (7, 0.9)]);
+        // Digits on different lines aren't one number
+        let numbers = onnx::assemble(vec![digit(30, 10, 0.9, 1), digit(42, 40, 0.9, 2)]);
+        assert_eq!(numbers, vec![(1, 0.9), (2, 0.9)]);
+        // "12" stacked over "34"
+        let numbers = onnx::assemble(vec![
+            digit(30, 40, 0.9, 3),
+            digit(30, 10, 0.9, 1),
+            digit(42, 40, 0.9, 4),
+            digit(42, 10, 0.9, 2),
+        ]);
+        assert_eq!(numbers, vec![(12, 0.9), (34, 0.9)]);
+        // Numbers out of range are dropped
+        let numbers = onnx::assemble(vec![
+            digit(30, 10, 0.9, 3),
+            digit(42, 10, 0.9, 0),
+            digit(54, 10, 0.9, 0),
+        ]);
+        assert!(numbers.is_empty());
+    }
+
+    #[test]
+    fn color_detector_test() {
+        let detector = color::ColorDetector::new(crate::module::util::conf::Color::default());
+        assert_eq!(color::rgb_to_hsv(&image::Rgb([255, 90, 0])).0.round(), 21.0);
+        // A pylon with a white band and a flat orange toy on the grass
+        let img = image::RgbImage::from_fn(640, 360, |x, y| {
+            let (x, y) = (x as i32, y as i32);
+            if (100..300).contains(&y) && (x - 200).abs() <= 10 + (y - 100) / 5 {
+                match (180..200).contains(&y) {
+                    true => image::Rgb([240, 240, 240]),
+                    false => image::Rgb([255, 90, 0]),
+                }
+            } else if (400..500).contains(&x) && (300..320).contains(&y) {
+                image::Rgb([255, 90, 0])
+            } else {
+                image::Rgb([60, 120, 40])
+            }
+        });
+        let img = image::DynamicImage::ImageRgb8(img);
+        let dets = detector.detect(&img);
+        assert_eq!(dets.len(), 1);
+        let pylon = dets[0].clone();
+        assert!(pylon.x1.abs_diff(150) <= 8 && pylon.x2.abs_diff(250) <= 8);
+        assert!(pylon.y1.abs_diff(100) <= 8 && pylon.y2.abs_diff(300) <= 8);
+        // Only the pylon boxes on the cone color are kept
+        let grass = Detection {
+            x1: 500,
+            y1: 50,
+            x2: 600,
+            y2: 250,
+            w: 100,
+            h: 200,
+            ..Default::default()
+        };
+        let person = Detection {
+            cls: RoktrackClasses::PERSON.to_u32(),
+            ..grass.clone()
+        };
+        let dets = detector.verify(&img, vec![pylon.clone(), grass, person.clone()]);
+        assert_eq!(dets, vec![pylon, person]);
+    }
+
     #[test]
     fn animal_detect_object_test() {
-        let mut detector = onnx::YoloV8::new();
-        let _ = detector.build_animal_sessions();
+        let detector = onnx::YoloV8::animal(&Default::default()).unwrap();
         let dets = detector.infer("asset/img/bear.jpg", onnx::SessionType::Sz320, "");
         let dets = AnimalClasses::filter(&mut dets.unwrap(), AnimalClasses::BEAR.to_u32(), 0.0);
         assert!(dets.len() == 1);
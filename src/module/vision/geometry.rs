@@ -0,0 +1,143 @@
+//! Monocular Distance and Bearing Estimation
+//!
+//! With a pinhole camera, an object of known height H at distance D appears
+//! h = f * H / D pixels tall, where f is the focal length in pixels derived from the field of view.
+
+use std::collections::HashMap;
+
+use super::detector::Detection;
+use crate::module::util::conf::Config;
+
+/// Estimates the distance and the bearing of the detections.
+///
+#[derive(Debug, Clone)]
+pub struct Geometry {
+    focal: f32,                    // Focal length (pixels of the original image)
+    cx: f32,                       // Horizontal center of the image (pixels)
+    img_height: u32,               // Height of the original image (pixels)
+    heights: HashMap<String, f32>, // Known heights of the classes (metres)
+    reach: f32,                    // Distance to regard the pylon as reached (metres)
+}
+
+impl Geometry {
+    /// Geometry's constructor.
+    ///
+    /// # Arguments
+    ///
+    /// * `conf` - Config holding the camera and the known heights.
+    ///
+    pub fn new(conf: &Config) -> Self {
+        let cx = conf.camera.width as f32 / 2.0;
+        Self {
+            focal: cx / (conf.camera.hfov.to_radians() / 2.0).tan(),
+            cx,
+            img_height: conf.camera.height as u32,
+            heights: conf.geometry.heights.clone(),
+            reach: conf.geometry.reach,
+        }
+    }
+
+    /// Distance to an object from its bbox height.
+    ///
+    /// # Arguments
+    ///
+    /// * `h` - Bbox height (pixels).
+    /// * `height` - Known height of the object (metres).
+    ///
+    /// # Returns
+    ///
+    /// The distance in metres, or `None` for an empty bbox.
+    pub fn distance(&self, h: u32, height: f32) -> Option<f32> {
+        (0 < h).then(|| height * self.focal / h as f32)
+    }
+
+    /// Bbox height of an object at a distance, the inverse of `distance`.
+    pub fn height_at(&self, distance: f32, height: f32) -> f32 {
+        height * self.focal / distance
+    }
+
+    /// Bearing of an image column in degrees, positive to the right of the camera axis.
+    pub fn bearing(&self, xc: f32) -> f32 {
+        ((xc - self.cx) / self.focal).atan().to_degrees()
+    }
+
+    /// Bbox height of the pylon to regard it as reached.
+    ///
+    /// The configured reach distance, or 90% of the image height if it isn't set.
+    pub fn reach_height(&self) -> u16 {
+        match self.heights.get("pylon") {
+            Some(height) if 0.0 < self.reach => self.height_at(self.reach, *height).round() as u16,
+            _ => (self.img_height as f32 * 0.9) as u16,
+        }
+    }
+
+    /// Attach the distance and the bearing to the detections.
+    ///
+    /// # Arguments
+    ///
+    /// * `dets` - Detections in the pixels of the original image.
+    /// * `classes` - Class names of the detector in the order of the class ids.
+    ///
+    pub fn estimate(&self, dets: &mut [Detection], classes: &[String]) {
+        for det in dets.iter_mut() {
+            det.bearing = Some(self.bearing(det.xc));
+            det.distance = classes
+                .get(det.cls as usize)
+                .and_then(|name| self.heights.get(name))
+                .and_then(|height| self.distance(det.h, *height));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: Option<f32>, b: f32) -> bool {
+        matches!(a, Some(a) if (a - b).abs() < 1e-4)
+    }
+
+    #[test]
+    fn geometry_test() {
+        let mut conf = crate::module::util::init::resource::init().conf;
+        conf.camera.width = 1280;
+        conf.camera.height = 720;
+        conf.camera.hfov = 90.0;
+        let geometry = Geometry::new(&conf);
+        // The focal length is half the width with a 90 degree field of view
+        assert!(approx(geometry.distance(320, 0.4), 0.8));
+        assert_eq!(geometry.distance(0, 0.4), None);
+        assert!(approx(Some(geometry.height_at(0.8, 0.4)), 320.0));
+        assert_eq!(geometry.bearing(640.0), 0.0);
+        assert!(approx(Some(geometry.bearing(1280.0)), 45.0));
+        assert!(approx(Some(geometry.bearing(0.0)), -45.0));
+        // Reach by the image height, or by the distance
+        assert_eq!(geometry.reach_height(), 648);
+        conf.geometry.reach = 0.5;
+        assert_eq!(Geometry::new(&conf).reach_height(), 512);
+        // Only the classes with a known height get the distance
+        let classes: Vec<String> = ["pylon", "person", "roktrack"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        let mut dets = vec![
+            Detection {
+                cls: 0,
+                xc: 640.0,
+                h: 320,
+                ..Default::default()
+            },
+            Detection {
+                cls: 2,
+                xc: 640.0,
+                h: 320,
+                ..Default::default()
+            },
+        ];
+        geometry.estimate(&mut dets, &classes);
+        assert!(approx(dets[0].distance, 0.8));
+        assert_eq!(dets[0].bearing, Some(0.0));
+        assert_eq!(dets[1].distance, None);
+        assert_eq!(dets[1].bearing, Some(0.0));
+    }
+}
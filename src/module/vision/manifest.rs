@@ -0,0 +1,122 @@
+//! Model Manifest
+//!
+//! The models, the files of their class names and their default thresholds are listed
+//! in `asset/model/manifest.toml`, so a retrained model can be dropped in without recompiling.
+
+use std::fs;
+
+use serde::Deserialize;
+
+use crate::module::util::conf::NmsParam;
+
+/// Model families of the manifest.
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct Manifest {
+    pub pylon: ModelFamily,  // Pylon, person and roktrack detection
+    pub animal: ModelFamily, // Animal detection
+    pub digit: ModelFamily,  // Digit OCR
+}
+
+/// Models of a family sharing the classes.
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelFamily {
+    pub classes: String,    // Path of the class names file
+    pub models: Vec<Model>, // Models by input size
+    #[serde(default)]
+    pub nms: NmsParam, // Default NMS thresholds
+}
+
+/// A model of an input size.
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct Model {
+    pub size: u32,    // Input size (pixels)
+    pub path: String, // Path of the ONNX file
+}
+
+impl Manifest {
+    /// Loads the manifest.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path of the manifest file.
+    ///
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+}
+
+impl ModelFamily {
+    /// The model of the smallest input size.
+    pub fn small(&self) -> Result<&Model, Box<dyn std::error::Error>> {
+        self.models
+            .iter()
+            .min_by_key(|m| m.size)
+            .ok_or_else(|| format!("No model in {}", self.classes).into())
+    }
+
+    /// The model of the largest input size.
+    pub fn large(&self) -> Result<&Model, Box<dyn std::error::Error>> {
+        self.models
+            .iter()
+            .max_by_key(|m| m.size)
+            .ok_or_else(|| format!("No model in {}", self.classes).into())
+    }
+
+    /// Loads the class names in the order of the class ids.
+    pub fn load_classes(&self) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        parse_classes(&fs::read_to_string(&self.classes)?)
+    }
+}
+
+/// Parse the class names file.
+///
+/// Each line is `<id>: <name>`, and the ids must be 0, 1, 2, ... in order.
+///
+/// # Returns
+///
+/// The class names in the order of the class ids.
+pub fn parse_classes(text: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut classes = vec![];
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let (id, name) = line
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid class line: {}", line))?;
+        if id.trim().parse::<usize>()? != classes.len() {
+            return Err(format!("Class id out of order: {}", line).into());
+        }
+        classes.push(name.trim().to_string());
+    }
+    Ok(classes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::vision::detector::AnimalClasses;
+
+    #[test]
+    fn manifest_test() {
+        // The shipped manifest and class files
+        let manifest = Manifest::load(crate::module::define::path::MODEL_MANIFEST).unwrap();
+        assert_eq!(
+            manifest.pylon.load_classes().unwrap(),
+            vec!["pylon", "person", "roktrack"]
+        );
+        // The class files agree with the class ids the pilots rely on
+        for (i, name) in manifest.animal.load_classes().unwrap().iter().enumerate() {
+            assert_eq!(AnimalClasses::from_name(name).unwrap().to_u32(), i as u32);
+        }
+        assert_eq!(manifest.digit.load_classes().unwrap().len(), 10);
+        assert_eq!(manifest.pylon.small().unwrap().size, 320);
+        assert_eq!(manifest.pylon.large().unwrap().size, 640);
+        assert_eq!(manifest.digit.small().unwrap().size, 96);
+        assert_eq!(manifest.digit.large().unwrap().size, 96);
+        // Broken class files
+        assert!(parse_classes("0: pylon\r\n2: person").is_err());
+        assert!(parse_classes("pylon").is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! Frame-to-frame Motion Estimation
+//!
+
+use image::{imageops::FilterType, DynamicImage, GrayImage};
+
+// Size of the thumbnail used for comparison.
+// Small enough to be cheap on the Raspberry Pi, large enough to see the grass move.
+const THUMB_WIDTH: u32 = 64;
+const THUMB_HEIGHT: u32 = 36;
+
+/// Keeps the previous frame and measures how much the scene has changed.
+///
+pub struct MotionEstimator {
+    last: Option<GrayImage>, // Thumbnail of the previous frame
+}
+
+impl Default for MotionEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MotionEstimator {
+    /// MotionEstimator's constructor.
+    ///
+    pub fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Feed a new frame and get the motion score against the previous one.
+    ///
+    /// Returns `None` for the very first frame.
+    pub fn update(&mut self, img: &DynamicImage) -> Option<f32> {
+        let thumb = img
+            .resize_exact(THUMB_WIDTH, THUMB_HEIGHT, FilterType::Triangle)
+            .to_luma8();
+        let score = self.last.as_ref().map(|last| frame_diff(last, &thumb));
+        self.last = Some(thumb);
+        score
+    }
+
+    /// Forget the previous frame.
+    ///
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// Mean absolute difference of two grayscale images.
+///
+/// The result is normalized to the range 0.0 (same) -> 1.0 (inverted).
+pub fn frame_diff(a: &GrayImage, b: &GrayImage) -> f32 {
+    if a.dimensions() != b.dimensions() || a.is_empty() {
+        return 0.0;
+    }
+    let sum: u64 = a
+        .pixels()
+        .zip(b.pixels())
+        .map(|(pa, pb)| (pa[0] as i16 - pb[0] as i16).unsigned_abs() as u64)
+        .sum();
+    sum as f32 / (a.len() as f32 * 255.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn frame_diff_test() {
+        let black = GrayImage::from_pixel(4, 4, Luma([0]));
+        let white = GrayImage::from_pixel(4, 4, Luma([255]));
+        let gray = GrayImage::from_pixel(4, 4, Luma([51]));
+        assert_eq!(frame_diff(&black, &black), 0.0);
+        assert_eq!(frame_diff(&black, &white), 1.0);
+        assert_eq!(frame_diff(&black, &gray), 0.2);
+    }
+
+    #[test]
+    fn motion_estimator_test() {
+        let mut estimator = MotionEstimator::new();
+        let still = DynamicImage::ImageLuma8(GrayImage::from_pixel(128, 72, Luma([100])));
+        let moved = DynamicImage::ImageLuma8(GrayImage::from_pixel(128, 72, Luma([200])));
+        // The first frame has nothing to compare with.
+        assert_eq!(estimator.update(&still), None);
+        assert_eq!(estimator.update(&still), Some(0.0));
+        assert!(estimator.update(&moved).unwrap() > 0.3);
+        estimator.reset();
+        assert_eq!(estimator.update(&moved), None);
+    }
+}
@@ -0,0 +1,411 @@
+//! Fiducial Tag Reading
+//!
+//! Reads AprilTag 16h5 tags stuck on the pylons as their ids. A tag is a 6 x 6 grid of cells,
+//! a black border around 4 x 4 data bits, on a white margin. The bits hold up at a distance
+//! and in poor light better than the digit OCR, and a bit error or two is corrected.
+
+use image::{imageops, DynamicImage, GrayImage};
+
+use super::detector::{Detection, RoktrackClasses};
+use crate::module::util::conf;
+
+/// Codes of the tag16h5 family by id. The data bits row by row from the top left, white is 1.
+const TAG16H5: [u16; 30] = [
+    0x231b, 0x2ea5, 0x346a, 0x45b9, 0x79a6, 0x7f6b, 0xb358, 0xe745, 0xfe59, 0x156d, 0x380b, 0xf0ab,
+    0x0d84, 0x4736, 0x8c72, 0xaf10, 0x093c, 0x93b4, 0xa503, 0x468f, 0xe137, 0x5795, 0xdf42, 0x1c1d,
+    0xe9dc, 0x73ad, 0xad5f, 0xd530, 0x07ca, 0xaf2e,
+];
+
+// Cells across a tag, the black border included
+const CELLS: usize = 6;
+// A pixel darker than the mean of its neighbourhood by this much is dark
+const THRESHOLD_OFFSET: i64 = 10;
+// Minimum side of a tag (pixels), about 2 pixels per cell
+const MIN_SIDE: f32 = 12.0;
+// Minimum difference between the black border and the white bits
+const MIN_CONTRAST: f32 = 30.0;
+
+/// A tag found in an image.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub id: u8,       // Tag id
+    pub xc: f32,      // Center in the pixels of the image
+    pub yc: f32,      // Center in the pixels of the image
+    pub hamming: u32, // Bit errors corrected
+}
+
+/// Reads the tags and gives the pylons their ids.
+///
+pub struct TagReader {
+    conf: conf::Tag,
+}
+
+impl TagReader {
+    /// TagReader's constructor.
+    ///
+    pub fn new(conf: conf::Tag) -> Self {
+        Self { conf }
+    }
+
+    /// Whether the tags are a source of the pylon ids.
+    pub fn enabled(&self) -> bool {
+        matches!(self.conf.id_source.as_str(), "tag" | "both")
+    }
+
+    /// Find the tags in a grayscale image.
+    ///
+    /// # Returns
+    ///
+    /// The tags in the pixels of the image.
+    pub fn decode(&self, img: &GrayImage) -> Vec<Tag> {
+        let (w, h) = (img.width() as usize, img.height() as usize);
+        let dark = threshold(img);
+
+        // Connected dark pixels, the black border of a tag being one of them
+        let mut tags = vec![];
+        let mut seen = vec![false; w * h];
+        for start in 0..w * h {
+            if !dark[start] || seen[start] {
+                continue;
+            }
+            seen[start] = true;
+            let mut stack = vec![start];
+            let mut pixels = vec![];
+            let mut on_edge = false;
+            while let Some(i) = stack.pop() {
+                let (x, y) = (i % w, i / w);
+                pixels.push((x as f32 + 0.5, y as f32 + 0.5));
+                on_edge |= x == 0 || y == 0 || x + 1 == w || y + 1 == h;
+                let mut neighbours = vec![];
+                if 0 < x {
+                    neighbours.push(i - 1);
+                }
+                if x + 1 < w {
+                    neighbours.push(i + 1);
+                }
+                if 0 < y {
+                    neighbours.push(i - w);
+                }
+                if y + 1 < h {
+                    neighbours.push(i + w);
+                }
+                for n in neighbours {
+                    if dark[n] && !seen[n] {
+                        seen[n] = true;
+                        stack.push(n);
+                    }
+                }
+            }
+            // A tag cut by the edge of the image can't be read
+            if on_edge || pixels.len() < (MIN_SIDE * 4.0) as usize {
+                continue;
+            }
+            if let Some(tag) = quad(&pixels).and_then(|q| self.read_quad(img, &q)) {
+                tags.push(tag);
+            }
+        }
+        tags
+    }
+
+    /// Give the pylons the ids of the tags on them.
+    ///
+    /// The ids of the tags take precedence over the ids of the OCR.
+    ///
+    /// # Arguments
+    ///
+    /// * `img` - The image the detections are in.
+    /// * `dets` - Detections of the pylon model. Other classes are kept as they are.
+    ///
+    pub fn read(&self, img: &DynamicImage, mut dets: Vec<Detection>) -> Vec<Detection> {
+        let gray = img.to_luma8();
+        // Decode the full frame once, or the crop of each pylon
+        let frame_tags = match self.conf.frame {
+            true => self.decode(&gray),
+            false => vec![],
+        };
+        for det in dets
+            .iter_mut()
+            .filter(|d| d.cls == RoktrackClasses::PYLON.to_u32())
+        {
+            let x2 = det.x2.min(gray.width());
+            let y2 = det.y2.min(gray.height());
+            if x2 <= det.x1 + 10 || y2 <= det.y1 + 10 {
+                continue;
+            }
+            let tags = match self.conf.frame {
+                true => frame_tags
+                    .iter()
+                    .filter(|t| det.x1 as f32 <= t.xc && t.xc < x2 as f32)
+                    .filter(|t| det.y1 as f32 <= t.yc && t.yc < y2 as f32)
+                    .cloned()
+                    .collect(),
+                false => {
+                    let crop = imageops::crop_imm(&gray, det.x1, det.y1, x2 - det.x1, y2 - det.y1);
+                    self.decode(&crop.to_image())
+                }
+            };
+            let mut ids: Vec<u8> = tags.iter().map(|t| t.id).collect();
+            ids.sort();
+            ids.dedup();
+            if !ids.is_empty() {
+                log::debug!("Tags Read: {:?}", tags);
+                det.ids = ids;
+            }
+        }
+        dets
+    }
+
+    /// Sample the cells of a quad and match the bits to the codes.
+    fn read_quad(&self, img: &GrayImage, corners: &[(f32, f32); 4]) -> Option<Tag> {
+        let homography = Homography::new(corners)?;
+        let mut cells = [[0.0; CELLS]; CELLS];
+        for (i, row) in cells.iter_mut().enumerate() {
+            for (j, cell) in row.iter_mut().enumerate() {
+                let u = (j as f32 + 0.5) / CELLS as f32;
+                let v = (i as f32 + 0.5) / CELLS as f32;
+                let (x, y) = homography.map(u, v);
+                if x < 0.0 || y < 0.0 || img.width() as f32 <= x || img.height() as f32 <= y {
+                    return None;
+                }
+                *cell = img.get_pixel(x as u32, y as u32)[0] as f32;
+            }
+        }
+
+        // Threshold between the black border and the brightest bit
+        let is_border = |i: usize, j: usize| i == 0 || j == 0 || i == CELLS - 1 || j == CELLS - 1;
+        let (mut border, mut bright) = (vec![], 0.0f32);
+        for (i, row) in cells.iter().enumerate() {
+            for (j, cell) in row.iter().enumerate() {
+                match is_border(i, j) {
+                    true => border.push(*cell),
+                    false => bright = bright.max(*cell),
+                }
+            }
+        }
+        let black = border.iter().sum::<f32>() / border.len() as f32;
+        if bright - black < MIN_CONTRAST {
+            return None;
+        }
+        let thresh = (black + bright) / 2.0;
+        if 1 < border.iter().filter(|c| thresh < **c).count() {
+            return None;
+        }
+
+        // Data bits in the 4 orientations
+        let mut bits = [[false; 4]; 4];
+        for (r, row) in bits.iter_mut().enumerate() {
+            for (k, bit) in row.iter_mut().enumerate() {
+                *bit = thresh < cells[r + 1][k + 1];
+            }
+        }
+        let (mut hamming, mut id) = (u32::MAX, 0);
+        for _ in 0..4 {
+            let code = bits
+                .iter()
+                .flatten()
+                .fold(0u16, |code, bit| code << 1 | *bit as u16);
+            for (i, c) in TAG16H5.iter().enumerate() {
+                let d = (code ^ c).count_ones();
+                if d < hamming {
+                    (hamming, id) = (d, i as u8);
+                }
+            }
+            bits = rotate(&bits);
+        }
+        let (xc, yc) = homography.map(0.5, 0.5);
+        (hamming <= self.conf.max_hamming).then_some(Tag {
+            id,
+            xc,
+            yc,
+            hamming,
+        })
+    }
+}
+
+/// Mark the pixels darker than their neighbourhood, so uneven light doesn't matter.
+fn threshold(img: &GrayImage) -> Vec<bool> {
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    // Integral image
+    let mut sum = vec![0i64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row = 0;
+        for x in 0..w {
+            row += img.get_pixel(x as u32, y as u32)[0] as i64;
+            sum[(y + 1) * (w + 1) + x + 1] = sum[y * (w + 1) + x + 1] + row;
+        }
+    }
+    let r = (w.max(h) / 16).max(3);
+    let mut dark = vec![false; w * h];
+    for y in 0..h {
+        let (y1, y2) = (y.saturating_sub(r), (y + r + 1).min(h));
+        for x in 0..w {
+            let (x1, x2) = (x.saturating_sub(r), (x + r + 1).min(w));
+            let total = sum[y2 * (w + 1) + x2] - sum[y1 * (w + 1) + x2] - sum[y2 * (w + 1) + x1]
+                + sum[y1 * (w + 1) + x1];
+            let area = ((x2 - x1) * (y2 - y1)) as i64;
+            let pixel = img.get_pixel(x as u32, y as u32)[0] as i64;
+            dark[y * w + x] = (pixel + THRESHOLD_OFFSET) * area < total;
+        }
+    }
+    dark
+}
+
+/// Corners of the quad around the pixels, clockwise on the image.
+fn quad(pixels: &[(f32, f32)]) -> Option<[(f32, f32); 4]> {
+    let n = pixels.len() as f32;
+    let cx = pixels.iter().map(|p| p.0).sum::<f32>() / n;
+    let cy = pixels.iter().map(|p| p.1).sum::<f32>() / n;
+    let farthest = |from: (f32, f32)| {
+        *pixels
+            .iter()
+            .max_by(|a, b| dist2(**a, from).total_cmp(&dist2(**b, from)))
+            .unwrap()
+    };
+    // Two opposite corners, then the farthest on each side of their diagonal
+    let c0 = farthest((cx, cy));
+    let c2 = farthest(c0);
+    let side = |p: &(f32, f32)| (c2.0 - c0.0) * (p.1 - c0.1) - (c2.1 - c0.1) * (p.0 - c0.0);
+    let c1 = *pixels.iter().max_by(|a, b| side(a).total_cmp(&side(b)))?;
+    let c3 = *pixels.iter().min_by(|a, b| side(a).total_cmp(&side(b)))?;
+    let mut corners = [c0, c1, c2, c3];
+    for i in 0..4 {
+        if dist2(corners[i], corners[(i + 1) % 4]).sqrt() < MIN_SIDE {
+            return None;
+        }
+    }
+    corners.sort_by(|a, b| {
+        let angle = |p: &(f32, f32)| (p.1 - cy).atan2(p.0 - cx);
+        angle(a).total_cmp(&angle(b))
+    });
+    Some(corners)
+}
+
+fn dist2(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)
+}
+
+/// Rotate the bits clockwise.
+fn rotate(bits: &[[bool; 4]; 4]) -> [[bool; 4]; 4] {
+    let mut rotated = [[false; 4]; 4];
+    for (r, row) in rotated.iter_mut().enumerate() {
+        for (k, bit) in row.iter_mut().enumerate() {
+            *bit = bits[3 - k][r];
+        }
+    }
+    rotated
+}
+
+/// Projective mapping of the unit square onto a quad.
+///
+struct Homography {
+    h: [f32; 8],
+}
+
+impl Homography {
+    /// Map (0, 0), (1, 0), (1, 1) and (0, 1) to the corners.
+    fn new(corners: &[(f32, f32); 4]) -> Option<Self> {
+        let [(x0, y0), (x1, y1), (x2, y2), (x3, y3)] = *corners;
+        let (sx, sy) = (x0 - x1 + x2 - x3, y0 - y1 + y2 - y3);
+        let (dx1, dx2, dy1, dy2) = (x1 - x2, x3 - x2, y1 - y2, y3 - y2);
+        let den = dx1 * dy2 - dx2 * dy1;
+        if den.abs() < f32::EPSILON {
+            return None;
+        }
+        let g = (sx * dy2 - dx2 * sy) / den;
+        let h = (dx1 * sy - sx * dy1) / den;
+        Some(Self {
+            h: [
+                x1 - x0 + g * x1,
+                x3 - x0 + h * x3,
+                x0,
+                y1 - y0 + g * y1,
+                y3 - y0 + h * y3,
+                y0,
+                g,
+                h,
+            ],
+        })
+    }
+
+    fn map(&self, u: f32, v: f32) -> (f32, f32) {
+        let [a, b, c, d, e, f, g, h] = self.h;
+        let z = g * u + h * v + 1.0;
+        ((a * u + b * v + c) / z, (d * u + e * v + f) / z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tag on a gray pylon, rotated, with 10 pixels per cell.
+    fn render(code: u16, angle: f32) -> GrayImage {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        GrayImage::from_fn(200, 300, |x, y| {
+            let (dx, dy) = (x as f32 - 100.0, y as f32 - 150.0);
+            // Cells of the tag with the white margin, 8 across
+            let u = (cos * dx + sin * dy) / 10.0 + 4.0;
+            let v = (-sin * dx + cos * dy) / 10.0 + 4.0;
+            if !(0.0..8.0).contains(&u) || !(0.0..8.0).contains(&v) {
+                return image::Luma([150]);
+            }
+            let (i, j) = (v as usize, u as usize);
+            let white = match (i, j) {
+                (0 | 7, _) | (_, 0 | 7) => true,
+                (1 | 6, _) | (_, 1 | 6) => false,
+                _ => code >> (15 - ((i - 2) * 4 + (j - 2))) & 1 == 1,
+            };
+            image::Luma([if white { 230 } else { 30 }])
+        })
+    }
+
+    #[test]
+    fn tag_test() {
+        let mut conf = conf::Tag::default();
+        let reader = TagReader::new(conf.clone());
+        assert!(!reader.enabled());
+        // Tags rotated in any direction
+        for (id, angle) in [(7, 0.0), (12, 20.0), (29, 110.0), (0, -160.0)] {
+            let tags = reader.decode(&render(TAG16H5[id], angle));
+            assert_eq!(tags.len(), 1);
+            assert_eq!((tags[0].id, tags[0].hamming), (id as u8, 0));
+            assert!((tags[0].xc - 100.0).abs() < 2.0 && (tags[0].yc - 150.0).abs() < 2.0);
+        }
+        // A bit error is corrected, but not two
+        let tags = reader.decode(&render(TAG16H5[5] ^ 0x0100, 30.0));
+        assert_eq!((tags[0].id, tags[0].hamming), (5, 1));
+        assert!(reader.decode(&render(TAG16H5[5] ^ 0x0101, 30.0)).is_empty());
+        // No tags on a plain image
+        assert!(reader.decode(&render(0, 0.0)).is_empty());
+
+        // The pylons get the ids of the tags on them, from the crops or the full frame
+        let img = DynamicImage::ImageLuma8(render(TAG16H5[3], 10.0));
+        let pylon = Detection {
+            x1: 20,
+            y1: 20,
+            x2: 180,
+            y2: 280,
+            w: 160,
+            h: 260,
+            ..Default::default()
+        };
+        let other = Detection {
+            x1: 150,
+            x2: 190,
+            ..pylon.clone()
+        };
+        let person = Detection {
+            cls: RoktrackClasses::PERSON.to_u32(),
+            ..pylon.clone()
+        };
+        for frame in [false, true] {
+            conf.frame = frame;
+            let dets = TagReader::new(conf.clone())
+                .read(&img, vec![pylon.clone(), other.clone(), person.clone()]);
+            assert_eq!(dets[0].ids, vec![3]);
+            assert!(dets[1].ids.is_empty());
+            assert!(dets[2].ids.is_empty());
+        }
+    }
+}
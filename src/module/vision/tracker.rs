@@ -0,0 +1,273 @@
+//! Multi-object Tracking
+//!
+//! Associates the detections of consecutive frames, so a pylon keeps the same id
+//! while it is in sight. The bbox of the previous frame is moved by the velocity of
+//! the track, and matched to the detections of the same class by IoU.
+//! The ids read by the OCR are voted on per track, so a misreading of a frame doesn't count.
+
+use super::detector::{onnx::iou, Detection};
+use crate::module::util::conf;
+
+/// A pylon (or any object) followed across frames.
+///
+#[derive(Debug, Clone)]
+struct Track {
+    id: u32,        // Track id
+    det: Detection, // Detection of the last matched frame
+    h: f32,         // Smoothed bbox height
+    vx: f32,        // Velocity of the center per frame (pixels)
+    vy: f32,        // Velocity of the center per frame (pixels)
+    misses: u8,     // Frames since the last match
+
+    votes: Vec<(Vec<u8>, u8, f32)>, // Ids read, the frames they were read in and their summed confidence
+}
+
+impl Track {
+    /// Where the track is expected in the current frame.
+    fn predict(&self) -> Detection {
+        let frames = (self.misses + 1) as f32;
+        let (dx, dy) = (self.vx * frames, self.vy * frames);
+        let shift = |v: u32, d: f32| (v as f32 + d).max(0.0) as u32;
+        Detection {
+            x1: shift(self.det.x1, dx),
+            x2: shift(self.det.x2, dx),
+            y1: shift(self.det.y1, dy),
+            y2: shift(self.det.y2, dy),
+            xc: self.det.xc + dx,
+            yc: self.det.yc + dy,
+            ..self.det.clone()
+        }
+    }
+
+    /// Track of a detection without one.
+    fn new(id: u32, mut det: Detection, votes: u8) -> (Self, Detection) {
+        det.track_id = Some(id);
+        let mut track = Self {
+            id,
+            det: det.clone(),
+            h: det.h as f32,
+            vx: 0.0,
+            vy: 0.0,
+            misses: 0,
+            votes: vec![],
+        };
+        track.vote(&mut det, votes);
+        track.det = det.clone();
+        (track, det)
+    }
+
+    /// Count the ids read in the frame, and replace them with the ids accepted by the votes.
+    ///
+    /// The ids read most often are accepted once they have been read in enough frames.
+    fn vote(&mut self, det: &mut Detection, votes: u8) {
+        if !det.ids.is_empty() {
+            let prob = det.id_prob.unwrap_or(1.0);
+            match self.votes.iter_mut().find(|v| v.0 == det.ids) {
+                Some(vote) => {
+                    vote.1 = vote.1.saturating_add(1);
+                    vote.2 += prob;
+                }
+                None => self.votes.push((det.ids.clone(), 1, prob)),
+            }
+        }
+        // The earliest of the ties, so the ids don't flip
+        let accepted = self.votes.iter().filter(|v| votes <= v.1).fold(
+            None,
+            |best: Option<&(Vec<u8>, u8, f32)>, vote| match best {
+                Some(best) if vote.1 <= best.1 => Some(best),
+                _ => Some(vote),
+            },
+        );
+        (det.ids, det.id_prob) = match accepted {
+            Some((ids, count, prob)) => (ids.clone(), Some(prob / *count as f32)),
+            None => (vec![], None),
+        };
+    }
+
+    /// Update the track with a matched detection and return it with the track applied.
+    fn assign(&mut self, mut det: Detection, smoothing: f32, votes: u8) -> Detection {
+        // Motion model of constant velocity, smoothed like the height
+        let frames = (self.misses + 1) as f32;
+        let vx = (det.xc - self.det.xc) / frames;
+        let vy = (det.yc - self.det.yc) / frames;
+        self.vx += (vx - self.vx) * smoothing;
+        self.vy += (vy - self.vy) * smoothing;
+        // Smooth the height, keeping the bottom where the pylon stands
+        self.h += (det.h as f32 - self.h) * smoothing;
+        det.h = self.h.round() as u32;
+        det.y1 = det.y2.saturating_sub(det.h);
+        // The OCR can't read every frame, and misreads some
+        self.vote(&mut det, votes);
+        det.track_id = Some(self.id);
+        self.det = det.clone();
+        self.misses = 0;
+        det
+    }
+}
+
+/// Keeps the tracks and gives the detections their track ids.
+///
+pub struct Tracker {
+    tracks: Vec<Track>,
+    next_id: u32, // Never reused, so a stale lock on a track doesn't match a new one
+    conf: conf::Tracker,
+}
+
+impl Tracker {
+    /// Tracker's constructor.
+    ///
+    pub fn new(conf: conf::Tracker) -> Self {
+        Self {
+            tracks: vec![],
+            next_id: 1,
+            conf,
+        }
+    }
+
+    /// Feed the detections of a new frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `dets` - Detections of the frame.
+    ///
+    /// # Returns
+    ///
+    /// The detections in the same order, with the track id, the smoothed height
+    /// and the ids accepted by the votes of the earlier frames.
+    pub fn update(&mut self, mut dets: Vec<Detection>) -> Vec<Detection> {
+        // Pairs of the same class overlapping enough, best first
+        let predictions: Vec<Detection> = self.tracks.iter().map(|t| t.predict()).collect();
+        let mut pairs = vec![];
+        for (i, pred) in predictions.iter().enumerate() {
+            for (j, det) in dets.iter().enumerate() {
+                let overlap = iou(pred, det) as f32;
+                if pred.cls == det.cls && self.conf.iou <= overlap {
+                    pairs.push((overlap, i, j));
+                }
+            }
+        }
+        pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        // Greedy association
+        let mut track_matched = vec![false; self.tracks.len()];
+        let mut det_matched = vec![false; dets.len()];
+        for (_, i, j) in pairs {
+            if track_matched[i] || det_matched[j] {
+                continue;
+            }
+            track_matched[i] = true;
+            det_matched[j] = true;
+            dets[j] = self.tracks[i].assign(dets[j].clone(), self.conf.smoothing, self.conf.votes);
+        }
+
+        // Tracks without a detection are kept for a while
+        let max_misses = self.conf.max_misses;
+        let mut matched = track_matched.into_iter();
+        self.tracks.retain_mut(|t| {
+            if !matched.next().unwrap() {
+                t.misses += 1;
+            }
+            t.misses <= max_misses
+        });
+
+        // Detections without a track start a new one
+        for (det, _) in dets.iter_mut().zip(det_matched).filter(|(_, m)| !m) {
+            let (track, tracked) = Track::new(self.next_id, det.clone(), self.conf.votes);
+            *det = tracked;
+            self.tracks.push(track);
+            self.next_id += 1;
+        }
+        dets
+    }
+
+    /// Forget the tracks, e.g. when the scene or the model has changed.
+    ///
+    pub fn reset(&mut self) {
+        self.tracks.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pylon(x1: u32, h: u32) -> Detection {
+        Detection {
+            x1,
+            x2: x1 + 40,
+            y1: 400 - h,
+            y2: 400,
+            xc: x1 as f32 + 20.0,
+            yc: 400.0 - h as f32 / 2.0,
+            w: 40,
+            h,
+            prob: 0.9,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn tracker_test() {
+        let mut tracker = Tracker::new(conf::Tracker {
+            votes: 1,
+            ..Default::default()
+        });
+        // Two pylons get their own tracks
+        let dets = tracker.update(vec![pylon(100, 100), pylon(600, 60)]);
+        assert_eq!(dets[0].track_id, Some(1));
+        assert_eq!(dets[1].track_id, Some(2));
+        // Moving to the right, in the other order, with an id read
+        let mut near = pylon(110, 120);
+        near.ids = vec![3];
+        let dets = tracker.update(vec![pylon(610, 60), near]);
+        assert_eq!(dets[0].track_id, Some(2));
+        assert_eq!(dets[1].track_id, Some(1));
+        // The height is smoothed
+        assert_eq!(dets[1].h, 110);
+        assert_eq!(dets[1].y1, 290);
+        // The far pylon is missed, the id of the near one is carried forward
+        let dets = tracker.update(vec![pylon(120, 120)]);
+        assert_eq!(dets[0].track_id, Some(1));
+        assert_eq!(dets[0].ids, vec![3]);
+        // The far pylon is back where the motion model expects it
+        let dets = tracker.update(vec![pylon(125, 120), pylon(630, 60)]);
+        assert_eq!(dets[1].track_id, Some(2));
+        // A lost track is dropped, and its id is not reused
+        for _ in 0..4 {
+            tracker.update(vec![]);
+        }
+        let dets = tracker.update(vec![pylon(125, 120)]);
+        assert_eq!(dets[0].track_id, Some(3));
+    }
+
+    #[test]
+    fn tracker_vote_test() {
+        let mut tracker = Tracker::new(conf::Tracker::default());
+        let read = |ids: Vec<u8>, prob: f32| Detection {
+            ids,
+            id_prob: Some(prob),
+            ..pylon(100, 100)
+        };
+        // Ids are accepted after 3 frames read them, a misreading of a frame doesn't count
+        for (ids, accepted) in [
+            (vec![12], vec![]),
+            (vec![17], vec![]),
+            (vec![12], vec![]),
+            (vec![], vec![]),
+            (vec![12], vec![12]),
+            (vec![17], vec![12]),
+            (vec![], vec![12]),
+        ] {
+            let dets = tracker.update(vec![read(ids, 0.6)]);
+            assert_eq!(dets[0].ids, accepted);
+        }
+        // The confidence is averaged over the frames accepting the ids
+        let dets = tracker.update(vec![read(vec![12], 1.0)]);
+        assert!((dets[0].id_prob.unwrap() - 0.7).abs() < 1e-4);
+        // A different reading replaces the ids once it is read more often
+        for _ in 0..3 {
+            tracker.update(vec![read(vec![17], 0.9)]);
+        }
+        assert_eq!(tracker.update(vec![read(vec![17], 0.9)])[0].ids, vec![17]);
+    }
+}